@@ -0,0 +1,248 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use leptos::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::{JsFuture, spawn_local};
+use web_sys::{Blob, BlobPropertyBag, File, FileList, HtmlAnchorElement, Url, window};
+
+use ankurah_template_model::{AttachmentView, BlobChunk, BlobChunkView};
+
+use crate::{ctx, require::Require};
+
+/// Largest file accepted by the composer.
+pub const MAX_ATTACHMENT_BYTES: usize = 10 * 1024 * 1024;
+/// Raw bytes per BlobChunk (base64 encoding grows this by a third).
+const CHUNK_BYTES: usize = 48 * 1024;
+/// Blob type of downloads, whatever the file's own type.
+const DOWNLOAD_MIME_TYPE: &str = "application/octet-stream";
+/// Image types shown inline. Anything else, SVG included, is only offered as a download.
+const PREVIEW_MIME_TYPES: &[&str] = &["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+/// A file picked, dropped or pasted into the composer, not yet sent.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingAttachment {
+    pub name: String,
+    pub mime_type: String,
+    pub bytes: Arc<Vec<u8>>,
+}
+
+/// Attachment metadata plus the chunks that still need to be stored for it.
+pub struct PreparedAttachment {
+    pub name: String,
+    pub mime_type: String,
+    pub size: i64,
+    pub content_hash: String,
+    /// Empty when a blob with the same content hash is already stored.
+    pub chunks: Vec<BlobChunk>,
+}
+
+/// Read every file in a FileList into memory, skipping files over the size limit.
+pub async fn read_files(files: FileList) -> Vec<PendingAttachment> {
+    let mut pending = Vec::new();
+    for i in 0..files.length() {
+        let Some(file) = files.get(i) else { continue };
+        match read_file(file).await {
+            Ok(attachment) => pending.push(attachment),
+            Err(e) => tracing::error!("Failed to attach file: {}", e),
+        }
+    }
+    pending
+}
+
+async fn read_file(file: File) -> Result<PendingAttachment, String> {
+    let name = file.name();
+    if file.size() as usize > MAX_ATTACHMENT_BYTES {
+        return Err(format!("{} is larger than {} MB", name, MAX_ATTACHMENT_BYTES / (1024 * 1024)));
+    }
+
+    let buffer = JsFuture::from(file.array_buffer()).await.require("failed to read file")?;
+    let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+    let mime_type = if file.type_().is_empty() { "application/octet-stream".to_string() } else { file.type_() };
+
+    Ok(PendingAttachment { name, mime_type, bytes: Arc::new(bytes) })
+}
+
+async fn sha256_hex(bytes: &[u8]) -> Result<String, String> {
+    let crypto = window().require("no window")?.crypto().require("no crypto")?;
+    let data = js_sys::Uint8Array::from(bytes);
+    let promise = crypto.subtle().digest_with_str_and_buffer_source("SHA-256", &data).require("failed to start digest")?;
+    let digest = JsFuture::from(promise).await.require("failed to hash file")?;
+    Ok(js_sys::Uint8Array::new(&digest).to_vec().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Hash and chunk pending attachments, uploaded as `user_id`. Blobs already stored under the same hash are not
+/// uploaded again, but only if the stored chunks really add up to the file.
+pub async fn prepare_attachments(pending: Vec<PendingAttachment>, user_id: &str) -> Result<Vec<PreparedAttachment>, String> {
+    let mut prepared = Vec::new();
+    for attachment in pending {
+        let content_hash = sha256_hex(&attachment.bytes).await?;
+
+        let chunks = if load_blob(&content_hash).await?.is_some() {
+            Vec::new()
+        } else {
+            attachment
+                .bytes
+                .chunks(CHUNK_BYTES)
+                .enumerate()
+                .map(|(seq, chunk)| BlobChunk {
+                    hash: content_hash.clone(),
+                    seq: seq as i64,
+                    user: user_id.to_string(),
+                    data: BASE64.encode(chunk),
+                })
+                .collect()
+        };
+
+        prepared.push(PreparedAttachment {
+            name: attachment.name,
+            mime_type: attachment.mime_type,
+            size: attachment.bytes.len() as i64,
+            content_hash,
+            chunks,
+        });
+    }
+    Ok(prepared)
+}
+
+/// Fetch a blob's chunks and reassemble it, or `None` if no complete copy is stored.
+/// Anyone can upload chunks under any hash, so each uploader's chunks are reassembled separately and
+/// only a copy whose SHA-256 matches `content_hash` is accepted.
+async fn load_blob(content_hash: &str) -> Result<Option<Vec<u8>>, String> {
+    let chunks = ctx().fetch::<BlobChunkView>(format!("hash = '{}'", content_hash).as_str()).await.map_err(|e| e.to_string())?;
+
+    let mut uploads: HashMap<String, BTreeMap<i64, String>> = HashMap::new();
+    for chunk in chunks {
+        // A file uploaded twice by the same user (say from two tabs) stores identical chunks twice
+        uploads
+            .entry(chunk.user().unwrap_or_default())
+            .or_default()
+            .entry(chunk.seq().unwrap_or(-1))
+            .or_insert(chunk.data().unwrap_or_default());
+    }
+
+    'uploads: for chunks in uploads.into_values() {
+        if chunks.keys().copied().ne(0..chunks.len() as i64) {
+            continue;
+        }
+        let mut bytes = Vec::new();
+        for data in chunks.values() {
+            match BASE64.decode(data) {
+                Ok(chunk) => bytes.extend(chunk),
+                Err(_) => continue 'uploads,
+            }
+        }
+        if sha256_hex(&bytes).await? == content_hash {
+            return Ok(Some(bytes));
+        }
+    }
+    Ok(None)
+}
+
+/// Fetch a blob's chunks on demand and expose them as an object URL of type `blob_type`. The caller revokes it.
+/// Chunks are only synced to this client when something actually needs to display or download them.
+async fn load_blob_url(content_hash: &str, blob_type: &str) -> Result<String, String> {
+    let bytes = load_blob(content_hash).await?.ok_or_else(|| "attachment is missing or does not match its content hash".to_string())?;
+
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes.as_slice()));
+    let options = BlobPropertyBag::new();
+    options.set_type(blob_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &options).require("failed to create blob")?;
+    Url::create_object_url_with_blob(&blob).require("failed to create object URL")
+}
+
+fn format_size(size: i64) -> String {
+    match size {
+        s if s < 1024 => format!("{} B", s),
+        s if s < 1024 * 1024 => format!("{:.1} KB", s as f64 / 1024.0),
+        s => format!("{:.1} MB", s as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+/// Save a blob under `name`. It is served as `application/octet-stream` so the browser never renders it.
+fn download_blob(content_hash: String, name: String) {
+    spawn_local(async move {
+        match (|| async {
+            let url = load_blob_url(&content_hash, DOWNLOAD_MIME_TYPE).await?;
+            let document = window().require("no window")?.document().require("no document")?;
+            let anchor = document.create_element("a").require("failed to create anchor")?.unchecked_into::<HtmlAnchorElement>();
+            anchor.set_href(&url);
+            anchor.set_download(&name);
+            anchor.click();
+            let _ = Url::revoke_object_url(&url);
+            Ok::<_, String>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::info!("Attachment downloaded"),
+            Err(e) => tracing::error!("Failed to download attachment: {}", e),
+        }
+    });
+}
+
+/// Attachments of a message: inline previews for images, download links for other files.
+#[component]
+pub fn AttachmentList(#[prop(into)] attachments: Signal<Vec<AttachmentView>>) -> impl IntoView {
+    view! {
+        <Show when=move || !attachments.get().is_empty()>
+            <div class="attachmentList">
+                <For
+                    each=move || attachments.get()
+                    key=|attachment: &AttachmentView| attachment.id()
+                    children=|attachment: AttachmentView| view! { <AttachmentItem attachment /> }
+                />
+            </div>
+        </Show>
+    }
+}
+
+#[component]
+fn AttachmentItem(attachment: AttachmentView) -> impl IntoView {
+    let name = attachment.name().unwrap_or_default();
+    let mime_type = attachment.mime_type().unwrap_or_default();
+    let content_hash = attachment.content_hash().unwrap_or_default();
+    let size = format_size(attachment.size().unwrap_or(0));
+
+    if PREVIEW_MIME_TYPES.contains(&mime_type.as_str()) {
+        let title = format!("{} ({})", name, size);
+        let src = RwSignal::new(None::<String>);
+        spawn_local(async move {
+            match load_blob_url(&content_hash, &mime_type).await {
+                Ok(url) => {
+                    // The row may have gone away while the image loaded
+                    if let Some(Some(url)) = src.try_set(Some(url)) {
+                        let _ = Url::revoke_object_url(&url);
+                    }
+                }
+                Err(e) => tracing::error!("Failed to load image attachment: {}", e),
+            }
+        });
+        on_cleanup(move || {
+            if let Some(url) = src.try_get_untracked().flatten() {
+                let _ = Url::revoke_object_url(&url);
+            }
+        });
+
+        view! {
+            <a class="attachmentImage" href=move || src.get() target="_blank" title=title>
+                <Show when=move || src.get().is_some() fallback=|| view! { <span class="attachmentLoading">"Loading image…"</span> }>
+                    <img src=move || src.get() alt=name.clone() />
+                </Show>
+            </a>
+        }
+        .into_any()
+    } else {
+        let label = name.clone();
+        view! {
+            <button class="attachmentFile" on:click=move |_| download_blob(content_hash.clone(), name.clone()) title="Download">
+                <span class="attachmentIcon">"📄"</span>
+                <span class="attachmentName">{label}</span>
+                <span class="attachmentSize">{size}</span>
+            </button>
+        }
+        .into_any()
+    }
+}
@@ -1,20 +1,56 @@
 use leptos::html::Div;
 use leptos::prelude::*;
+use wasm_bindgen_futures::spawn_local;
 
+use ankurah::{EntityId, LiveQuery};
 use ankurah_signals::Get as AnkurahGet;
 use ankurah_template_model::{MessageView, RoomView, UserView};
 
 use crate::{
-    chat_debug_header::ChatDebugHeader, chat_scroll_manager::ChatScrollManager, ctx, message_input::MessageInput,
-    message_list::MessageList, notification_manager::NotificationManager,
+    chat_debug_header::ChatDebugHeader, chat_scroll_manager::ChatScrollManager, ctx, message_input::MessageInput, message_list::MessageList,
+    notification_manager::NotificationManager, pinned_strip::PinnedStrip, room_header::RoomHeader, typing_indicator::TypingIndicator,
 };
 
+/// A message to reveal once its room is shown, e.g. after clicking a search hit in another room.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageJump {
+    pub room_id: String,
+    pub message_id: String,
+}
+
+pub async fn fetch_message(message_id: &str) -> Result<MessageView, Box<dyn std::error::Error>> {
+    let entity_id = EntityId::from_base64(message_id).map_err(|e| format!("invalid message id {}: {:?}", message_id, e))?;
+    Ok(ctx().get::<MessageView>(entity_id).await?)
+}
+
+/// Scroll the timeline to a message, loading its history if needed.
+/// Thread replies are not in the main timeline, so jump to their root and open the thread.
+pub fn reveal_message(manager: ChatScrollManager, target: MessageView, open_thread: RwSignal<Option<MessageView>>) {
+    let thread_id = target.thread().unwrap_or_default();
+    if thread_id.is_empty() {
+        manager.jump_to_message(target.id().to_base64(), target.timestamp().unwrap_or(0));
+        return;
+    }
+    spawn_local(async move {
+        match fetch_message(&thread_id).await {
+            Ok(root) => {
+                manager.jump_to_message(root.id().to_base64(), root.timestamp().unwrap_or(0));
+                open_thread.set(Some(root));
+            }
+            Err(e) => tracing::error!("Failed to load thread root: {}", e),
+        }
+    });
+}
+
 /// Main chat component displaying messages, input, and scroll controls.
 /// Manages ChatScrollManager lifecycle and coordinates all chat sub-components.
 #[component]
 pub fn Chat(
     room: RwSignal<Option<RoomView>>,
     current_user: RwSignal<Option<UserView>>,
+    users: LiveQuery<UserView>,
+    open_thread: RwSignal<Option<MessageView>>,
+    message_jump: RwSignal<Option<MessageJump>>,
     notification_manager: NotificationManager,
 ) -> impl IntoView {
     let show_debug = RwSignal::new(false);
@@ -50,8 +86,24 @@ pub fn Chat(
         }
     });
 
-    // Query for all users
-    let users = ctx().query::<UserView>("true").expect("failed to create UserView LiveQuery");
+    // Reveal a requested message once the manager for its room exists
+    Effect::new({
+        let manager = manager.clone();
+        move |_| {
+            let Some(target) = message_jump.get() else { return };
+            let Some(m) = manager.get() else { return };
+            if m.room_id() != target.room_id {
+                return;
+            }
+            message_jump.set(None);
+            spawn_local(async move {
+                match fetch_message(&target.message_id).await {
+                    Ok(message) => reveal_message(m, message, open_thread),
+                    Err(e) => tracing::error!("Failed to load message: {}", e),
+                }
+            });
+        }
+    });
 
     let messages_container_ref = NodeRef::<Div>::new();
 
@@ -118,6 +170,9 @@ pub fn Chat(
                 move || room.get().and_then(|current_room| {
                     manager.get().map(|mgr| {
                         let current_room_for_input = current_room.clone();
+                        let room_id_for_typing = current_room.id().to_base64();
+                        let room_id_for_pins = current_room.id().to_base64();
+                        let room_for_header = current_room.clone();
                         let current_user_id = current_user.get().map(|u| u.id().to_base64());
                         let show_jump_to_current = !mgr.should_auto_scroll();
 
@@ -125,7 +180,9 @@ pub fn Chat(
                         let mgr1 = mgr.clone();
                         let mgr2 = mgr.clone();
                         let mgr3 = mgr.clone();
-                        let mgr4 = mgr;
+                        let mgr4 = mgr.clone();
+                        let mgr6 = mgr.clone();
+                        let mgr5 = mgr;
 
                         view! {
                             <div class="chatContainer">
@@ -147,6 +204,12 @@ pub fn Chat(
                                     {move || if show_debug.get() { "▼" } else { "▲" }}
                                 </button>
 
+                                // Room name, topic and description
+                                <RoomHeader room=room_for_header />
+
+                                // Pinned messages
+                                <PinnedStrip room_id=room_id_for_pins manager=mgr5 open_thread />
+
                                 // Messages container
                                 <div class="messagesContainer" node_ref=messages_container_ref>
                                     <MessageList
@@ -154,6 +217,8 @@ pub fn Chat(
                                         users=users.clone()
                                         current_user_id=current_user_id.clone()
                                         editing_message=editing_message
+                                        manager=mgr6
+                                        open_thread=Some(open_thread)
                                     />
                                 </div>
 
@@ -177,8 +242,16 @@ pub fn Chat(
                                     room=current_room_for_input
                                     current_user=current_user.get()
                                     editing_message=editing_message
+                                    users=users.clone()
                                     manager=mgr4
                                 />
+
+                                // Typing indicator
+                                <TypingIndicator
+                                    room_id=room_id_for_typing
+                                    current_user_id=current_user_id.clone()
+                                    users=users.clone()
+                                />
                             </div>
                         }
                     })
@@ -1,9 +1,10 @@
 use ankurah::LiveQuery;
 use ankurah_signals::{Get as AnkurahGet, Mut, Peek, Read, Subscribe, SubscriptionGuard};
-use ankurah_template_model::MessageView;
+use ankurah_template_model::{AttachmentView, MessageView, PinView, ReactionView};
 use send_wrapper::SendWrapper;
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{HtmlDivElement, HtmlElement, window};
@@ -11,6 +12,11 @@ use web_sys::{HtmlDivElement, HtmlElement, window};
 use crate::ctx;
 use crate::notification_manager::NotificationManager;
 
+/// Animation frames to wait for a jumped-to message to render before giving up.
+const MAX_REVEAL_FRAMES: u32 = 60;
+/// How long a jumped-to message stays highlighted.
+const HIGHLIGHT_MS: i32 = 2_000;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScrollMode {
     Live,
@@ -46,8 +52,9 @@ struct Inner {
     continuation_step_back: f64,
     query_size: f64,
 
-    // Room context
+    // Room context (thread_id is set when this manager drives a thread panel)
     room_id: String,
+    thread_id: Option<String>,
     notification_manager: NotificationManager,
 
     // Reactive state
@@ -55,6 +62,7 @@ struct Inner {
     loading: Mut<Option<LoadingDirection>>,
     metrics: Mut<ScrollMetrics>,
     messages: LiveQuery<MessageView>,
+    page: PageQueries,
 
     // Track query parameters (for boundary detection)
     current_limit: Mut<usize>,
@@ -77,7 +85,15 @@ struct Inner {
 }
 
 impl ChatScrollManager {
-    pub fn new(room_id: String, notification_manager: NotificationManager) -> Self {
+    /// Manager for the main room timeline (thread replies excluded).
+    pub fn new(room_id: String, notification_manager: NotificationManager) -> Self { Self::with_scope(room_id, None, notification_manager) }
+
+    /// Manager for the replies of a single thread within a room.
+    pub fn new_thread(room_id: String, thread_id: String, notification_manager: NotificationManager) -> Self {
+        Self::with_scope(room_id, Some(thread_id), notification_manager)
+    }
+
+    fn with_scope(room_id: String, thread_id: Option<String>, notification_manager: NotificationManager) -> Self {
         let mode = Mut::new(ScrollMode::Live);
         let loading = Mut::new(None);
         let metrics = Mut::new(ScrollMetrics { top_gap: 0.0, bottom_gap: 0.0, min_buffer: 0.0, step_back: 0.0, result_count: 0 });
@@ -87,18 +103,27 @@ impl ChatScrollManager {
 
         // Create initial live mode query
         let limit = 100; // Will be recomputed after container is bound
-        let predicate = format!("room = '{}' AND deleted = false ORDER BY timestamp DESC LIMIT {}", room_id, limit);
+        let predicate = format!("{} ORDER BY timestamp DESC LIMIT {}", scope_predicate(&room_id, thread_id.as_deref()), limit);
         let messages = ctx().query::<MessageView>(predicate.as_str()).expect("failed to create MessageView LiveQuery");
 
-        // Subscribe to message changes
+        // Keep the per-page queries covering the loaded messages (and the root, in a thread panel)
         // TODO: Call afterLayout on message updates (requires capturing self in closure)
-        let _guard = messages.subscribe(move |_| {
-            // Schedule afterLayout on next tick (after DOM updates)
-            // For now this is a no-op; afterLayout will be called manually after render
-        });
+        let page = PageQueries::new(&room_id, thread_id.is_none());
+        let _guard = {
+            let page = page.clone();
+            let messages_for_sub = messages.clone();
+            let root = thread_id.clone();
+            messages.subscribe(move |_| {
+                let mut ids: Vec<String> = messages_for_sub.get().iter().map(|message| message.id().to_base64()).collect();
+                ids.extend(root.clone());
+                page.cover(ids);
+            })
+        };
 
-        // Set as active room since rooms start in live mode
-        notification_manager.set_active_room(Some(room_id.clone()));
+        // Set as active room since rooms start in live mode (thread panels don't affect unread state)
+        if thread_id.is_none() {
+            notification_manager.set_active_room(Some(room_id.clone()));
+        }
 
         let inner = Inner {
             min_row_px: 74.0,
@@ -107,12 +132,14 @@ impl ChatScrollManager {
             query_size: 3.0,
 
             room_id,
+            thread_id,
             notification_manager,
 
             mode,
             loading,
             metrics,
             messages,
+            page,
 
             current_limit,
             current_direction,
@@ -145,10 +172,50 @@ impl ChatScrollManager {
         self.0.metrics.read()
     }
 
+    pub fn room_id(&self) -> &str {
+        &self.0.room_id
+    }
+
     pub fn messages(&self) -> &LiveQuery<MessageView> {
         &self.0.messages
     }
 
+    /// Reactions on a loaded message, including removed ones so toggling can reuse the entity.
+    pub fn reactions(&self, message_id: &str) -> Vec<ReactionView> {
+        self.0.page.reactions.get().into_iter().filter(|reaction| reaction.message().unwrap_or_default() == message_id).collect()
+    }
+
+    /// Attachments of a loaded message added by `author`, the message's author; nobody else's are shown.
+    pub fn attachments(&self, message_id: &str, author: &str) -> Vec<AttachmentView> {
+        self.0
+            .page
+            .attachments
+            .get()
+            .into_iter()
+            .filter(|attachment| attachment.message().unwrap_or_default() == message_id && attachment.user().unwrap_or_default() == author)
+            .collect()
+    }
+
+    /// Pins of a message, including unpinned ones so pinning again can reuse the entity.
+    pub fn pins(&self, message_id: &str) -> Vec<PinView> {
+        self.0.page.pins.get().into_iter().filter(|pin| pin.message().unwrap_or_default() == message_id).collect()
+    }
+
+    /// How many replies a loaded thread root has. Always 0 in a thread panel.
+    pub fn reply_count(&self, message_id: &str) -> usize {
+        let Some(replies) = &self.0.page.replies else { return 0 };
+        replies.get().iter().filter(|reply| reply.thread().unwrap_or_default() == message_id).count()
+    }
+
+    /// Predicate selecting the messages this manager pages through, without ordering or limit.
+    fn base_predicate(&self) -> String { scope_predicate(&self.0.room_id, self.0.thread_id.as_deref()) }
+
+    fn set_active_room(&self, room_id: Option<String>) {
+        if self.0.thread_id.is_none() {
+            self.0.notification_manager.set_active_room(room_id);
+        }
+    }
+
     pub fn set_live_mode(&self) {
         tracing::info!("→ setLiveMode");
         self.0.mode.set(ScrollMode::Live);
@@ -159,11 +226,11 @@ impl ChatScrollManager {
         self.0.current_limit.set(limit);
         self.0.current_direction.set("DESC".to_string());
 
-        let predicate = format!("room = '{}' AND deleted = false ORDER BY timestamp DESC LIMIT {}", self.0.room_id, limit);
+        let predicate = format!("{} ORDER BY timestamp DESC LIMIT {}", self.base_predicate(), limit);
         let _ = self.0.messages.update_selection(predicate.as_str());
 
         // Set as active room when entering live mode
-        self.0.notification_manager.set_active_room(Some(self.0.room_id.clone()));
+        self.set_active_room(Some(self.0.room_id.clone()));
         // afterLayout() will handle scrolling on next render
     }
 
@@ -173,6 +240,38 @@ impl ChatScrollManager {
         self.scroll_to_bottom();
     }
 
+    /// Scroll to an arbitrary message and highlight it.
+    /// If it is not currently loaded, the history starting at the message is loaded first (switching to live mode
+    /// when that reaches the newest message), then the view waits for the row to render.
+    pub fn jump_to_message(&self, message_id: String, timestamp: i64) {
+        tracing::info!("jumpToMessage: {} timestamp={}", message_id, timestamp);
+        if self.find_message_element(&message_id).is_none() {
+            let limit = self.compute_limit();
+            self.0.mode.set(ScrollMode::Forward);
+            *self.0.last_continuation_key.borrow_mut() = None;
+            self.0.loading.set(None);
+            self.set_active_room(None);
+
+            self.0.current_limit.set(limit);
+            self.0.current_direction.set("ASC".to_string());
+            let predicate = format!("{} AND timestamp >= {} ORDER BY timestamp ASC LIMIT {}", self.base_predicate(), timestamp, limit);
+            let self_clone = self.clone();
+            spawn_local(async move {
+                // Whether the page reaches the newest message is only known once it has loaded
+                if let Err(e) = self_clone.0.messages.update_selection_wait(predicate.as_str()).await {
+                    tracing::error!("Failed to load messages around {}: {}", message_id, e);
+                    return;
+                }
+                if self_clone.0.mode.peek() == ScrollMode::Forward && self_clone.at_latest() {
+                    self_clone.set_live_mode();
+                }
+                self_clone.reveal_message(message_id, MAX_REVEAL_FRAMES);
+            });
+            return;
+        }
+        self.reveal_message(message_id, MAX_REVEAL_FRAMES);
+    }
+
     pub fn at_earliest(&self) -> bool {
         let result_count = self.0.messages.get().len();
         let current_limit = self.0.current_limit.peek();
@@ -427,7 +526,7 @@ impl ChatScrollManager {
 
         // Clear active room when leaving live mode
         if self.0.mode.peek() != ScrollMode::Live {
-            self.0.notification_manager.set_active_room(None);
+            self.set_active_room(None);
         }
 
         *self.0.last_continuation_key.borrow_mut() = Some(key);
@@ -442,16 +541,13 @@ impl ChatScrollManager {
         let op = if is_backward { "<=" } else { ">=" };
         let order = if is_backward { "DESC" } else { "ASC" };
 
-        let room_id = self.0.room_id.clone();
+        let base_predicate = self.base_predicate();
         let messages = self.0.messages.clone();
         let self_clone = self.clone();
         let el_clone = el.clone();
 
         spawn_local(async move {
-            let predicate = format!(
-                "room = '{}' AND deleted = false AND timestamp {} {} ORDER BY timestamp {} LIMIT {}",
-                room_id, op, timestamp, order, limit
-            );
+            let predicate = format!("{} AND timestamp {} {} ORDER BY timestamp {} LIMIT {}", base_predicate, op, timestamp, order, limit);
             let _ = messages.update_selection(predicate.as_str());
 
             self_clone.0.current_limit.set(limit);
@@ -551,6 +647,38 @@ impl ChatScrollManager {
         }
     }
 
+    fn find_message_element(&self, message_id: &str) -> Option<HtmlElement> {
+        let container = self.0.container.borrow();
+        container.as_ref()?.query_selector(&format!("[data-msg-id=\"{}\"]", message_id)).ok()??.dyn_into::<HtmlElement>().ok()
+    }
+
+    /// Center a message in the container once its row exists, retrying on later frames while it renders.
+    fn reveal_message(&self, message_id: String, frames_left: u32) {
+        let Some(el) = self.find_message_element(&message_id) else {
+            if frames_left == 0 {
+                tracing::warn!("jumpToMessage: message {} never rendered", message_id);
+                return;
+            }
+            let self_clone = self.clone();
+            let closure = Closure::once(move || self_clone.reveal_message(message_id, frames_left - 1));
+            let _ = window().expect("no window").request_animation_frame(closure.as_ref().unchecked_ref());
+            closure.forget();
+            return;
+        };
+
+        let client_height = self.0.container.borrow().as_ref().map(|c| c.client_height() as f64).unwrap_or(0.0);
+        self.scroll_to(el.offset_top() as f64 - (client_height - el.offset_height() as f64) / 2.0);
+
+        let _ = el.class_list().add_1("highlighted");
+        let closure = Closure::once(move || {
+            let _ = el.class_list().remove_1("highlighted");
+        });
+        let _ = window()
+            .expect("no window")
+            .set_timeout_with_callback_and_timeout_and_arguments_0(closure.as_ref().unchecked_ref(), HIGHLIGHT_MS);
+        closure.forget();
+    }
+
     fn scroll_to_bottom(&self) {
         let container = self.0.container.borrow();
         let Some(ref container) = *container else {
@@ -560,6 +688,62 @@ impl ChatScrollManager {
     }
 }
 
+/// What rows show alongside their messages, queried once for the whole loaded page rather than by
+/// each row, and re-selected whenever the page changes. Pins are few, so they cover the whole room.
+#[derive(Clone)]
+struct PageQueries {
+    room_id: String,
+    reactions: LiveQuery<ReactionView>,
+    attachments: LiveQuery<AttachmentView>,
+    pins: LiveQuery<PinView>,
+    /// Replies to the loaded messages, for their reply counts; only the main timeline has threads.
+    replies: Option<LiveQuery<MessageView>>,
+    /// IDs of the messages the queries cover, sorted.
+    ids: Arc<Mutex<Vec<String>>>,
+}
+
+impl PageQueries {
+    fn new(room_id: &str, with_replies: bool) -> Self {
+        let none = any_of("message", &[]);
+        Self {
+            room_id: room_id.to_string(),
+            reactions: ctx().query::<ReactionView>(none.as_str()).expect("failed to create ReactionView LiveQuery"),
+            attachments: ctx().query::<AttachmentView>(none.as_str()).expect("failed to create AttachmentView LiveQuery"),
+            pins: ctx().query::<PinView>(format!("room = '{}'", room_id).as_str()).expect("failed to create PinView LiveQuery"),
+            replies: with_replies.then(|| ctx().query::<MessageView>(none.as_str()).expect("failed to create thread replies LiveQuery")),
+            ids: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    fn cover(&self, mut ids: Vec<String>) {
+        ids.sort();
+        let mut current = self.ids.lock().unwrap();
+        if *current == ids {
+            return;
+        }
+        let _ = self.reactions.update_selection(any_of("message", &ids).as_str());
+        let _ = self.attachments.update_selection(any_of("message", &ids).as_str());
+        if let Some(replies) = &self.replies {
+            let predicate = format!("room = '{}' AND deleted = false AND {}", self.room_id, any_of("thread", &ids));
+            let _ = replies.update_selection(predicate.as_str());
+        }
+        *current = ids;
+    }
+}
+
+/// Predicate matching entities whose `field` is any of `values`; with no values it matches nothing.
+fn any_of(field: &str, values: &[String]) -> String {
+    if values.is_empty() {
+        return "false".to_string();
+    }
+    format!("({})", values.iter().map(|value| format!("{} = '{}'", field, value)).collect::<Vec<_>>().join(" OR "))
+}
+
+/// Main room timelines only show thread roots and standalone messages; thread panels only show replies.
+fn scope_predicate(room_id: &str, thread_id: Option<&str>) -> String {
+    format!("room = '{}' AND thread = '{}' AND deleted = false", room_id, thread_id.unwrap_or_default())
+}
+
 fn offset_to_parent(el: &HtmlElement) -> Option<(f64, f64)> {
     let a = el.get_bounding_client_rect();
     let parent = el.parent_element()?;
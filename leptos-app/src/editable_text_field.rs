@@ -2,21 +2,40 @@ use leptos::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::KeyboardEvent;
 
-use ankurah_signals::Get as AnkurahGet;
+/// A single splice turning one string into another: delete `delete_len` bytes at byte offset `index`, then insert `insert`.
+/// Applied to a YrsString field, a splice merges with concurrent edits instead of overwriting them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextSplice {
+    pub index: usize,
+    pub delete_len: usize,
+    pub insert: String,
+}
 
-use crate::ctx;
+impl TextSplice {
+    /// The minimal splice between two values, trimming their common prefix and suffix. None if they are equal.
+    pub fn diff(old: &str, new: &str) -> Option<Self> {
+        if old == new {
+            return None;
+        }
+        let prefix: usize = old.chars().zip(new.chars()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+        let suffix: usize =
+            old[prefix..].chars().rev().zip(new[prefix..].chars().rev()).take_while(|(a, b)| a == b).map(|(a, _)| a.len_utf8()).sum();
+        Some(Self { index: prefix, delete_len: old.len() - prefix - suffix, insert: new[prefix..new.len() - suffix].to_string() })
+    }
+}
 
-/// Editable text field that applies operational transforms for collaborative editing.
+/// Editable text field that emits each keystroke as a `TextSplice` for collaborative editing.
 /// Switches between display and edit modes on click/blur.
 ///
-/// TODO: This is currently a stub that doesn't apply operational transforms.
-/// The full implementation needs to call view.edit(trx) and apply YrsStringString operations.
+/// The caller applies splices to a YrsString field via `view.edit(trx)`, so concurrent edits from
+/// other clients merge. Remote changes arriving while editing are picked up into the input.
 #[component]
 pub fn EditableTextField(
     /// The current value to display
-    value: String,
-    /// Callback when value changes
-    on_change: impl Fn(String) + Clone + Send + Sync + 'static,
+    #[prop(into)]
+    value: Signal<String>,
+    /// Callback with the splice for each local change
+    on_edit: impl Fn(TextSplice) + Clone + Send + Sync + 'static,
     #[prop(optional)] placeholder: Option<String>,
     #[prop(optional)] class: Option<String>,
 ) -> impl IntoView {
@@ -36,39 +55,46 @@ pub fn EditableTextField(
             if is_editing.get() {
                 if let Some(input_el) = input_ref.get() {
                     let _ = input_el.focus();
-                    let pos = cursor_pos.get() as u32;
+                    let pos = cursor_pos.get_untracked() as u32;
                     let _ = input_el.set_selection_range(pos, pos);
                 }
             }
         }
     });
 
-    let start_edit = {
-        let value = value.clone();
-        move |_| {
-            local_value.set(value.clone());
-            last_value.set(value.clone());
-            cursor_pos.set(value.len());
-            is_editing.set(true);
+    // Merge remote changes into the input while editing, keeping the caret where it was
+    Effect::new(move |_| {
+        let remote = value.get();
+        if !is_editing.get_untracked() || remote == last_value.get_untracked() {
+            return;
         }
-    };
-
-    let apply_changes = {
-        let on_change = on_change.clone();
-        move |_old_value: String, new_value: String| {
-            on_change(new_value);
+        local_value.set(remote.clone());
+        last_value.set(remote);
+        if let Some(input_el) = input_ref.get_untracked() {
+            let pos = cursor_pos.get_untracked() as u32;
+            let _ = input_el.set_selection_range(pos, pos);
         }
+    });
+
+    let start_edit = move |_| {
+        let current = value.get_untracked();
+        cursor_pos.set(current.encode_utf16().count());
+        local_value.set(current.clone());
+        last_value.set(current);
+        is_editing.set(true);
     };
 
     let handle_change = {
-        let apply_changes = apply_changes.clone();
+        let on_edit = on_edit.clone();
         move |ev: web_sys::Event| {
             let target = ev.target().and_then(|t| t.dyn_into::<web_sys::HtmlInputElement>().ok());
             if let Some(input) = target {
                 let new_value = input.value();
                 let new_cursor_pos = input.selection_start().ok().flatten().unwrap_or(0) as usize;
 
-                apply_changes(last_value.get(), new_value.clone());
+                if let Some(splice) = TextSplice::diff(&last_value.get_untracked(), &new_value) {
+                    on_edit(splice);
+                }
 
                 local_value.set(new_value.clone());
                 last_value.set(new_value);
@@ -94,16 +120,16 @@ pub fn EditableTextField(
         <Show
             when=move || is_editing.get()
             fallback={
-                let value = value.clone();
                 let placeholder = placeholder.clone();
                 let class_name = class_name.clone();
-                let start_edit = start_edit.clone();
                 move || {
-                    let display = if value.is_empty() { placeholder.clone() } else { value.clone() };
+                    let current = value.get();
+                    let is_empty = current.is_empty();
+                    let display = if is_empty { placeholder.clone() } else { current };
                     view! {
                         <span
-                            class=format!("editableText {}", class_name)
-                            on:click=start_edit.clone()
+                            class=format!("editableText {}{}", class_name, if is_empty { " placeholder" } else { "" })
+                            on:click=start_edit
                             title=placeholder.clone()
                         >
                             {display}
@@ -114,8 +140,6 @@ pub fn EditableTextField(
         >
             {
                 let handle_change = handle_change.clone();
-                let handle_key_down = handle_key_down.clone();
-                let end_edit = end_edit.clone();
                 let class_name = class_name.clone();
                 move || view! {
                     <input
@@ -124,7 +148,7 @@ pub fn EditableTextField(
                         class=format!("editableInput {}", class_name)
                         prop:value=move || local_value.get()
                         on:input=handle_change.clone()
-                        on:keydown=handle_key_down.clone()
+                        on:keydown=handle_key_down
                         on:blur=move |_| end_edit()
                     />
                 }
@@ -4,7 +4,35 @@ use web_sys::window;
 use ankurah_signals::Get as AnkurahGet;
 use ankurah_template_model::UserView;
 
-use crate::{editable_text_field::EditableTextField, qr_code_modal::QRCodeModal, ws_client};
+use crate::{
+    ctx,
+    editable_text_field::{EditableTextField, TextSplice},
+    qr_code_modal::QRCodeModal,
+    ws_client,
+};
+
+/// Apply a local splice to the user's display name so it merges with edits from their other devices.
+fn apply_display_name_splice(user: UserView, splice: TextSplice) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            let mutable = user.edit(&trx)?;
+            if splice.delete_len > 0 {
+                mutable.display_name().delete(splice.index as u32, splice.delete_len as u32)?;
+            }
+            if !splice.insert.is_empty() {
+                mutable.display_name().insert(splice.index as u32, &splice.insert)?;
+            }
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::debug!("Display name updated"),
+            Err(e) => tracing::error!("Failed to update display name: {}", e),
+        }
+    });
+}
 
 /// Header component displaying app title, user info, connection status, and QR code button.
 #[component]
@@ -37,14 +65,12 @@ pub fn Header(current_user: RwSignal<Option<UserView>>) -> impl IntoView {
                         >
                             {move || {
                                 current_user.get().map(|user| {
-                                    let display_name = user.display_name().unwrap_or_default();
+                                    let user_for_name = user.clone();
+                                    let display_name = Signal::derive(move || user_for_name.display_name().unwrap_or_default());
                                     view! {
                                         <EditableTextField
-                                            value=display_name.clone()
-                                            on_change=move |new_name: String| {
-                                                // TODO: Update user display_name via transaction
-                                                tracing::info!("Would update display_name to: {}", new_name);
-                                            }
+                                            value=display_name
+                                            on_edit=move |splice: TextSplice| apply_display_name_splice(user.clone(), splice)
                                             class="userName".to_string()
                                         />
                                     }
@@ -1,45 +1,71 @@
 use leptos::prelude::*;
 
-use ankurah::{Context, EntityId, Node, model::Mutable, policy::DEFAULT_CONTEXT as C, policy::PermissiveAgent};
-use ankurah_signals::{CurrentObserver, ReactiveGraphObserver};
+use ankurah::{Context, EntityId, Node, model::Mutable};
+use ankurah_signals::{CurrentObserver, Get as AnkurahGet, ReactiveGraphObserver};
 use ankurah_storage_indexeddb_wasm::IndexedDBStorageEngine;
-use ankurah_template_model::{Message, RoomView, User, UserView};
+use ankurah_template_model::{
+    MessageView, RoomView, User, UserView,
+    identity::DeviceKey,
+    policy::{ANONYMOUS_CONTEXT, ChatAgent, Identity, Standing},
+    role,
+};
 use ankurah_websocket_client_wasm::WebsocketClient;
 use lazy_static::lazy_static;
 use send_wrapper::SendWrapper;
-use std::sync::{Arc, OnceLock};
+use std::sync::{Arc, OnceLock, RwLock};
 use wasm_bindgen_futures::spawn_local;
 use web_sys::window;
 
+mod attachments;
 mod chat;
 mod chat_debug_header;
 mod chat_scroll_manager;
 mod debug_overlay;
 mod editable_text_field;
 mod header;
+mod member_list;
+mod mentions;
 mod message_context_menu;
+mod message_history_modal;
 mod message_input;
 mod message_list;
 mod message_row;
+mod message_text;
 mod notification_manager;
+mod pinned_strip;
 mod qr_code_modal;
+mod reaction_bar;
 mod require;
+mod room_header;
 mod room_list;
+mod search_panel;
+mod thread_panel;
+mod typing_indicator;
 
-use chat::Chat;
+use chat::{Chat, MessageJump};
 use debug_overlay::DebugOverlay;
 use header::Header;
 use notification_manager::NotificationManager;
 use room_list::RoomList;
+use thread_panel::ThreadPanel;
 
 lazy_static! {
-    static ref NODE: OnceLock<Node<IndexedDBStorageEngine, PermissiveAgent>> = OnceLock::new();
+    static ref AGENT: ChatAgent = ChatAgent::new();
+    static ref NODE: OnceLock<Node<IndexedDBStorageEngine, ChatAgent>> = OnceLock::new();
     static ref CLIENT: OnceLock<SendWrapper<WebsocketClient>> = OnceLock::new();
+    static ref IDENTITY: RwLock<Identity> = RwLock::new(ANONYMOUS_CONTEXT);
 }
 
-/// Get the global Ankurah context.
+/// Get the global Ankurah context, acting as the current user once known.
 pub fn ctx() -> Context {
-    NODE.get().expect("Node not initialized").context(C).expect("failed to create context")
+    let identity = IDENTITY.read().unwrap().clone();
+    NODE.get().expect("Node not initialized").context(identity).expect("failed to create context")
+}
+
+/// Act as this user in every context created from now on, signing requests with the device key.
+fn set_current_user_identity(user_id: String, key: DeviceKey) {
+    AGENT.set_signer(user_id.clone(), key);
+    *IDENTITY.write().unwrap() = Identity::User { id: user_id, connection: None };
 }
 
 /// Get the global WebSocket client.
@@ -62,13 +88,16 @@ fn main() {
 async fn initialize() {
     // Open IndexedDB-backed storage and create a Node.
     let storage = IndexedDBStorageEngine::open("ankurah_template_app").await.expect("failed to open IndexedDB storage");
-    let node = Node::new(Arc::new(storage), PermissiveAgent::new());
+    let node = Node::new(Arc::new(storage), AGENT.clone());
 
-    // Build WebSocket URL based on current window location (same pattern as wasm-bindings).
+    // The server serves this page and accepts the WebSocket on the same origin, at `/ws`. It ties
+    // the node named in the URL to the address we connect from.
     let window = window().expect("no window available");
     let location = window.location();
-    let hostname = location.hostname().unwrap_or_else(|_| "127.0.0.1".into());
-    let ws_url = format!("ws://{}:9797", hostname);
+    let host = location.host().unwrap_or_else(|_| "127.0.0.1:9797".into());
+    // Pages served over HTTPS may only open secure sockets, so follow the page's scheme
+    let scheme = if location.protocol().as_deref() == Ok("https:") { "wss" } else { "ws" };
+    let ws_url = format!("{}://{}/ws?node={}", scheme, host, node.id.to_base64());
 
     let client = WebsocketClient::new(node.clone(), &ws_url).expect("failed to create WebsocketClient");
 
@@ -97,22 +126,55 @@ pub fn App() -> impl IntoView {
     // UI-local state for current user (Leptos signal).
     let current_user = RwSignal::new(None::<UserView>);
 
+    // UI-local state for the thread shown in the side panel.
+    let open_thread = RwSignal::new(None::<MessageView>);
+
+    // UI-local state for a message to reveal once its room is shown (search hits).
+    let message_jump = RwSignal::new(None::<MessageJump>);
+
+    // Shared users LiveQuery for author names in the chat and thread panel.
+    let users = ctx().query::<UserView>("true").expect("failed to create UserView LiveQuery");
+
+    // Keep the local policy agent's view of our own role and ban current, so moderator and admin actions
+    // pass its checks before they reach the server
+    Effect::new({
+        let users = users.clone();
+        move |_| {
+            let Some(user_id) = current_user.get().map(|user| user.id()) else { return };
+            if let Some(user) = users.get().into_iter().find(|user| user.id() == user_id) {
+                AGENT.set_standing(user_id.to_base64(), Standing::of(&user));
+            }
+        }
+    });
+
+    // Close the thread panel when switching rooms
+    Effect::new(move |_| {
+        selected_room.track();
+        open_thread.set(None);
+    });
+
+    // Create notification manager with rooms query; the current user is attached once known
+    let notification_manager = NotificationManager::new(rooms.clone());
+
     // Initialize user asynchronously
     Effect::new({
         let current_user = current_user.clone();
+        let notification_manager = notification_manager.clone();
         move |_| {
+            let notification_manager = notification_manager.clone();
             spawn_local(async move {
                 match ensure_user().await {
-                    Ok(user) => current_user.set(Some(user)),
+                    Ok((user, key)) => {
+                        set_current_user_identity(user.id().to_base64(), key);
+                        notification_manager.set_current_user(user.id().to_base64());
+                        current_user.set(Some(user));
+                    }
                     Err(e) => tracing::error!("Failed to initialize user: {}", e),
                 }
             });
         }
     });
 
-    // Create notification manager with rooms query and current user ID
-    let notification_manager = NotificationManager::new(rooms.clone(), current_user.get_untracked().map(|u| u.id().to_base64()));
-
     view! {
         <DebugOverlay />
 
@@ -120,26 +182,61 @@ pub fn App() -> impl IntoView {
             <Header current_user />
 
             <div class="mainContent">
-                <RoomList rooms selected_room notification_manager=notification_manager.clone() />
-                <Chat room=selected_room current_user=current_user notification_manager=notification_manager />
+                <RoomList
+                    rooms
+                    selected_room
+                    users=users.clone()
+                    current_user
+                    message_jump
+                    notification_manager=notification_manager.clone()
+                />
+                <Chat
+                    room=selected_room
+                    current_user=current_user
+                    users=users.clone()
+                    open_thread
+                    message_jump
+                    notification_manager=notification_manager.clone()
+                />
+                <ThreadPanel room=selected_room open_thread current_user users notification_manager />
             </div>
         </div>
     }
 }
 
 const STORAGE_KEY_USER_ID: &str = "ankurah_template_user_id";
+const STORAGE_KEY_DEVICE_KEY: &str = "ankurah_template_device_key";
+
+/// Load this device's signing key from localStorage, generating and storing one on first use.
+fn ensure_device_key() -> Result<DeviceKey, Box<dyn std::error::Error>> {
+    let storage = window().and_then(|w| w.local_storage().ok().flatten());
+    let stored = storage.as_ref().and_then(|s| s.get_item(STORAGE_KEY_DEVICE_KEY).ok().flatten());
+    if let Some(key) = stored.and_then(|k| DeviceKey::from_base64(&k)) {
+        return Ok(key);
+    }
+
+    let key = DeviceKey::generate().map_err(|e| format!("failed to generate device key: {}", e))?;
+    if let Some(storage) = storage {
+        let _ = storage.set_item(STORAGE_KEY_DEVICE_KEY, &key.to_base64());
+    }
+    Ok(key)
+}
 
 /// Ensures a user exists, creating one if necessary.
-/// Stores the user ID in localStorage for persistence across sessions.
-async fn ensure_user() -> Result<UserView, Box<dyn std::error::Error>> {
+/// The user is registered with this device's public key, and only reused if that key matches,
+/// so knowing a user's ID is not enough to act as them.
+async fn ensure_user() -> Result<(UserView, DeviceKey), Box<dyn std::error::Error>> {
     let context = ctx();
+    let key = ensure_device_key()?;
 
     // Check localStorage for existing user
     if let Some(storage) = window().and_then(|w| w.local_storage().ok().flatten()) {
         if let Ok(Some(stored_id)) = storage.get_item(STORAGE_KEY_USER_ID) {
             if let Ok(entity_id) = EntityId::from_base64(&stored_id) {
                 if let Ok(user) = context.get::<UserView>(entity_id).await {
-                    return Ok(user);
+                    if user.public_key().unwrap_or_default() == key.public_key() {
+                        return Ok((user, key));
+                    }
                 }
             }
         }
@@ -148,7 +245,18 @@ async fn ensure_user() -> Result<UserView, Box<dyn std::error::Error>> {
     // Create new user
     let transaction = context.begin();
     let random_suffix = (js_sys::Math::random() * 10000.0).floor() as u32;
-    let mutable = transaction.create(&User { display_name: format!("User-{}", random_suffix) }).await?;
+    let mutable = transaction
+        .create(&User {
+            display_name: format!("User-{}", random_suffix),
+            // The server marks the user online once this node authenticates as them
+            online: false,
+            last_seen: 0,
+            public_key: key.public_key(),
+            role: role::MEMBER.to_string(),
+            banned: false,
+            server_id: String::new(),
+        })
+        .await?;
     let user = mutable.read();
     transaction.commit().await?;
 
@@ -157,5 +265,5 @@ async fn ensure_user() -> Result<UserView, Box<dyn std::error::Error>> {
         let _ = storage.set_item(STORAGE_KEY_USER_ID, &user.id().to_base64());
     }
 
-    Ok(user)
+    Ok((user, key))
 }
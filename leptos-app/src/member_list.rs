@@ -0,0 +1,55 @@
+use leptos::prelude::*;
+
+use ankurah::LiveQuery;
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::UserView;
+
+/// Small dot indicating whether a user is currently online.
+#[component]
+pub fn PresenceDot(user: UserView) -> impl IntoView {
+    let is_online = move || user.online().unwrap_or(false);
+    let is_online_for_title = is_online.clone();
+
+    view! {
+        <span
+            class=move || if is_online() { "presenceDot online" } else { "presenceDot" }
+            title=move || if is_online_for_title() { "Online" } else { "Offline" }
+        ></span>
+    }
+}
+
+/// Sidebar list of all users, online users first.
+#[component]
+pub fn MemberList(users: LiveQuery<UserView>) -> impl IntoView {
+    let online_count = {
+        let users = users.clone();
+        move || users.get().iter().filter(|u| u.online().unwrap_or(false)).count()
+    };
+    let sorted_users = move || {
+        let mut list = users.get();
+        list.sort_by_key(|u| (!u.online().unwrap_or(false), u.display_name().unwrap_or_default().to_lowercase()));
+        list
+    };
+
+    view! {
+        <div class="memberList">
+            <div class="sidebarHeader">
+                <span>"Members"</span>
+                <span class="memberOnlineCount">{move || format!("{} online", online_count())}</span>
+            </div>
+            <For
+                each=sorted_users
+                key=|user: &UserView| (user.id(), user.online().unwrap_or(false), user.display_name().unwrap_or_default())
+                children=move |user: UserView| {
+                    let name = user.display_name().unwrap_or_default();
+                    view! {
+                        <div class="memberItem">
+                            <PresenceDot user=user />
+                            <span class="memberName">{name}</span>
+                        </div>
+                    }
+                }
+            />
+        </div>
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashSet;
+
+use leptos::prelude::*;
+
+use ankurah_template_model::{MessageView, UserView};
+
+/// Most suggestions shown in the autocomplete popup at once.
+const MAX_SUGGESTIONS: usize = 5;
+/// Longest text after `@` still treated as an in-progress mention.
+const MAX_QUERY_LEN: usize = 32;
+
+/// User IDs stored in a message's `mentions` field.
+pub fn mentioned_user_ids(message: &MessageView) -> Vec<String> {
+    message.mentions().unwrap_or_default().split(',').filter(|id| !id.is_empty()).map(str::to_string).collect()
+}
+
+pub fn mentions_user(message: &MessageView, user_id: &str) -> bool { mentioned_user_ids(message).iter().any(|id| id == user_id) }
+
+/// Whether `text` has an `@name` token starting at byte offset `at`.
+/// The `@` must start a word and the name must not run on into further word characters.
+fn mention_at(text: &str, at: usize, name: &str) -> bool {
+    let preceded_ok = text[..at].chars().next_back().is_none_or(char::is_whitespace);
+    let rest = &text[at..];
+    preceded_ok
+        && !name.is_empty()
+        && rest.starts_with('@')
+        && rest[1..].starts_with(name)
+        && rest[1 + name.len()..].chars().next().is_none_or(|c| !c.is_alphanumeric())
+}
+
+/// Resolve the `@Display Name` tokens in `text` to user IDs, encoded for `Message.mentions`.
+/// When several users share a display name, the one picked from autocomplete wins.
+pub fn encode_mentions(text: &str, users: &[UserView], picked: &[String]) -> String {
+    let is_picked = |u: &&UserView| picked.contains(&u.id().to_base64());
+    let candidates = users.iter().filter(is_picked).chain(users.iter().filter(|u| !is_picked(u)));
+
+    let mut seen_names = HashSet::new();
+    let mut ids = Vec::new();
+    for user in candidates {
+        let name = user.display_name().unwrap_or_default();
+        if seen_names.contains(&name) {
+            continue;
+        }
+        if text.match_indices('@').any(|(at, _)| mention_at(text, at, &name)) {
+            ids.push(user.id().to_base64());
+            seen_names.insert(name);
+        }
+    }
+    ids.join(",")
+}
+
+/// The mention being typed just before the caret: byte offset of its `@` and the text typed after it.
+pub fn active_mention_query(text: &str, caret: usize) -> Option<(usize, String)> {
+    let before = text.get(..caret)?;
+    let at = before.rfind('@')?;
+    if !before[..at].chars().next_back().is_none_or(char::is_whitespace) {
+        return None;
+    }
+    let query = &before[at + 1..];
+    if query.len() > MAX_QUERY_LEN || query.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some((at, query.to_string()))
+}
+
+/// Convert a DOM caret position (UTF-16 code units) into a byte offset into `text`.
+pub fn utf16_to_byte_offset(text: &str, units: usize) -> usize {
+    let mut seen = 0;
+    for (i, c) in text.char_indices() {
+        if seen >= units {
+            return i;
+        }
+        seen += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Users whose display name (or any word of it) starts with the query, excluding the current user.
+pub fn matching_users(users: &[UserView], query: &str, current_user_id: Option<&str>) -> Vec<UserView> {
+    let query = query.to_lowercase();
+    users
+        .iter()
+        .filter(|u| current_user_id != Some(u.id().to_base64().as_str()))
+        .filter(|u| {
+            let name = u.display_name().unwrap_or_default().to_lowercase();
+            !name.is_empty() && (name.starts_with(&query) || name.split_whitespace().any(|word| word.starts_with(&query)))
+        })
+        .take(MAX_SUGGESTIONS)
+        .cloned()
+        .collect()
+}
+
+enum Segment {
+    Text(String),
+    Mention { user_id: String, label: String },
+}
+
+/// (user ID, display name) pairs for the users a message mentions, longest names first so "@Ann Lee" wins over "@Ann".
+pub fn mention_names(users: &[UserView], mention_ids: &[String]) -> Vec<(String, String)> {
+    let mut names: Vec<(String, String)> = users
+        .iter()
+        .map(|u| (u.id().to_base64(), u.display_name().unwrap_or_default()))
+        .filter(|(id, _)| mention_ids.contains(id))
+        .collect();
+    names.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+    names
+}
+
+/// Split text into plain runs and `@name` tokens for the given (user ID, display name) pairs.
+fn split_mentions(text: &str, names: &[(String, String)]) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut plain_start = 0;
+    let mut pos = 0;
+    while let Some(offset) = text[pos..].find('@') {
+        let at = pos + offset;
+        match names.iter().find(|(_, name)| mention_at(text, at, name)) {
+            Some((user_id, name)) => {
+                if plain_start < at {
+                    segments.push(Segment::Text(text[plain_start..at].to_string()));
+                }
+                let end = at + 1 + name.len();
+                segments.push(Segment::Mention { user_id: user_id.clone(), label: text[at..end].to_string() });
+                plain_start = end;
+                pos = end;
+            }
+            None => pos = at + 1,
+        }
+    }
+    if plain_start < text.len() {
+        segments.push(Segment::Text(text[plain_start..].to_string()));
+    }
+    segments
+}
+
+/// Plain text with its mentions highlighted; mentions of the current user stand out further.
+pub fn render_mentions(text: &str, names: &[(String, String)], current_user_id: Option<&str>) -> Vec<AnyView> {
+    split_mentions(text, names)
+        .into_iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.into_any(),
+            Segment::Mention { user_id, label } => {
+                let class = if current_user_id == Some(user_id.as_str()) { "mention mentionSelf" } else { "mention" };
+                view! { <span class=class>{label}</span> }.into_any()
+            }
+        })
+        .collect()
+}
+
+/// Autocomplete popup listing the users matching the mention being typed.
+#[component]
+pub fn MentionSuggestions(
+    suggestions: Signal<Vec<UserView>>,
+    selected_index: RwSignal<usize>,
+    on_pick: impl Fn(UserView) + Clone + Send + Sync + 'static,
+) -> impl IntoView {
+    view! {
+        <Show when=move || !suggestions.get().is_empty()>
+            <ul class="mentionSuggestions">
+                {
+                    let on_pick = on_pick.clone();
+                    move || {
+                        suggestions
+                            .get()
+                            .into_iter()
+                            .enumerate()
+                            .map(|(index, user)| {
+                                let on_pick = on_pick.clone();
+                                let name = user.display_name().unwrap_or_default();
+                                view! {
+                                    <li
+                                        class=move || if selected_index.get() == index { "mentionSuggestion selected" } else { "mentionSuggestion" }
+                                        on:mousedown=move |e| {
+                                            // Keep focus in the input
+                                            e.prevent_default();
+                                            on_pick(user.clone());
+                                        }
+                                        on:mouseenter=move |_| selected_index.set(index)
+                                    >
+                                        "@"
+                                        {name}
+                                    </li>
+                                }
+                            })
+                            .collect::<Vec<_>>()
+                    }
+                }
+            </ul>
+        </Show>
+    }
+}
@@ -3,20 +3,99 @@ use leptos::prelude::*;
 use wasm_bindgen::JsCast;
 use web_sys::{KeyboardEvent, MouseEvent, window};
 
-use ankurah::model::Mutable;
-use ankurah_template_model::MessageView;
+use ankurah_template_model::{MessageView, Pin, PinView, UserView, role};
 
 use crate::ctx;
 
-/// Context menu for message actions (edit, delete).
-/// Appears on right-click of own messages.
+/// Toggle whether a message is pinned to its room.
+/// Reuses an existing Pin entity (flipping `deleted`) so each message has at most one pin.
+/// `pinned_by` records whoever pinned or unpinned it last, as the server requires.
+fn toggle_pin(pins: Vec<PinView>, message: &MessageView, user_id: String) {
+    let room_id = message.room().unwrap_or_default();
+    let message_id = message.id().to_base64();
+
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            let timestamp = js_sys::Date::now() as i64;
+            if let Some(pin) = pins.first() {
+                let was_deleted = pin.deleted().unwrap_or(false);
+                let mutable = pin.edit(&trx)?;
+                mutable.deleted().set(&!was_deleted)?;
+                mutable.pinned_by().set(&user_id)?;
+                if was_deleted {
+                    mutable.timestamp().set(&timestamp)?;
+                }
+            } else {
+                trx.create(&Pin { room: room_id, message: message_id, pinned_by: user_id, timestamp, deleted: false }).await?;
+            }
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::info!("Pin toggled"),
+            Err(e) => tracing::error!("Failed to toggle pin: {}", e),
+        }
+    });
+}
+
+/// Ban or unban a user. The server only accepts this from admins; banned users become read-only.
+fn set_banned(user: UserView, banned: bool) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            user.edit(&trx)?.banned().set(&banned)?;
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::info!("User {}", if banned { "banned" } else { "unbanned" }),
+            Err(e) => tracing::error!("Failed to update ban: {}", e),
+        }
+    });
+}
+
+/// Give a user another role. The server only accepts this from admins.
+fn set_role(user: UserView, new_role: &'static str) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            user.edit(&trx)?.role().set(&new_role.to_string())?;
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::info!("User is now {}", new_role),
+            Err(e) => tracing::error!("Failed to change role: {}", e),
+        }
+    });
+}
+
+/// Role changes offered to admins, as (role, label).
+const ROLE_ACTIONS: &[(&str, &str)] = &[(role::MEMBER, "Make member"), (role::MODERATOR, "Make moderator"), (role::ADMIN, "Make admin")];
+
+/// Context menu for message actions (pin, edit, view history, delete, ban, role changes).
+/// Pinning is offered on any message; editing and history only on own messages.
+/// Moderators can also delete other people's messages, and admins can ban their authors or change their role.
 #[component]
 pub fn MessageContextMenu(
     x: i32,
     y: i32,
     message: MessageView,
+    current_user_id: Option<String>,
+    is_own: bool,
+    can_moderate: bool,
+    is_admin: bool,
+    author: Option<UserView>,
+    /// The message's pins, including unpinned ones so toggling can reuse the existing entity.
+    #[prop(into)]
+    pins: Signal<Vec<PinView>>,
     editing_message: RwSignal<Option<MessageView>>,
-    on_close: impl Fn() + Clone + 'static,
+    show_history: RwSignal<bool>,
+    on_close: impl Fn() + Clone + Send + Sync + 'static,
 ) -> impl IntoView {
     let menu_ref = NodeRef::<leptos::html::Div>::new();
     let position = RwSignal::new((x, y));
@@ -105,6 +184,68 @@ pub fn MessageContextMenu(
         }
     };
 
+    let is_edited = message.edited_at().unwrap_or(0) > 0;
+
+    let is_pinned = move || pins.get().iter().any(|pin| !pin.deleted().unwrap_or(false));
+
+    let handle_toggle_pin = {
+        let on_close = on_close.clone();
+        let message = message.clone();
+        move |_: LeptosMouseEvent| {
+            if let Some(user_id) = current_user_id.clone() {
+                toggle_pin(pins.get_untracked(), &message, user_id);
+            }
+            on_close();
+        }
+    };
+
+    let handle_view_history = {
+        let on_close = on_close.clone();
+        move |_: LeptosMouseEvent| {
+            show_history.set(true);
+            on_close();
+        }
+    };
+
+    // Admins can ban the author of someone else's message
+    let ban_target = author.filter(|_| is_admin && !is_own);
+    let can_ban = ban_target.is_some();
+    let is_banned = ban_target.as_ref().is_some_and(|u| u.banned().unwrap_or(false));
+
+    // Admins can also change the role of someone else's message author
+    let role_actions = ban_target.clone().map(|user| {
+        let current_role = user.role().unwrap_or_default();
+        ROLE_ACTIONS
+            .iter()
+            .filter(|(new_role, _)| *new_role != current_role)
+            .map(|&(new_role, label)| {
+                let user = user.clone();
+                let on_close = on_close.clone();
+                view! {
+                    <button
+                        class="contextMenuItem"
+                        on:click=move |_| {
+                            set_role(user.clone(), new_role);
+                            on_close();
+                        }
+                    >
+                        {label}
+                    </button>
+                }
+            })
+            .collect_view()
+    });
+
+    let handle_toggle_ban = {
+        let on_close = on_close.clone();
+        move |_: LeptosMouseEvent| {
+            if let Some(user) = ban_target.clone() {
+                set_banned(user, !is_banned);
+            }
+            on_close();
+        }
+    };
+
     let handle_delete = move |_: LeptosMouseEvent| {
         let message = message.clone();
         let on_close = on_close.clone();
@@ -112,7 +253,7 @@ pub fn MessageContextMenu(
             match (|| async {
                 let trx = ctx().begin();
                 let mutable = message.edit(&trx)?;
-                mutable.deleted().set(&true);
+                mutable.deleted().set(&true)?;
                 trx.commit().await?;
                 Ok::<_, Box<dyn std::error::Error>>(())
             })()
@@ -133,12 +274,30 @@ pub fn MessageContextMenu(
             style:left=move || format!("{}px", position.get().0)
             style:top=move || format!("{}px", position.get().1)
         >
-            <button class="contextMenuItem" on:click=handle_edit>
-                "Edit"
-            </button>
-            <button class="contextMenuItem contextMenuItemDanger" on:click=handle_delete>
-                "Delete"
+            <button class="contextMenuItem" on:click=handle_toggle_pin>
+                {move || if is_pinned() { "Unpin" } else { "Pin" }}
             </button>
+            <Show when=move || is_own>
+                <button class="contextMenuItem" on:click=handle_edit.clone()>
+                    "Edit"
+                </button>
+            </Show>
+            <Show when=move || is_own && is_edited>
+                <button class="contextMenuItem" on:click=handle_view_history.clone()>
+                    "View history"
+                </button>
+            </Show>
+            <Show when=move || is_own || can_moderate>
+                <button class="contextMenuItem contextMenuItemDanger" on:click=handle_delete.clone()>
+                    "Delete"
+                </button>
+            </Show>
+            <Show when=move || can_ban>
+                <button class="contextMenuItem contextMenuItemDanger" on:click=handle_toggle_ban.clone()>
+                    {if is_banned { "Unban author" } else { "Ban author" }}
+                </button>
+            </Show>
+            {role_actions}
         </div>
     }
 }
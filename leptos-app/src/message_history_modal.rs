@@ -0,0 +1,65 @@
+use leptos::prelude::*;
+use wasm_bindgen::JsValue;
+
+use ankurah::LiveQuery;
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{MessageRevisionView, MessageView, UserView};
+
+use crate::ctx;
+
+/// Format a millisecond Unix timestamp in the browser's locale.
+fn format_timestamp(timestamp: i64) -> String {
+    js_sys::Date::new(&JsValue::from_f64(timestamp as f64)).to_locale_string("default", &JsValue::UNDEFINED).into()
+}
+
+/// Modal listing the prior versions of a message, newest first, with who replaced each one and when.
+/// Only revisions recorded by the message's author count; anyone else's would be forged.
+#[component]
+pub fn MessageHistoryModal(message: MessageView, users: LiveQuery<UserView>, on_close: impl Fn() + Clone + 'static) -> impl IntoView {
+    let predicate =
+        format!("message = '{}' AND editor = '{}' ORDER BY timestamp DESC", message.id().to_base64(), message.user().unwrap_or_default());
+    let revisions = ctx().query::<MessageRevisionView>(predicate.as_str()).expect("failed to create MessageRevisionView LiveQuery");
+
+    let user_name = move |user_id: String| {
+        users.get().iter().find(|u| u.id().to_base64() == user_id).map(|u| u.display_name().unwrap_or_default()).unwrap_or_else(|| "Unknown".to_string())
+    };
+
+    let on_close_overlay = on_close.clone();
+    let on_close_button = on_close.clone();
+    let current_text = move || message.text().unwrap_or_default();
+
+    view! {
+        <div class="historyModalOverlay" on:click=move |_| on_close_overlay()>
+            <div class="historyModalContent" on:click=|e| e.stop_propagation()>
+                <div class="historyModalHeader">
+                    <h2>"Edit history"</h2>
+                    <button class="historyCloseButton" on:click=move |_| on_close_button()>
+                        "×"
+                    </button>
+                </div>
+                <div class="historyEntry current">
+                    <div class="historyMeta">"Current"</div>
+                    <div class="historyText">{current_text}</div>
+                </div>
+                <For
+                    each=move || revisions.get()
+                    key=|revision: &MessageRevisionView| revision.id()
+                    children=move |revision: MessageRevisionView| {
+                        // Each revision holds the text that the edit at `timestamp` replaced
+                        let meta = format!(
+                            "Replaced by {} · {}",
+                            user_name(revision.editor().unwrap_or_default()),
+                            format_timestamp(revision.timestamp().unwrap_or(0))
+                        );
+                        view! {
+                            <div class="historyEntry">
+                                <div class="historyMeta">{meta}</div>
+                                <div class="historyText">{revision.text().unwrap_or_default()}</div>
+                            </div>
+                        }
+                    }
+                />
+            </div>
+        </div>
+    }
+}
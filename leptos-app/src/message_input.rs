@@ -1,20 +1,58 @@
 use leptos::prelude::*;
-use web_sys::KeyboardEvent;
+use web_sys::{ClipboardEvent, DragEvent, FileList, HtmlInputElement, HtmlTextAreaElement, KeyboardEvent};
 
-use ankurah_template_model::{Message, MessageView, RoomView, UserView};
+use ankurah::{LiveQuery, model::Mutable};
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{Attachment, Message, MessageView, RoomView, UserView, policy::RATE_LIMITED};
 
-use crate::{chat_scroll_manager::ChatScrollManager, ctx};
+use crate::{
+    attachments::{PendingAttachment, prepare_attachments, read_files},
+    chat_scroll_manager::ChatScrollManager,
+    ctx,
+    mentions::{MentionSuggestions, active_mention_query, encode_mentions, matching_users, mentioned_user_ids, utf16_to_byte_offset},
+    typing_indicator::TypingNotifier,
+};
+
+/// A user-facing explanation for a rejected send; the server's rate limit gets its own wording.
+fn describe_send_error(error: &str) -> String {
+    if error.contains(RATE_LIMITED) {
+        "You're sending messages too quickly. Wait a moment and try again.".to_string()
+    } else {
+        format!("Couldn't send message: {}", error)
+    }
+}
 
 /// Message input component for sending and editing messages.
-/// Handles Enter to send, Escape to cancel edit, Cmd/Ctrl+Up/Down to navigate own messages.
+/// Handles Enter to send, Shift+Enter for a new line, Escape to cancel edit, Cmd/Ctrl+Up/Down to navigate own messages.
+/// New messages can carry attachments added via the file picker, drag and drop, or paste.
+/// Typing `@` suggests users to mention; the mentioned user IDs are stored on the message.
 #[component]
 pub fn MessageInput(
     room: RoomView,
     current_user: Option<UserView>,
     editing_message: RwSignal<Option<MessageView>>,
+    users: LiveQuery<UserView>,
     #[prop(optional)] manager: Option<ChatScrollManager>,
+    /// Thread root message ID when composing replies in a thread panel.
+    #[prop(optional)]
+    thread: Option<String>,
 ) -> impl IntoView {
     let message_input = RwSignal::new(String::new());
+    // Why the last send or update was rejected, shown above the input until the next attempt
+    let send_error = RwSignal::new(None::<String>);
+    let pending_attachments = RwSignal::new(Vec::<PendingAttachment>::new());
+    let file_input_ref = NodeRef::<leptos::html::Input>::new();
+    let input_ref = NodeRef::<leptos::html::Textarea>::new();
+    // The mention being typed (byte offset of its `@`, text after it) and the users picked from autocomplete
+    let mention_query = RwSignal::new(None::<(usize, String)>);
+    let mention_index = RwSignal::new(0usize);
+    let picked_mentions = RwSignal::new(Vec::<String>::new());
+    let current_user_id = current_user.as_ref().map(|user| user.id().to_base64());
+    let placeholder = if thread.is_some() { "Reply in thread..." } else { "Type a message..." };
+    let typing_notifier = current_user.as_ref().map(|user| TypingNotifier::new(room.id().to_base64(), user.id().to_base64()));
+    // Banned users can still read; the server rejects anything they send
+    let banned_user = current_user.clone();
+    let is_banned = Signal::derive(move || banned_user.as_ref().is_some_and(|user| user.banned().unwrap_or(false)));
 
     // TODO: Get connection state from WebSocket client
     let connection_state = move || "Connected".to_string();
@@ -25,29 +63,84 @@ pub fn MessageInput(
         move |_| {
             if let Some(edit_msg) = editing_message.get() {
                 message_input.set(edit_msg.text().unwrap_or_default());
+                picked_mentions.set(mentioned_user_ids(&edit_msg));
             } else {
                 message_input.set(String::new());
+                picked_mentions.set(Vec::new());
             }
+            mention_query.set(None);
         }
     });
 
+    // Attachments only apply to new messages, not edits
+    let add_files = move |files: Option<FileList>| {
+        let Some(files) = files else { return };
+        if files.length() == 0 || editing_message.get_untracked().is_some() {
+            return;
+        }
+        wasm_bindgen_futures::spawn_local(async move {
+            let added = read_files(files).await;
+            pending_attachments.update(|list| list.extend(added));
+        });
+    };
+
+    let mention_suggestions = Signal::derive({
+        let users = users.clone();
+        let current_user_id = current_user_id.clone();
+        move || {
+            mention_query.get().map(|(_, query)| matching_users(&users.get(), &query, current_user_id.as_deref())).unwrap_or_default()
+        }
+    });
+
+    // Replace the `@query` being typed with the full mention and put the caret after it
+    let pick_mention = move |user: UserView| {
+        let Some((at, query)) = mention_query.get_untracked() else { return };
+        let text = message_input.get_untracked();
+        let end = (at + 1 + query.len()).min(text.len());
+        let mention = format!("@{} ", user.display_name().unwrap_or_default());
+        message_input.set(format!("{}{}{}", &text[..at], mention, &text[end..]));
+        picked_mentions.update(|ids| ids.push(user.id().to_base64()));
+        mention_query.set(None);
+
+        if let Some(input) = input_ref.get_untracked() {
+            let caret = text[..at].encode_utf16().count() + mention.encode_utf16().count();
+            let _ = input.focus();
+            let _ = input.set_selection_range(caret as u32, caret as u32);
+        }
+    };
+
+    let typing_notifier_for_send = typing_notifier.clone();
     let handle_send_message = move || {
         let input_text = message_input.get();
-        if input_text.trim().is_empty() || current_user.is_none() {
+        let attachments = pending_attachments.get();
+        let has_content = !input_text.trim().is_empty() || (!attachments.is_empty() && editing_message.get().is_none());
+        if !has_content || current_user.is_none() {
             tracing::info!("Cannot send: no input or no user");
             return;
         }
 
         let Some(user) = current_user.clone() else { return };
+        send_error.set(None);
+
+        let mentions = encode_mentions(input_text.trim(), &users.get(), &picked_mentions.get_untracked());
 
         if let Some(edit_msg) = editing_message.get() {
-            // Edit existing message
+            // Edit existing message; the server records the previous text as a revision
             let input_text = input_text.clone();
             wasm_bindgen_futures::spawn_local(async move {
                 match (|| async {
+                    let previous_text = edit_msg.text().unwrap_or_default();
+                    let new_text = input_text.trim();
+                    if previous_text == new_text && edit_msg.mentions().unwrap_or_default() == mentions {
+                        return Ok(());
+                    }
+
                     let trx = ctx().begin();
+                    let timestamp = js_sys::Date::now() as i64;
                     let mutable = edit_msg.edit(&trx)?;
-                    mutable.text().replace(&input_text.trim());
+                    mutable.text().replace(new_text)?;
+                    mutable.edited_at().set(&timestamp)?;
+                    mutable.mentions().set(&mentions)?;
                     trx.commit().await?;
                     Ok::<_, Box<dyn std::error::Error>>(())
                 })()
@@ -58,29 +151,63 @@ pub fn MessageInput(
                         editing_message.set(None);
                         message_input.set(String::new());
                     }
-                    Err(e) => tracing::error!("Failed to update message: {}", e),
+                    Err(e) => {
+                        tracing::error!("Failed to update message: {}", e);
+                        send_error.set(Some(describe_send_error(&e.to_string())));
+                    }
                 }
             });
         } else {
             // Create new message
+            if let Some(notifier) = &typing_notifier_for_send {
+                notifier.clear();
+            }
+
             let room_id = room.id().to_base64();
             let user_id = user.id().to_base64();
+            let thread_id = thread.clone().unwrap_or_default();
             let input_text = input_text.clone();
             let manager_clone = manager.clone();
 
             wasm_bindgen_futures::spawn_local(async move {
                 match (|| async {
+                    let prepared = prepare_attachments(attachments, &user_id).await?;
                     let transaction = ctx().begin();
                     let timestamp = js_sys::Date::now() as i64;
-                    let _msg = transaction
+                    let message = transaction
                         .create(&Message {
                             user: user_id.clone(),
                             room: room_id.clone(),
+                            thread: thread_id.clone(),
                             text: input_text.trim().to_string(),
                             timestamp,
                             deleted: false,
+                            edited_at: 0,
+                            mentions,
+                            deleted_at: 0,
+                            purged: false,
+                            // The server stamps this once it has stored the message
+                            received_at: 0,
+                            server_id: String::new(),
                         })
-                        .await?;
+                        .await?
+                        .read();
+                    let message_id = message.id().to_base64();
+                    for attachment in prepared {
+                        transaction
+                            .create(&Attachment {
+                                message: message_id.clone(),
+                                user: user_id.clone(),
+                                name: attachment.name,
+                                mime_type: attachment.mime_type,
+                                size: attachment.size,
+                                content_hash: attachment.content_hash,
+                            })
+                            .await?;
+                        for chunk in attachment.chunks {
+                            transaction.create(&chunk).await?;
+                        }
+                    }
                     transaction.commit().await?;
                     Ok::<_, Box<dyn std::error::Error>>(())
                 })()
@@ -89,12 +216,17 @@ pub fn MessageInput(
                     Ok(_) => {
                         tracing::info!("Message sent");
                         message_input.set(String::new());
+                        pending_attachments.set(Vec::new());
+                        picked_mentions.set(Vec::new());
                         // Jump to live mode after sending
                         if let Some(m) = manager_clone {
                             m.jump_to_live();
                         }
                     }
-                    Err(e) => tracing::error!("Failed to send message: {}", e),
+                    Err(e) => {
+                        tracing::error!("Failed to send message: {}", e);
+                        send_error.set(Some(describe_send_error(&e.to_string())));
+                    }
                 }
             });
         }
@@ -103,7 +235,34 @@ pub fn MessageInput(
     let handle_key_down = {
         let handle_send_message = handle_send_message.clone();
         move |e: KeyboardEvent| {
-            if e.key() == "Enter" && !e.shift_key() {
+            // While the mention popup is open it owns the navigation keys
+            let suggestions = mention_suggestions.get_untracked();
+            if !suggestions.is_empty() {
+                match e.key().as_str() {
+                    "ArrowDown" | "ArrowUp" if !(e.meta_key() || e.ctrl_key()) => {
+                        e.prevent_default();
+                        let len = suggestions.len();
+                        let step = if e.key() == "ArrowDown" { 1 } else { len - 1 };
+                        mention_index.update(|i| *i = (*i + step) % len);
+                        return;
+                    }
+                    "Enter" | "Tab" => {
+                        e.prevent_default();
+                        let index = mention_index.get_untracked().min(suggestions.len() - 1);
+                        pick_mention(suggestions[index].clone());
+                        return;
+                    }
+                    "Escape" => {
+                        e.prevent_default();
+                        mention_query.set(None);
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+
+            // Shift+Enter falls through to the textarea's newline; Enter while an IME is composing confirms the composition
+            if e.key() == "Enter" && !e.shift_key() && !e.is_composing() {
                 e.prevent_default();
                 handle_send_message();
             } else if e.key() == "Escape" && editing_message.get().is_some() {
@@ -123,35 +282,122 @@ pub fn MessageInput(
     };
 
     let is_connected = move || connection_state() == "Connected";
-    let can_send = move || !message_input.get().trim().is_empty() && is_connected();
+    let can_send =
+        move || (!message_input.get().trim().is_empty() || !pending_attachments.get().is_empty()) && is_connected() && !is_banned.get();
+
+    let handle_paste = move |ev: ClipboardEvent| {
+        let files = ev.clipboard_data().and_then(|data| data.files());
+        if files.as_ref().is_some_and(|f| f.length() > 0) {
+            ev.prevent_default();
+            add_files(files);
+        }
+    };
+
+    let handle_drop = move |ev: DragEvent| {
+        ev.prevent_default();
+        add_files(ev.data_transfer().and_then(|data| data.files()));
+    };
 
     view! {
-        <div class="inputContainer">
-            <input
-                type="text"
-                class="input"
-                placeholder="Type a message..."
-                prop:value=move || message_input.get()
-                on:input=move |ev| message_input.set(event_target_value(&ev))
-                on:keydown=handle_key_down
-                prop:disabled=move || !is_connected()
-            />
-            <button class="button" on:click=move |_| handle_send_message() prop:disabled=move || !can_send()>
-                {move || if editing_message.get().is_some() { "Update" } else { "Send" }}
-            </button>
-            <Show when=move || editing_message.get().is_some()>
+        <div class="composer" on:dragover=|ev: DragEvent| ev.prevent_default() on:drop=handle_drop>
+            <Show when=move || !pending_attachments.get().is_empty()>
+                <div class="pendingAttachments">
+                    <For
+                        each=move || { pending_attachments.get().into_iter().enumerate().collect::<Vec<_>>() }
+                        key=|(index, attachment): &(usize, PendingAttachment)| (*index, attachment.name.clone())
+                        children=move |(index, attachment): (usize, PendingAttachment)| {
+                            view! {
+                                <span class="pendingAttachment">
+                                    {attachment.name}
+                                    <button
+                                        class="pendingAttachmentRemove"
+                                        on:click=move |_| {
+                                            pending_attachments.update(|list| {
+                                                list.remove(index);
+                                            })
+                                        }
+                                        title="Remove attachment"
+                                    >
+                                        "×"
+                                    </button>
+                                </span>
+                            }
+                        }
+                    />
+                </div>
+            </Show>
+            <MentionSuggestions suggestions=mention_suggestions selected_index=mention_index on_pick=pick_mention />
+            {move || send_error.get().map(|error| view! { <div class="sendError">{error}</div> })}
+            <Show when=move || is_banned.get()>
+                <div class="bannedNotice">"You have been banned from posting. You can still read messages."</div>
+            </Show>
+            <div class="inputContainer">
+                <input
+                    type="file"
+                    multiple
+                    node_ref=file_input_ref
+                    style="display: none"
+                    on:change=move |ev| {
+                        let input: HtmlInputElement = event_target(&ev);
+                        add_files(input.files());
+                        input.set_value("");
+                    }
+                />
                 <button
-                    class="button"
+                    class="attachButton"
                     on:click=move |_| {
-                        editing_message.set(None);
-                        message_input.set(String::new());
+                        if let Some(input) = file_input_ref.get() {
+                            input.click();
+                        }
                     }
-
-                    style="margin-left: 8px"
+                    prop:disabled=move || editing_message.get().is_some()
+                    title="Attach files"
                 >
-                    "Cancel"
+                    "📎"
                 </button>
-            </Show>
+                <textarea
+                    class="input"
+                    rows="1"
+                    node_ref=input_ref
+                    placeholder=placeholder
+                    prop:value=move || message_input.get()
+                    on:input=move |ev| {
+                        let input: HtmlTextAreaElement = event_target(&ev);
+                        let value = input.value();
+                        let caret = utf16_to_byte_offset(&value, input.selection_start().ok().flatten().unwrap_or(0) as usize);
+                        mention_query.set(active_mention_query(&value, caret));
+                        mention_index.set(0);
+                        if let Some(notifier) = &typing_notifier {
+                            if value.trim().is_empty() {
+                                notifier.clear();
+                            } else if editing_message.get_untracked().is_none() {
+                                notifier.notify_typing();
+                            }
+                        }
+                        message_input.set(value);
+                    }
+                    on:keydown=handle_key_down
+                    on:blur=move |_| mention_query.set(None)
+                    on:paste=handle_paste
+                    prop:disabled=move || !is_connected() || is_banned.get()
+                ></textarea>
+                <button class="button" on:click=move |_| handle_send_message() prop:disabled=move || !can_send()>
+                    {move || if editing_message.get().is_some() { "Update" } else { "Send" }}
+                </button>
+                <Show when=move || editing_message.get().is_some()>
+                    <button
+                        class="button"
+                        on:click=move |_| {
+                            editing_message.set(None);
+                            message_input.set(String::new());
+                        }
+
+                        style="margin-left: 8px"
+                    >
+                        "Cancel"
+                    </button>
+                </Show>
+            </div>
         </div>
     }
 }
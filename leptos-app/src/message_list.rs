@@ -3,7 +3,7 @@ use leptos::prelude::*;
 use ankurah::LiveQuery;
 use ankurah_template_model::{MessageView, UserView};
 
-use crate::message_row::MessageRow;
+use crate::{chat_scroll_manager::ChatScrollManager, message_row::MessageRow};
 
 /// Message list component that displays messages.
 #[component]
@@ -12,6 +12,10 @@ pub fn MessageList(
     users: LiveQuery<UserView>,
     current_user_id: Option<String>,
     editing_message: RwSignal<Option<MessageView>>,
+    manager: ChatScrollManager,
+    /// Thread panel selection; when provided, rows show a reply affordance.
+    #[prop(optional_no_strip)]
+    open_thread: Option<RwSignal<Option<MessageView>>>,
 ) -> impl IntoView {
     view! {
         <Show
@@ -30,6 +34,7 @@ pub fn MessageList(
                 children={
                     let users = users.clone();
                     let current_user_id = current_user_id.clone();
+                    let manager = manager.clone();
                     move |message: MessageView| {
                         view! {
                             <MessageRow
@@ -37,6 +42,8 @@ pub fn MessageList(
                                 users=users.clone()
                                 current_user_id=current_user_id.clone()
                                 editing_message=editing_message
+                                manager=manager.clone()
+                                open_thread=open_thread
                             />
                         }
                     }
@@ -5,27 +5,52 @@ use ankurah::LiveQuery;
 use ankurah_signals::Get as AnkurahGet;
 use ankurah_template_model::{MessageView, UserView};
 
-use crate::message_context_menu::MessageContextMenu;
+use crate::{
+    attachments::AttachmentList,
+    chat_scroll_manager::ChatScrollManager,
+    member_list::PresenceDot,
+    mentions::mentions_user,
+    message_context_menu::MessageContextMenu,
+    message_history_modal::MessageHistoryModal,
+    message_text::MessageText,
+    reaction_bar::ReactionBar,
+    thread_panel::ThreadReplyLink,
+};
 
 /// Individual message row component.
-/// Displays message text, author name, and context menu on right-click for own messages.
+/// Displays Markdown-formatted message text with highlighted mentions, author name, reaction chips, and a context menu on right-click.
 #[component]
 pub fn MessageRow(
     message: MessageView,
     users: LiveQuery<UserView>,
     current_user_id: Option<String>,
     editing_message: RwSignal<Option<MessageView>>,
+    /// The manager that loaded this row; it also holds the row's attachments, reactions, pins and reply count.
+    manager: ChatScrollManager,
+    /// Thread panel selection; when provided, the row shows a reply count / reply button.
+    #[prop(optional_no_strip)]
+    open_thread: Option<RwSignal<Option<MessageView>>>,
 ) -> impl IntoView {
     let context_menu = RwSignal::new(None::<(i32, i32)>);
+    let show_history = RwSignal::new(false);
 
     // Clone values that will be used in multiple closures
     let message_for_author = message.clone();
-    let message_for_context = message.clone();
     let message_for_editing = message.clone();
     let message_for_own = message.clone();
+    let message_for_reactions = message.clone();
+    let message_for_thread = message.clone();
+    let message_for_edited = message.clone();
+    let message_for_history = message.clone();
+    let message_for_text = message.clone();
+    let users_for_text = users.clone();
+    let users_for_history = users.clone();
+    let users_for_menu = users.clone();
     let current_user_id_for_context = current_user_id.clone();
     let current_user_id_for_own = current_user_id.clone();
 
+    let is_edited = move || message_for_edited.edited_at().unwrap_or(0) > 0;
+
     // Find the author from the users list
     let author = move || {
         let user_list = users.get();
@@ -35,10 +60,8 @@ pub fn MessageRow(
 
     let handle_context_menu = move |e: MouseEvent| {
         e.prevent_default();
-        if let Some(ref current_id) = current_user_id_for_context {
-            if message_for_context.user().unwrap_or_default() == *current_id {
-                context_menu.set(Some((e.client_x(), e.client_y())));
-            }
+        if current_user_id_for_context.is_some() {
+            context_menu.set(Some((e.client_x(), e.client_y())));
         }
     };
 
@@ -47,8 +70,29 @@ pub fn MessageRow(
 
     let is_own_message = current_user_id_for_own.as_ref().map(|id| message_for_own.user().unwrap_or_default() == *id).unwrap_or(false);
 
+    let mentions_me = current_user_id.as_ref().is_some_and(|id| mentions_user(&message, id));
+
     let message_id = message.id().to_base64();
-    let message_text = message.text().unwrap_or_default();
+    let attachments = Signal::derive({
+        let manager = manager.clone();
+        let message_id = message_id.clone();
+        let author = message.user().unwrap_or_default();
+        move || manager.attachments(&message_id, &author)
+    });
+    let reactions = Signal::derive({
+        let manager = manager.clone();
+        let message_id = message_id.clone();
+        move || manager.reactions(&message_id)
+    });
+    let pins = Signal::derive({
+        let manager = manager.clone();
+        let message_id = message_id.clone();
+        move || manager.pins(&message_id)
+    });
+    let reply_count = Signal::derive({
+        let message_id = message_id.clone();
+        move || manager.reply_count(&message_id)
+    });
 
     view! {
         <div
@@ -60,6 +104,9 @@ pub fn MessageRow(
                 if is_own_message {
                     classes.push("ownMessage");
                 }
+                if mentions_me {
+                    classes.push("mentionsMe");
+                }
                 classes.join(" ")
             }
             data-msg-id=message_id.clone()
@@ -68,27 +115,52 @@ pub fn MessageRow(
             <Show when=move || !is_own_message fallback=|| ()>
                 {
                     let author = author.clone();
-                    move || view! {
-                        <div class="messageHeader">
-                            <span class="messageAuthor">
-                                {author().map(|u| u.display_name().unwrap_or_default()).unwrap_or_else(|| "Unknown".to_string())}
-                            </span>
-                        </div>
+                    move || {
+                        let author = author();
+                        view! {
+                            <div class="messageHeader">
+                                {author.clone().map(|u| view! { <PresenceDot user=u /> })}
+                                <span class="messageAuthor">
+                                    {author.map(|u| u.display_name().unwrap_or_default()).unwrap_or_else(|| "Unknown".to_string())}
+                                </span>
+                            </div>
+                        }
                     }
                 }
             </Show>
-            <div class="messageText">{message_text.clone()}</div>
+            <div class="messageText">
+                <MessageText message=message_for_text users=users_for_text current_user_id=current_user_id.clone() />
+                <Show when=is_edited>
+                    <span class="editedTag">" (edited)"</span>
+                </Show>
+            </div>
+            <AttachmentList attachments />
+            <ReactionBar message=message_for_reactions current_user_id=current_user_id.clone() reactions=reactions />
+            {open_thread.map(|open_thread| view! { <ThreadReplyLink message=message_for_thread open_thread reply_count /> })}
             <Show when=move || context_menu.get().is_some()>
                 {
                     let message = message.clone();
+                    let current_user_id = current_user_id.clone();
+                    let users_for_menu = users_for_menu.clone();
                     move || {
                         context_menu.get().map(|(x, y)| {
+                            let user_list = users_for_menu.get();
+                            let find_user = |id: &str| user_list.iter().find(|u| u.id().to_base64() == id).cloned();
+                            let viewer = current_user_id.as_deref().and_then(find_user);
+                            let author = find_user(&message.user().unwrap_or_default());
                             view! {
                                 <MessageContextMenu
                                     x=x
                                     y=y
                                     message=message.clone()
+                                    current_user_id=current_user_id.clone()
+                                    is_own=is_own_message
+                                    can_moderate=viewer.as_ref().is_some_and(|v| v.can_moderate())
+                                    is_admin=viewer.as_ref().is_some_and(|v| v.is_admin())
+                                    author=author
+                                    pins
                                     editing_message=editing_message
+                                    show_history=show_history
                                     on_close=move || context_menu.set(None)
                                 />
                             }
@@ -96,6 +168,21 @@ pub fn MessageRow(
                     }
                 }
             </Show>
+            <Show when=move || show_history.get()>
+                {
+                    let message = message_for_history.clone();
+                    let users = users_for_history.clone();
+                    move || {
+                        view! {
+                            <MessageHistoryModal
+                                message=message.clone()
+                                users=users.clone()
+                                on_close=move || show_history.set(false)
+                            />
+                        }
+                    }
+                }
+            </Show>
         </div>
     }
 }
@@ -0,0 +1,73 @@
+use leptos::prelude::*;
+
+use ankurah::LiveQuery;
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{
+    MessageView, UserView,
+    markdown::{self, Block, Inline},
+};
+
+use crate::mentions::{mention_names, mentioned_user_ids, render_mentions};
+
+/// Names and viewer needed to highlight mentions inside rendered text runs.
+struct MentionContext<'a> {
+    names: &'a [(String, String)],
+    current_user_id: Option<&'a str>,
+}
+
+/// Message text rendered from its Markdown subset, with mentions highlighted.
+/// Every string from the parser is emitted as a text node, so raw HTML in a message is shown, never interpreted.
+#[component]
+pub fn MessageText(message: MessageView, users: LiveQuery<UserView>, current_user_id: Option<String>) -> impl IntoView {
+    let blocks = markdown::parse(&message.text().unwrap_or_default());
+    let mention_ids = mentioned_user_ids(&message);
+
+    move || {
+        let names = mention_names(&users.get(), &mention_ids);
+        let mentions = MentionContext { names: &names, current_user_id: current_user_id.as_deref() };
+        blocks.iter().map(|block| render_block(block, &mentions)).collect::<Vec<_>>()
+    }
+}
+
+fn render_block(block: &Block, mentions: &MentionContext) -> AnyView {
+    match block {
+        Block::Paragraph(inlines) => view! { <p class="mdParagraph">{render_inlines(inlines, mentions)}</p> }.into_any(),
+        Block::CodeBlock { lang, code } => view! {
+            <pre class="mdCodeBlock" data-lang=lang.clone()>
+                <code>{code.clone()}</code>
+            </pre>
+        }
+        .into_any(),
+        Block::List { ordered, start, items } => {
+            let items = items.iter().map(|item| view! { <li>{render_inlines(item, mentions)}</li> }).collect::<Vec<_>>();
+            if *ordered {
+                view! { <ol class="mdList" start=*start>{items}</ol> }.into_any()
+            } else {
+                view! { <ul class="mdList">{items}</ul> }.into_any()
+            }
+        }
+        Block::Quote(blocks) => {
+            let blocks = blocks.iter().map(|block| render_block(block, mentions)).collect::<Vec<_>>();
+            view! { <blockquote class="mdQuote">{blocks}</blockquote> }.into_any()
+        }
+    }
+}
+
+fn render_inlines(inlines: &[Inline], mentions: &MentionContext) -> Vec<AnyView> {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text(text) => render_mentions(text, mentions.names, mentions.current_user_id).into_any(),
+            Inline::Bold(children) => view! { <strong>{render_inlines(children, mentions)}</strong> }.into_any(),
+            Inline::Italic(children) => view! { <em>{render_inlines(children, mentions)}</em> }.into_any(),
+            Inline::Code(code) => view! { <code class="mdInlineCode">{code.clone()}</code> }.into_any(),
+            Inline::Link { text, url } => view! {
+                <a class="mdLink" href=url.clone() target="_blank" rel="noopener noreferrer">
+                    {render_inlines(text, mentions)}
+                </a>
+            }
+            .into_any(),
+            Inline::LineBreak => view! { <br /> }.into_any(),
+        })
+        .collect()
+}
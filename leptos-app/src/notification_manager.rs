@@ -2,19 +2,26 @@ use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
 use ankurah::{LiveQuery, changes::ChangeSet};
-use ankurah_signals::{Mut, Peek, Subscribe, SubscriptionGuard};
-use ankurah_template_model::{MessageView, RoomView};
+use ankurah_signals::{Get, Mut, Peek, Read, Subscribe, SubscriptionGuard};
+use ankurah_template_model::{MessageView, ReadCursor, ReadCursorView, RoomView};
 use send_wrapper::SendWrapper;
 use wasm_bindgen::prelude::*;
 use wasm_bindgen_futures::spawn_local;
 use web_sys::{AudioBuffer, AudioContext};
 
-use crate::ctx;
+use crate::{ctx, mentions::mentions_user};
 
-/// Manages notification sounds and unread message counts per room.
+/// Most unread messages counted per room; badges show anything beyond as "99+".
+const UNREAD_LIMIT: usize = 100;
+
+/// Manages notification sounds, unread message counts and mention counts per room.
 ///
-/// Uses one query per room (since GROUP BY is not yet available in Ankurah).
-/// Tracks messages from other users and plays notification sounds.
+/// Unread counts are derived from the current user's synced `ReadCursor` entities, so they
+/// survive reloads and agree across tabs and devices. Uses one query per room selecting the
+/// newest [`UNREAD_LIMIT`] messages after that room's cursor (since GROUP BY / COUNT is not yet
+/// available in Ankurah). Rooms the user has no cursor for yet get one when first seen, so joining
+/// doesn't mark a room's whole history unread.
+/// Unread messages that @mention the current user are counted separately as high-priority notifications.
 #[derive(Clone)]
 pub struct NotificationManager(SendWrapper<Arc<Inner>>);
 
@@ -22,32 +29,46 @@ struct Inner {
     current_user_id: Mutex<Option<String>>,
     active_room_id: Mutex<Option<String>>,
     room_queries: Mutex<HashMap<String, RoomQueryState>>,
+    read_cursors: Mutex<Option<ReadCursorState>>,
+    /// Whether the user's cursors have loaded, so a room without one really has none.
+    cursors_loaded: Mutex<bool>,
     audio_context: SendWrapper<AudioContext>,
     audio_buffer: Mutex<Option<SendWrapper<AudioBuffer>>>,
     last_sound_played_at: Mutex<f64>,
     unread_counts: Mut<HashMap<String, usize>>,
+    mention_counts: Mut<HashMap<String, usize>>,
     _rooms_guard: Mutex<Option<SubscriptionGuard>>,
 }
 
 struct RoomQueryState {
-    _query: LiveQuery<MessageView>,
+    /// Messages in the room newer than `last_read_timestamp`
+    query: LiveQuery<MessageView>,
+    _guard: SubscriptionGuard,
+    last_read_timestamp: i64,
+}
+
+struct ReadCursorState {
+    query: LiveQuery<ReadCursorView>,
     _guard: SubscriptionGuard,
-    notification_count: usize,
 }
 
 impl NotificationManager {
-    pub fn new(rooms: LiveQuery<RoomView>, current_user: Option<String>) -> Self {
+    pub fn new(rooms: LiveQuery<RoomView>) -> Self {
         let audio_context = AudioContext::new().expect("Failed to create AudioContext");
         let unread_counts = Mut::new(HashMap::new());
+        let mention_counts = Mut::new(HashMap::new());
 
         let inner = Arc::new(Inner {
-            current_user_id: Mutex::new(current_user),
+            current_user_id: Mutex::new(None),
             active_room_id: Mutex::new(None),
             room_queries: Mutex::new(HashMap::new()),
+            read_cursors: Mutex::new(None),
+            cursors_loaded: Mutex::new(false),
             audio_context: SendWrapper::new(audio_context.clone()),
             audio_buffer: Mutex::new(None),
             last_sound_played_at: Mutex::new(0.0),
             unread_counts: unread_counts.clone(),
+            mention_counts: mention_counts.clone(),
             _rooms_guard: Mutex::new(None),
         });
 
@@ -77,6 +98,47 @@ impl NotificationManager {
         Self(SendWrapper::new(inner))
     }
 
+    /// Set the current user once it is known, and start following their read cursors.
+    pub fn set_current_user(&self, user_id: String) {
+        *self.0.current_user_id.lock().unwrap() = Some(user_id.clone());
+
+        let predicate = format!("user = '{}'", user_id);
+        let query = match ctx().query::<ReadCursorView>(predicate.as_str()) {
+            Ok(q) => q,
+            Err(e) => {
+                tracing::error!("Failed to create read cursor query: {:?}", e);
+                return;
+            }
+        };
+
+        *self.0.cursors_loaded.lock().unwrap() = false;
+        let inner_for_sub = self.0.clone();
+        let query_for_sub = query.clone();
+        let guard = query.subscribe(move |_: ChangeSet<ReadCursorView>| {
+            let cursors = query_for_sub.get();
+            Self::apply_read_cursors((*inner_for_sub).clone(), &cursors);
+
+            // On first load, start a cursor in every room the user has never read
+            let first_load = !std::mem::replace(&mut *inner_for_sub.cursors_loaded.lock().unwrap(), true);
+            if first_load {
+                let room_ids: Vec<String> = inner_for_sub.room_queries.lock().unwrap().keys().cloned().collect();
+                for room_id in room_ids {
+                    if !cursors.iter().any(|c| c.room().unwrap_or_default() == room_id) {
+                        Self::start_read_cursor((*inner_for_sub).clone(), room_id);
+                    }
+                }
+            }
+        });
+
+        *self.0.read_cursors.lock().unwrap() = Some(ReadCursorState { query, _guard: guard });
+
+        // Own messages were counted as unread until now
+        let room_ids: Vec<String> = self.0.room_queries.lock().unwrap().keys().cloned().collect();
+        for room_id in room_ids {
+            Self::recount_room((*self.0).clone(), &room_id);
+        }
+    }
+
     fn setup_audio_unlock(audio_context: AudioContext) {
         let unlock = Closure::wrap(Box::new(move || {
             if audio_context.state() == web_sys::AudioContextState::Suspended {
@@ -130,9 +192,9 @@ impl NotificationManager {
             return;
         }
 
-        // Create lightweight query for latest messages in this room
-        let predicate = format!("room = '{}' AND deleted = false ORDER BY timestamp DESC LIMIT 10", room_id);
-        let query = match ctx().query::<MessageView>(predicate.as_str()) {
+        // Query for messages newer than this room's read cursor
+        let last_read_timestamp = Self::cursor_timestamp(&inner, &room_id);
+        let query = match ctx().query::<MessageView>(unread_predicate(&room_id, last_read_timestamp).as_str()) {
             Ok(q) => q,
             Err(e) => {
                 tracing::error!("Failed to create message query for room {}: {:?}", room_id, e);
@@ -143,62 +205,167 @@ impl NotificationManager {
         let inner_for_sub = inner.clone();
         let room_id_for_sub = room_id.clone();
         let notification_count = Arc::new(Mutex::new(0usize));
-        let notification_count_for_sub = notification_count.clone();
 
         let guard = query.subscribe(move |changeset: ChangeSet<MessageView>| {
-            let mut count = notification_count_for_sub.lock().unwrap();
+            let mut count = notification_count.lock().unwrap();
             *count += 1;
+            let is_initial_load = *count == 1;
+            drop(count);
+
+            Self::recount_room(inner_for_sub.clone(), &room_id_for_sub);
+
+            // Messages arriving in the room being viewed are read immediately
+            let is_active_room = inner_for_sub.active_room_id.lock().unwrap().as_ref() == Some(&room_id_for_sub);
+            if is_active_room {
+                Self::advance_read_cursor(inner_for_sub.clone(), &room_id_for_sub);
+            }
 
             // Skip initial load
-            if *count == 1 {
+            if is_initial_load {
                 return;
             }
 
             // After initial load, any adds from other users trigger notification
-            let current_user_id = inner_for_sub.current_user_id.lock().unwrap();
-            let new_messages_from_others: Vec<_> = changeset
-                .adds()
-                .into_iter()
-                .filter(|msg| {
-                    // msg.user() returns Result<String>
-                    let msg_user_id = msg.user().ok();
-                    msg_user_id.as_deref() != current_user_id.as_deref()
-                })
-                .collect();
-
-            if !new_messages_from_others.is_empty() {
-                tracing::info!("NotificationManager: {} new messages from others", new_messages_from_others.len());
-
-                // Only increment unread count if not the active room
-                let active_room_id = inner_for_sub.active_room_id.lock().unwrap();
-                let is_active_room = active_room_id.as_ref() == Some(&room_id_for_sub);
-
-                if !is_active_room {
-                    let mut counts = inner_for_sub.unread_counts.peek().clone();
-                    let new_count = counts.get(&room_id_for_sub).unwrap_or(&0) + new_messages_from_others.len();
-                    counts.insert(room_id_for_sub.clone(), new_count);
-                    inner_for_sub.unread_counts.set(counts);
-                }
+            let current_user_id = inner_for_sub.current_user_id.lock().unwrap().clone();
+            let has_new_messages_from_others =
+                changeset.adds().into_iter().any(|msg| msg.user().ok().as_deref() != current_user_id.as_deref());
 
-                // Always play sound for messages from others (even in active room)
+            // Always play sound for messages from others (even in active room)
+            if has_new_messages_from_others {
                 Self::play_notification_sound(inner_for_sub.clone());
             }
         });
 
-        inner
-            .room_queries
-            .lock()
-            .unwrap()
-            .insert(room_id, RoomQueryState { _query: query, _guard: guard, notification_count: *notification_count.lock().unwrap() });
+        inner.room_queries.lock().unwrap().insert(room_id.clone(), RoomQueryState { query, _guard: guard, last_read_timestamp });
+
+        // A room that appears after the cursors loaded (newly created, or newly visible) starts out read
+        let cursors_loaded = *inner.cursors_loaded.lock().unwrap();
+        if cursors_loaded && Self::cursor_timestamp(&inner, &room_id) == 0 {
+            Self::start_read_cursor(inner, room_id);
+        }
     }
 
     fn remove_room_query(inner: Arc<Inner>, room_id: String) {
         inner.room_queries.lock().unwrap().remove(&room_id);
 
-        // Remove unread count for this room
-        let mut counts = inner.unread_counts.peek().clone();
-        counts.remove(&room_id);
-        inner.unread_counts.set(counts);
+        // Remove unread and mention counts for this room
+        set_room_count(&inner.unread_counts, &room_id, 0);
+        set_room_count(&inner.mention_counts, &room_id, 0);
+    }
+
+    /// Latest read timestamp for a room across all of the user's cursors (0 if never read).
+    /// Tabs racing to create a room's first cursor can leave duplicates, so take the max.
+    fn cursor_timestamp(inner: &Arc<Inner>, room_id: &str) -> i64 {
+        let cursors = inner.read_cursors.lock().unwrap().as_ref().map(|state| state.query.get()).unwrap_or_default();
+        cursors.iter().filter(|c| c.room().unwrap_or_default() == room_id).map(|c| c.last_read_timestamp().unwrap_or(0)).max().unwrap_or(0)
+    }
+
+    /// Narrow each room's unread query to match its (possibly remotely updated) read cursor.
+    fn apply_read_cursors(inner: Arc<Inner>, cursors: &[ReadCursorView]) {
+        let mut latest: HashMap<String, i64> = HashMap::new();
+        for cursor in cursors {
+            let timestamp = cursor.last_read_timestamp().unwrap_or(0);
+            let entry = latest.entry(cursor.room().unwrap_or_default()).or_insert(0);
+            *entry = (*entry).max(timestamp);
+        }
+
+        // Collect selection updates first so query callbacks never run while room_queries is locked
+        let mut updates = Vec::new();
+        for (room_id, state) in inner.room_queries.lock().unwrap().iter_mut() {
+            let timestamp = latest.get(room_id).copied().unwrap_or(0);
+            if timestamp != state.last_read_timestamp {
+                state.last_read_timestamp = timestamp;
+                updates.push((state.query.clone(), unread_predicate(room_id, timestamp)));
+            }
+        }
+
+        for (query, predicate) in updates {
+            let _ = query.update_selection(predicate.as_str());
+        }
+    }
+
+    /// Recompute the exact unread and mention counts for a room from its unread query, ignoring own messages.
+    /// The active room is always considered read.
+    fn recount_room(inner: Arc<Inner>, room_id: &str) {
+        let Some(query) = inner.room_queries.lock().unwrap().get(room_id).map(|state| state.query.clone()) else { return };
+        let is_active_room = inner.active_room_id.lock().unwrap().as_deref() == Some(room_id);
+        let current_user_id = inner.current_user_id.lock().unwrap().clone();
+        let (unread, mentions) = if is_active_room {
+            (0, 0)
+        } else {
+            let from_others: Vec<MessageView> =
+                query.get().into_iter().filter(|msg| msg.user().ok().as_deref() != current_user_id.as_deref()).collect();
+            let mentions =
+                current_user_id.as_deref().map(|id| from_others.iter().filter(|msg| mentions_user(msg, id)).count()).unwrap_or(0);
+            (from_others.len(), mentions)
+        };
+
+        set_room_count(&inner.unread_counts, room_id, unread);
+        set_room_count(&inner.mention_counts, room_id, mentions);
+    }
+
+    /// Move the user's read cursor for a room up to the newest message currently in the room.
+    fn advance_read_cursor(inner: Arc<Inner>, room_id: &str) {
+        let Some(user_id) = inner.current_user_id.lock().unwrap().clone() else { return };
+        let Some(query) = inner.room_queries.lock().unwrap().get(room_id).map(|state| state.query.clone()) else { return };
+
+        // The unread query only holds messages newer than the cursor, so its newest entry is the new cursor position
+        let Some(newest) = query.get().into_iter().max_by_key(|msg| msg.timestamp().unwrap_or(0)) else { return };
+        let newest_timestamp = newest.timestamp().unwrap_or(0);
+
+        let existing = inner.read_cursors.lock().unwrap().as_ref().and_then(|state| {
+            state.query.get().into_iter().filter(|c| c.room().unwrap_or_default() == room_id).max_by_key(|c| c.last_read_timestamp().unwrap_or(0))
+        });
+        if existing.as_ref().is_some_and(|c| c.last_read_timestamp().unwrap_or(0) >= newest_timestamp) {
+            return;
+        }
+
+        let room_id = room_id.to_string();
+        let message_id = newest.id().to_base64();
+        spawn_local(async move {
+            match (|| async {
+                let trx = ctx().begin();
+                if let Some(cursor) = existing {
+                    let mutable = cursor.edit(&trx)?;
+                    mutable.last_read_timestamp().set(&newest_timestamp)?;
+                    mutable.last_read_message().set(&message_id)?;
+                } else {
+                    trx.create(&ReadCursor {
+                        user: user_id,
+                        room: room_id,
+                        last_read_timestamp: newest_timestamp,
+                        last_read_message: message_id,
+                    })
+                    .await?;
+                }
+                trx.commit().await?;
+                Ok::<_, Box<dyn std::error::Error>>(())
+            })()
+            .await
+            {
+                Ok(_) => tracing::debug!("Read cursor advanced"),
+                Err(e) => tracing::error!("Failed to update read cursor: {}", e),
+            }
+        });
+    }
+
+    /// Create the user's cursor for a room they have never read, at the current time.
+    fn start_read_cursor(inner: Arc<Inner>, room_id: String) {
+        let Some(user_id) = inner.current_user_id.lock().unwrap().clone() else { return };
+        spawn_local(async move {
+            match (|| async {
+                let trx = ctx().begin();
+                let last_read_timestamp = js_sys::Date::now() as i64;
+                trx.create(&ReadCursor { user: user_id, room: room_id, last_read_timestamp, last_read_message: String::new() }).await?;
+                trx.commit().await?;
+                Ok::<_, Box<dyn std::error::Error>>(())
+            })()
+            .await
+            {
+                Ok(_) => tracing::debug!("Read cursor started"),
+                Err(e) => tracing::error!("Failed to create read cursor: {}", e),
+            }
+        });
     }
 
     fn play_notification_sound(inner: Arc<Inner>) {
@@ -266,9 +433,14 @@ impl NotificationManager {
         }
     }
 
-    /// Get unread message counts by room ID (base64).
-    pub fn unread_counts(&self) -> HashMap<String, usize> {
-        self.0.unread_counts.peek().clone()
+    /// Get unread message counts by room ID (base64), at most [`UNREAD_LIMIT`] per room.
+    pub fn unread_counts(&self) -> Read<HashMap<String, usize>> {
+        self.0.unread_counts.read()
+    }
+
+    /// Get counts of unread messages that mention the current user, by room ID (base64).
+    pub fn mention_counts(&self) -> Read<HashMap<String, usize>> {
+        self.0.mention_counts.read()
     }
 
     /// Set the currently active room (for marking messages as read).
@@ -281,8 +453,38 @@ impl NotificationManager {
     }
 
     fn mark_as_read(&self, room_id: &str) {
-        let mut counts = self.0.unread_counts.peek().clone();
-        counts.remove(room_id);
-        self.0.unread_counts.set(counts);
+        // Clear the badge right away; the synced cursor brings other tabs and devices along
+        set_room_count(&self.0.unread_counts, room_id, 0);
+        set_room_count(&self.0.mention_counts, room_id, 0);
+
+        Self::advance_read_cursor((*self.0).clone(), room_id);
     }
 }
+
+/// Store a room's count, dropping zero entries and skipping no-op updates.
+fn set_room_count(counts: &Mut<HashMap<String, usize>>, room_id: &str, count: usize) {
+    if counts.peek().get(room_id).copied().unwrap_or(0) == count {
+        return;
+    }
+    let mut updated = counts.peek().clone();
+    if count == 0 {
+        updated.remove(room_id);
+    } else {
+        updated.insert(room_id.to_string(), count);
+    }
+    counts.set(updated);
+}
+
+/// Badge text for an unread count.
+pub fn badge_count(count: usize) -> String {
+    if count >= UNREAD_LIMIT { format!("{}+", UNREAD_LIMIT - 1) } else { count.to_string() }
+}
+
+/// The newest main-timeline messages in a room after the given read cursor timestamp; thread replies
+/// are left to the thread panel.
+fn unread_predicate(room_id: &str, last_read_timestamp: i64) -> String {
+    format!(
+        "room = '{}' AND thread = '' AND deleted = false AND timestamp > {} ORDER BY timestamp DESC LIMIT {}",
+        room_id, last_read_timestamp, UNREAD_LIMIT
+    )
+}
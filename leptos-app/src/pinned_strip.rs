@@ -0,0 +1,96 @@
+use leptos::prelude::*;
+use wasm_bindgen_futures::spawn_local;
+
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{MessageView, PinView};
+
+use crate::{
+    chat::{fetch_message, reveal_message},
+    chat_scroll_manager::ChatScrollManager,
+    ctx,
+};
+
+/// Longest pinned message preview before it is cut off.
+const PREVIEW_CHARS: usize = 80;
+
+fn preview(text: &str) -> String {
+    if text.chars().count() > PREVIEW_CHARS { format!("{}…", text.chars().take(PREVIEW_CHARS).collect::<String>()) } else { text.to_string() }
+}
+
+/// Collapsible strip listing the selected room's pinned messages, newest pin first.
+/// Clicking a pin jumps to the message in the timeline, loading its history if needed.
+#[component]
+pub fn PinnedStrip(room_id: String, manager: ChatScrollManager, open_thread: RwSignal<Option<MessageView>>) -> impl IntoView {
+    let collapsed = RwSignal::new(false);
+    let pins = ctx()
+        .query::<PinView>(format!("room = '{}' AND deleted = false ORDER BY timestamp DESC", room_id).as_str())
+        .expect("failed to create PinView LiveQuery");
+    let pins_for_show = pins.clone();
+    let pins_for_count = pins.clone();
+
+    view! {
+        <Show when=move || !pins_for_show.get().is_empty()>
+            {
+                let pins = pins.clone();
+                let manager = manager.clone();
+                let pins_for_count = pins_for_count.clone();
+                view! {
+                    <div class="pinnedStrip">
+                        <button class="pinnedHeader" on:click=move |_| collapsed.update(|c| *c = !*c)>
+                            <span>{move || format!("📌 Pinned ({})", pins_for_count.get().len())}</span>
+                            <span class="pinnedToggle">{move || if collapsed.get() { "▸" } else { "▾" }}</span>
+                        </button>
+                        <Show when=move || !collapsed.get()>
+                            <div class="pinnedList">
+                                <For
+                                    each={
+                                        let pins = pins.clone();
+                                        move || pins.get()
+                                    }
+                                    key=|pin: &PinView| pin.id()
+                                    children={
+                                        let manager = manager.clone();
+                                        move |pin: PinView| view! { <PinnedItem pin manager=manager.clone() open_thread /> }
+                                    }
+                                />
+                            </div>
+                        </Show>
+                    </div>
+                }
+            }
+        </Show>
+    }
+}
+
+#[component]
+fn PinnedItem(pin: PinView, manager: ChatScrollManager, open_thread: RwSignal<Option<MessageView>>) -> impl IntoView {
+    let message = RwSignal::new(None::<MessageView>);
+    let message_id = pin.message().unwrap_or_default();
+    spawn_local(async move {
+        match fetch_message(&message_id).await {
+            Ok(view) => {
+                let _ = message.try_set(Some(view));
+            }
+            Err(e) => tracing::error!("Failed to load pinned message: {}", e),
+        }
+    });
+
+    let jump = move |target: MessageView| reveal_message(manager.clone(), target, open_thread);
+
+    view! {
+        {move || {
+            message
+                .get()
+                .filter(|m| !m.deleted().unwrap_or(false))
+                .map(|m| {
+                    let text = preview(&m.text().unwrap_or_default());
+                    let jump = jump.clone();
+                    view! {
+                        <button class="pinnedItem" on:click=move |_| jump(m.clone()) title="Jump to message">
+                            {text}
+                        </button>
+                    }
+                })
+        }}
+    }
+}
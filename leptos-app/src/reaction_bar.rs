@@ -0,0 +1,162 @@
+use std::collections::HashSet;
+
+use leptos::prelude::*;
+
+use ankurah_template_model::{MessageView, Reaction, ReactionView};
+
+use crate::ctx;
+
+/// Emojis offered by the quick reaction picker.
+const QUICK_REACTIONS: &[&str] = &["👍", "❤️", "😂", "🎉", "👀", "✅"];
+
+/// One aggregated reaction chip: an emoji, how many users picked it, and whether the current user did.
+#[derive(Debug, Clone, PartialEq)]
+struct ReactionChip {
+    emoji: String,
+    count: usize,
+    mine: bool,
+}
+
+/// Group active reactions by emoji, keeping the order in which each emoji first appeared.
+/// Each user counts once per emoji, even if racing tabs left them more than one reaction.
+fn aggregate_reactions(reactions: &[ReactionView], current_user_id: Option<&str>) -> Vec<ReactionChip> {
+    let mut chips: Vec<(ReactionChip, HashSet<String>)> = Vec::new();
+    for reaction in reactions.iter().filter(|r| !r.deleted().unwrap_or(false)) {
+        let emoji = reaction.emoji().unwrap_or_default();
+        let user = reaction.user().unwrap_or_default();
+        let mine = current_user_id.is_some_and(|id| user == id);
+        let index = match chips.iter().position(|(chip, _)| chip.emoji == emoji) {
+            Some(index) => index,
+            None => {
+                chips.push((ReactionChip { emoji, count: 0, mine: false }, HashSet::new()));
+                chips.len() - 1
+            }
+        };
+        let (chip, users) = &mut chips[index];
+        users.insert(user);
+        chip.count = users.len();
+        chip.mine |= mine;
+    }
+    chips.into_iter().map(|(chip, _)| chip).collect()
+}
+
+/// Toggle the current user's reaction with the given emoji.
+/// Reuses an existing Reaction entity (flipping `deleted`) so each (message, user, emoji) has at most one entity.
+/// Withdrawing deletes every live one, in case racing tabs created more than one.
+fn toggle_reaction(reactions: Vec<ReactionView>, message_id: String, user_id: String, emoji: String) {
+    let existing: Vec<ReactionView> =
+        reactions.into_iter().filter(|r| r.user().unwrap_or_default() == user_id && r.emoji().unwrap_or_default() == emoji).collect();
+    let live: Vec<ReactionView> = existing.iter().filter(|r| !r.deleted().unwrap_or(false)).cloned().collect();
+
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            if !live.is_empty() {
+                for reaction in live {
+                    reaction.edit(&trx)?.deleted().set(&true)?;
+                }
+            } else if let Some(reaction) = existing.into_iter().next() {
+                reaction.edit(&trx)?.deleted().set(&false)?;
+            } else {
+                trx.create(&Reaction { message: message_id, user: user_id, emoji, deleted: false }).await?;
+            }
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::info!("Reaction toggled"),
+            Err(e) => tracing::error!("Failed to toggle reaction: {}", e),
+        }
+    });
+}
+
+/// Reaction chips shown under a message bubble, plus a quick picker for adding a new reaction.
+/// Clicking a chip toggles the current user's reaction with that emoji.
+/// `reactions` are the message's reactions, including deleted ones so toggling can reuse the existing entity.
+#[component]
+pub fn ReactionBar(
+    message: MessageView,
+    current_user_id: Option<String>,
+    #[prop(into)] reactions: Signal<Vec<ReactionView>>,
+) -> impl IntoView {
+    let message_id = message.id().to_base64();
+    let show_picker = RwSignal::new(false);
+
+    let toggle = {
+        let current_user_id = current_user_id.clone();
+        move |emoji: String| {
+            let Some(user_id) = current_user_id.clone() else { return };
+            toggle_reaction(reactions.get_untracked(), message_id.clone(), user_id, emoji);
+        }
+    };
+
+    let chips = {
+        let current_user_id = current_user_id.clone();
+        move || aggregate_reactions(&reactions.get(), current_user_id.as_deref())
+    };
+
+    let can_react = current_user_id.is_some();
+
+    view! {
+        <div class="reactionBar">
+            <For
+                each=chips
+                key=|chip: &ReactionChip| (chip.emoji.clone(), chip.count, chip.mine)
+                children={
+                    let toggle = toggle.clone();
+                    move |chip: ReactionChip| {
+                        let toggle = toggle.clone();
+                        let emoji = chip.emoji.clone();
+                        view! {
+                            <button
+                                class=if chip.mine { "reactionChip mine" } else { "reactionChip" }
+                                on:click=move |_| toggle(emoji.clone())
+                                prop:disabled=!can_react
+                            >
+                                {chip.emoji.clone()}
+                                <span class="reactionCount">{chip.count}</span>
+                            </button>
+                        }
+                    }
+                }
+            />
+            <Show when=move || can_react>
+                <button class="reactionAdd" on:click=move |_| show_picker.update(|v| *v = !*v) title="Add reaction">
+                    "☺+"
+                </button>
+            </Show>
+            <Show when=move || show_picker.get()>
+                {
+                    let toggle = toggle.clone();
+                    move || {
+                        let toggle = toggle.clone();
+                        view! {
+                            <div class="reactionPicker">
+                                {QUICK_REACTIONS
+                                    .iter()
+                                    .map(|emoji| {
+                                        let toggle = toggle.clone();
+                                        let emoji = emoji.to_string();
+                                        let label = emoji.clone();
+                                        view! {
+                                            <button
+                                                class="reactionPickerItem"
+                                                on:click=move |_| {
+                                                    toggle(emoji.clone());
+                                                    show_picker.set(false);
+                                                }
+                                            >
+                                                {label}
+                                            </button>
+                                        }
+                                    })
+                                    .collect_view()}
+                            </div>
+                        }
+                    }
+                }
+            </Show>
+        </div>
+    }
+}
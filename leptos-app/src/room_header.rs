@@ -0,0 +1,91 @@
+use leptos::prelude::*;
+
+use ankurah_template_model::RoomView;
+
+use crate::{
+    ctx,
+    editable_text_field::{EditableTextField, TextSplice},
+};
+
+#[derive(Debug, Clone, Copy)]
+enum RoomField {
+    Name,
+    Topic,
+    Description,
+}
+
+/// Apply a local splice to one of the room's YrsString fields so it merges with concurrent edits.
+fn apply_room_splice(room: RoomView, field: RoomField, splice: TextSplice) {
+    wasm_bindgen_futures::spawn_local(async move {
+        match (|| async {
+            let trx = ctx().begin();
+            let mutable = room.edit(&trx)?;
+            let text = match field {
+                RoomField::Name => mutable.name(),
+                RoomField::Topic => mutable.topic(),
+                RoomField::Description => mutable.description(),
+            };
+            if splice.delete_len > 0 {
+                text.delete(splice.index as u32, splice.delete_len as u32)?;
+            }
+            if !splice.insert.is_empty() {
+                text.insert(splice.index as u32, &splice.insert)?;
+            }
+            trx.commit().await?;
+            Ok::<_, Box<dyn std::error::Error>>(())
+        })()
+        .await
+        {
+            Ok(_) => tracing::debug!("Room {:?} updated", field),
+            Err(e) => tracing::error!("Failed to update room {:?}: {}", field, e),
+        }
+    });
+}
+
+/// Header above the messages showing the room's name, topic and description, each editable in place.
+#[component]
+pub fn RoomHeader(room: RoomView) -> impl IntoView {
+    let name = Signal::derive({
+        let room = room.clone();
+        move || room.name().unwrap_or_default()
+    });
+    let topic = Signal::derive({
+        let room = room.clone();
+        move || room.topic().unwrap_or_default()
+    });
+    let description = Signal::derive({
+        let room = room.clone();
+        move || room.description().unwrap_or_default()
+    });
+
+    let field = move |field: RoomField| {
+        let room = room.clone();
+        move |splice: TextSplice| apply_room_splice(room.clone(), field, splice)
+    };
+
+    view! {
+        <div class="roomHeader">
+            <div class="roomHeaderTitle">
+                <span class="roomHeaderHash">"#"</span>
+                <EditableTextField
+                    value=name
+                    on_edit=field(RoomField::Name)
+                    placeholder="Room name".to_string()
+                    class="roomHeaderName".to_string()
+                />
+                <EditableTextField
+                    value=topic
+                    on_edit=field(RoomField::Topic)
+                    placeholder="Add a topic".to_string()
+                    class="roomHeaderTopic".to_string()
+                />
+            </div>
+            <EditableTextField
+                value=description
+                on_edit=field(RoomField::Description)
+                placeholder="Add a description".to_string()
+                class="roomHeaderDescription".to_string()
+            />
+        </div>
+    }
+}
@@ -4,9 +4,9 @@ use web_sys::{KeyboardEvent, window};
 
 use ankurah::{LiveQuery, model::Mutable};
 use ankurah_signals::Get as AnkurahGet;
-use ankurah_template_model::{Room, RoomView};
+use ankurah_template_model::{Room, RoomView, UserView};
 
-use crate::{ctx, notification_manager::NotificationManager};
+use crate::{chat::MessageJump, ctx, member_list::MemberList, notification_manager::{NotificationManager, badge_count}, search_panel::SearchPanel};
 
 /// Auto-select a room from the list if none is currently selected.
 /// Chooses based on URL parameter or defaults to "General".
@@ -63,14 +63,20 @@ fn sync_url_with_room(selected_room: &RwSignal<Option<RoomView>>) -> impl Fn() +
 pub fn RoomList(
     rooms: LiveQuery<RoomView>,
     selected_room: RwSignal<Option<RoomView>>,
+    users: LiveQuery<UserView>,
+    current_user: RwSignal<Option<UserView>>,
+    message_jump: RwSignal<Option<MessageJump>>,
     notification_manager: NotificationManager,
 ) -> impl IntoView {
     let is_creating = RwSignal::new(false);
     Effect::new(auto_select_room(&rooms, selected_room));
     Effect::new(sync_url_with_room(&selected_room));
+    let rooms_for_search = rooms.clone();
 
     view! {
         <div class="sidebar">
+            <SearchPanel rooms=rooms_for_search selected_room current_user message_jump />
+
             <div class="sidebarHeader">
                 <span>"Rooms"</span>
                 <button class="createRoomButton" on:click=move |_| is_creating.set(true) title="Create new room">
@@ -85,6 +91,8 @@ pub fn RoomList(
 
                 <RoomListUl rooms selected_room notification_manager />
             </div>
+
+            <MemberList users />
         </div>
     }
 }
@@ -118,11 +126,16 @@ fn RoomListUl(
 #[component]
 fn RoomItem(room: RoomView, selected_room: RwSignal<Option<RoomView>>, notification_manager: NotificationManager) -> impl IntoView {
     let room_id = room.id().to_base64();
-    let name = room.name().unwrap_or_default();
-    let unread_count = notification_manager.unread_counts().get(&room_id).copied().unwrap_or(0);
-
+    let unread_counts = notification_manager.unread_counts();
+    let room_id_for_count = room_id.clone();
+    let unread_count = move || unread_counts.get().get(&room_id_for_count).copied().unwrap_or(0);
+    let mention_counts = notification_manager.mention_counts();
+    let room_id_for_mentions = room_id.clone();
+    let mention_count = move || mention_counts.get().get(&room_id_for_mentions).copied().unwrap_or(0);
     let is_selected = move || selected_room.get().as_ref().map(|r| r.id().to_base64() == room_id).unwrap_or(false);
 
+    let room_for_name = room.clone();
+    let name = move || room_for_name.name().unwrap_or_default();
     let room_for_click = room.clone();
 
     view! {
@@ -131,14 +144,16 @@ fn RoomItem(room: RoomView, selected_room: RwSignal<Option<RoomView>>, notificat
             on:click=move |_| selected_room.set(Some(room_for_click.clone()))
         >
             "# " {name}
-            {move || {
-                if unread_count > 0 {
-                    let badge_text = if unread_count >= 10 { "10+".to_string() } else { unread_count.to_string() };
-                    Some(view! { <span class="unreadBadge">{badge_text}</span> })
-                } else {
-                    None
-                }
-            }}
+            <span class="roomBadges">
+                {move || {
+                    let count = mention_count();
+                    (count > 0).then(|| view! { <span class="mentionBadge" title="Mentions">"@"{count}</span> })
+                }}
+                {move || {
+                    let count = unread_count();
+                    (count > 0).then(|| view! { <span class="unreadBadge">{badge_count(count)}</span> })
+                }}
+            </span>
         </div>
     }
 }
@@ -158,7 +173,10 @@ fn NewRoomInput(selected_room: RwSignal<Option<RoomView>>, on_cancel: impl Fn()
                     wasm_bindgen_futures::spawn_local(async move {
                         match (|| async {
                             let transaction = ctx().begin();
-                            let room = transaction.create(&Room { name }).await?.read();
+                            let room = transaction
+                                .create(&Room { name, topic: String::new(), description: String::new() })
+                                .await?
+                                .read();
                             transaction.commit().await?;
                             Ok::<_, Box<dyn std::error::Error>>(room)
                         })()
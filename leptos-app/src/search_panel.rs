@@ -0,0 +1,163 @@
+use leptos::prelude::*;
+use web_sys::KeyboardEvent;
+
+use ankurah::{LiveQuery, model::Mutable};
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{RoomView, SearchQuery, SearchQueryView, UserView, search::SearchHit};
+
+use crate::{chat::MessageJump, ctx};
+
+/// Split a snippet into (text, is_match) runs using the highlight byte ranges from the server.
+fn highlight_runs(snippet: &str, highlights: &[(usize, usize)]) -> Vec<(String, bool)> {
+    let mut runs = Vec::new();
+    let mut pos = 0;
+    for &(start, end) in highlights {
+        // Ignore ranges that overlap, run past the end or split a character
+        if start < pos || snippet.get(start..end).is_none() {
+            continue;
+        }
+        if start > pos {
+            runs.push((snippet[pos..start].to_string(), false));
+        }
+        runs.push((snippet[start..end].to_string(), true));
+        pos = end;
+    }
+    if pos < snippet.len() {
+        runs.push((snippet[pos..].to_string(), false));
+    }
+    runs
+}
+
+/// Message search box. Pressing Enter sends the query to the server's search index through a
+/// `SearchQuery` entity; the ranked hits show up in a dropdown once the server has answered.
+/// Clicking a hit opens its room and jumps to the message.
+#[component]
+pub fn SearchPanel(
+    rooms: LiveQuery<RoomView>,
+    selected_room: RwSignal<Option<RoomView>>,
+    current_user: RwSignal<Option<UserView>>,
+    message_jump: RwSignal<Option<MessageJump>>,
+) -> impl IntoView {
+    let input = RwSignal::new(String::new());
+    let active_query = RwSignal::new(None::<SearchQueryView>);
+
+    let submit = move || {
+        let text = input.get_untracked().trim().to_string();
+        if text.is_empty() {
+            active_query.set(None);
+            return;
+        }
+        let Some(user) = current_user.get_untracked() else { return };
+
+        wasm_bindgen_futures::spawn_local(async move {
+            match (|| async {
+                let trx = ctx().begin();
+                let query = trx
+                    .create(&SearchQuery {
+                        user: user.id().to_base64(),
+                        query: text,
+                        room: String::new(),
+                        timestamp: js_sys::Date::now() as i64,
+                        status: "pending".to_string(),
+                        results: String::new(),
+                        answered_at: 0,
+                    })
+                    .await?
+                    .read();
+                trx.commit().await?;
+                Ok::<_, Box<dyn std::error::Error>>(query)
+            })()
+            .await
+            {
+                // A newer query replaces the previous one, so late answers to old queries are never shown
+                Ok(query) => active_query.set(Some(query)),
+                Err(e) => tracing::error!("Failed to send search query: {}", e),
+            }
+        });
+    };
+
+    let handle_key = move |ev: KeyboardEvent| match ev.key().as_str() {
+        "Enter" => {
+            ev.prevent_default();
+            submit();
+        }
+        "Escape" => {
+            input.set(String::new());
+            active_query.set(None);
+        }
+        _ => {}
+    };
+
+    let rooms_for_open = rooms.clone();
+    let open_hit = move |hit: SearchHit| {
+        if let Some(room) = rooms_for_open.get().into_iter().find(|r| r.id().to_base64() == hit.room) {
+            selected_room.set(Some(room));
+            message_jump.set(Some(MessageJump { room_id: hit.room, message_id: hit.message }));
+        }
+        active_query.set(None);
+    };
+
+    view! {
+        <div class="searchPanel">
+            <input
+                type="search"
+                class="searchInput"
+                placeholder="Search messages..."
+                prop:value=move || input.get()
+                on:input=move |ev| input.set(event_target_value(&ev))
+                on:keydown=handle_key
+            />
+            {move || {
+                active_query.get().map(|query| {
+                    let open_hit = open_hit.clone();
+                    let rooms = rooms.clone();
+                    view! {
+                        <div class="searchResults">
+                            {move || {
+                                if query.status().unwrap_or_default() == "pending" {
+                                    return view! { <div class="searchStatus">"Searching..."</div> }.into_any();
+                                }
+                                if query.status().unwrap_or_default() == "expired" {
+                                    return view! { <div class="searchStatus">"This search has expired; search again"</div> }.into_any();
+                                }
+                                let hits = SearchHit::decode(&query.results().unwrap_or_default());
+                                if hits.is_empty() {
+                                    return view! { <div class="searchStatus">"No matching messages"</div> }.into_any();
+                                }
+                                let room_names: Vec<(String, String)> =
+                                    rooms.get().iter().map(|r| (r.id().to_base64(), r.name().unwrap_or_default())).collect();
+                                hits.into_iter()
+                                    .map(|hit| {
+                                        let room_name = room_names
+                                            .iter()
+                                            .find(|(id, _)| *id == hit.room)
+                                            .map(|(_, name)| name.clone())
+                                            .unwrap_or_default();
+                                        let runs = highlight_runs(&hit.snippet, &hit.highlights)
+                                            .into_iter()
+                                            .map(|(text, is_match)| {
+                                                if is_match {
+                                                    view! { <mark class="searchMatch">{text}</mark> }.into_any()
+                                                } else {
+                                                    text.into_any()
+                                                }
+                                            })
+                                            .collect::<Vec<_>>();
+                                        let open_hit = open_hit.clone();
+                                        view! {
+                                            <button class="searchHit" on:click=move |_| open_hit(hit.clone())>
+                                                <span class="searchHitRoom">"# "{room_name}</span>
+                                                <span class="searchHitSnippet">{runs}</span>
+                                            </button>
+                                        }
+                                    })
+                                    .collect::<Vec<_>>()
+                                    .into_any()
+                            }}
+                        </div>
+                    }
+                })
+            }}
+        </div>
+    }
+}
@@ -0,0 +1,144 @@
+use leptos::html::Div;
+use leptos::prelude::*;
+
+use ankurah::LiveQuery;
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{MessageView, RoomView, UserView};
+
+use crate::{
+    chat_scroll_manager::ChatScrollManager, message_input::MessageInput, message_list::MessageList, message_row::MessageRow,
+    notification_manager::NotificationManager,
+};
+
+/// "N replies" / "Reply" affordance shown under a thread root in the main timeline.
+/// Clicking it opens the thread panel for that message.
+#[component]
+pub fn ThreadReplyLink(
+    message: MessageView,
+    open_thread: RwSignal<Option<MessageView>>,
+    #[prop(into)] reply_count: Signal<usize>,
+) -> impl IntoView {
+    view! {
+        <button
+            class=move || { if reply_count.get() > 0 { "threadReplyLink hasReplies" } else { "threadReplyLink" } }
+            on:click=move |_| open_thread.set(Some(message.clone()))
+        >
+            {move || match reply_count.get() {
+                0 => "Reply".to_string(),
+                1 => "1 reply".to_string(),
+                n => format!("{} replies", n),
+            }}
+        </button>
+    }
+}
+
+/// Side panel showing the replies of the currently open thread.
+/// Owns its own ChatScrollManager scoped to the thread, so paging works like the main timeline.
+#[component]
+pub fn ThreadPanel(
+    room: RwSignal<Option<RoomView>>,
+    open_thread: RwSignal<Option<MessageView>>,
+    current_user: RwSignal<Option<UserView>>,
+    users: LiveQuery<UserView>,
+    notification_manager: NotificationManager,
+) -> impl IntoView {
+    let editing_message = RwSignal::new(None::<MessageView>);
+    let manager = RwSignal::new(None::<ChatScrollManager>);
+
+    // Replace the thread manager whenever a different thread is opened
+    Effect::new({
+        let notification_manager = notification_manager.clone();
+        move |_| {
+            let new_manager = open_thread.get().map(|root| {
+                ChatScrollManager::new_thread(root.room().unwrap_or_default(), root.id().to_base64(), notification_manager.clone())
+            });
+
+            // Clean up old manager before setting new one (use untracked to avoid loop)
+            manager.update_untracked(|old| {
+                if let Some(old_manager) = old.take() {
+                    old_manager.destroy();
+                }
+                *old = new_manager;
+            });
+            editing_message.set(None);
+        }
+    });
+
+    let messages_container_ref = NodeRef::<Div>::new();
+
+    // Bind container to scroll manager after it's rendered
+    Effect::new(move |_| {
+        if let Some(m) = manager.get() {
+            m.bind_container(messages_container_ref.get_untracked());
+        }
+    });
+
+    // Call after_layout when replies change
+    Effect::new(move |prev_count: Option<usize>| {
+        let Some(m) = manager.get_untracked() else { return 0 };
+        let count = m.messages().get().len();
+        if prev_count.is_some_and(|prev| prev != count) {
+            let m = m.clone();
+            leptos::task::spawn_local(async move {
+                leptos::task::tick().await;
+                m.after_layout();
+            });
+        }
+        count
+    });
+
+    view! {
+        <Show when=move || open_thread.get().is_some() && room.get().is_some()>
+            {
+                let users = users.clone();
+                move || {
+                    let root = open_thread.get()?;
+                    let current_room = room.get()?;
+                    let mgr = manager.get()?;
+                    let current_user_id = current_user.get().map(|u| u.id().to_base64());
+                    let root_id = root.id().to_base64();
+                    let mgr_for_list = mgr.clone();
+                    let mgr_for_root = mgr.clone();
+                    let mgr_for_rows = mgr.clone();
+
+                    Some(view! {
+                        <div class="threadPanel">
+                            <div class="threadHeader">
+                                <span>"Thread"</span>
+                                <button class="threadClose" on:click=move |_| open_thread.set(None) title="Close thread">
+                                    "×"
+                                </button>
+                            </div>
+                            <div class="threadRoot">
+                                <MessageRow
+                                    message=root
+                                    users=users.clone()
+                                    current_user_id=current_user_id.clone()
+                                    editing_message=editing_message
+                                    manager=mgr_for_root
+                                />
+                            </div>
+                            <div class="messagesContainer threadMessages" node_ref=messages_container_ref>
+                                <MessageList
+                                    messages=Signal::derive(move || mgr_for_list.items())
+                                    users=users.clone()
+                                    current_user_id=current_user_id
+                                    editing_message=editing_message
+                                    manager=mgr_for_rows
+                                />
+                            </div>
+                            <MessageInput
+                                room=current_room
+                                current_user=current_user.get()
+                                editing_message=editing_message
+                                users=users.clone()
+                                manager=mgr
+                                thread=root_id
+                            />
+                        </div>
+                    })
+                }
+            }
+        </Show>
+    }
+}
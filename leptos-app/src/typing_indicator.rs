@@ -0,0 +1,126 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use leptos::prelude::*;
+use send_wrapper::SendWrapper;
+use wasm_bindgen_futures::spawn_local;
+
+use ankurah::{LiveQuery, model::Mutable};
+use ankurah_signals::Get as AnkurahGet;
+use ankurah_template_model::{Typing, TypingView, UserView};
+
+use crate::ctx;
+
+/// Minimum time between typing broadcasts from one input.
+const TYPING_THROTTLE_MS: f64 = 2_000.0;
+/// How long a typing broadcast stays valid; the server deactivates it afterwards.
+const TYPING_TTL_MS: f64 = 6_000.0;
+
+/// Publishes the current user's typing state for a room.
+/// Reuses a single Typing entity per (room, user) and throttles updates from keystrokes.
+#[derive(Clone)]
+pub struct TypingNotifier(SendWrapper<Rc<Inner>>);
+
+struct Inner {
+    room_id: String,
+    user_id: String,
+    last_sent_at: Cell<f64>,
+    entity: RefCell<Option<TypingView>>,
+}
+
+impl TypingNotifier {
+    pub fn new(room_id: String, user_id: String) -> Self {
+        Self(SendWrapper::new(Rc::new(Inner { room_id, user_id, last_sent_at: Cell::new(0.0), entity: RefCell::new(None) })))
+    }
+
+    /// Called on every keystroke; broadcasts at most once per throttle window.
+    pub fn notify_typing(&self) {
+        let now = js_sys::Date::now();
+        if now - self.0.last_sent_at.get() < TYPING_THROTTLE_MS {
+            return;
+        }
+        self.0.last_sent_at.set(now);
+        self.publish(true, (now + TYPING_TTL_MS) as i64);
+    }
+
+    /// Clear the indicator right away (message sent or input emptied).
+    pub fn clear(&self) {
+        if self.0.last_sent_at.get() == 0.0 {
+            return;
+        }
+        self.0.last_sent_at.set(0.0);
+        self.publish(false, js_sys::Date::now() as i64);
+    }
+
+    fn publish(&self, active: bool, expires_at: i64) {
+        let this = self.clone();
+        spawn_local(async move {
+            match (|| async {
+                let context = ctx();
+                let existing = this.0.entity.borrow().clone();
+                let existing = match existing {
+                    Some(view) => Some(view),
+                    None => context
+                        .fetch::<TypingView>(format!("room = '{}' AND user = '{}'", this.0.room_id, this.0.user_id).as_str())
+                        .await?
+                        .into_iter()
+                        .next(),
+                };
+
+                let trx = context.begin();
+                let view = if let Some(view) = existing {
+                    let mutable = view.edit(&trx)?;
+                    mutable.active().set(&active)?;
+                    mutable.expires_at().set(&expires_at)?;
+                    view
+                } else {
+                    trx.create(&Typing { room: this.0.room_id.clone(), user: this.0.user_id.clone(), active, expires_at }).await?.read()
+                };
+                trx.commit().await?;
+                *this.0.entity.borrow_mut() = Some(view);
+                Ok::<_, Box<dyn std::error::Error>>(())
+            })()
+            .await
+            {
+                Ok(_) => tracing::debug!("Typing state published (active={})", active),
+                Err(e) => tracing::error!("Failed to publish typing state: {}", e),
+            }
+        });
+    }
+}
+
+/// "Alice is typing…" line shown under the message input for the selected room.
+#[component]
+pub fn TypingIndicator(room_id: String, current_user_id: Option<String>, users: LiveQuery<UserView>) -> impl IntoView {
+    let typing = ctx()
+        .query::<TypingView>(format!("room = '{}' AND active = true", room_id).as_str())
+        .expect("failed to create TypingView LiveQuery");
+
+    let text = move || {
+        let now = js_sys::Date::now() as i64;
+        let user_list = users.get();
+        let names: Vec<String> = typing
+            .get()
+            .iter()
+            .filter(|t| t.expires_at().unwrap_or(0) > now)
+            .map(|t| t.user().unwrap_or_default())
+            .filter(|user_id| current_user_id.as_deref() != Some(user_id.as_str()))
+            .map(|user_id| {
+                user_list
+                    .iter()
+                    .find(|u| u.id().to_base64() == user_id)
+                    .map(|u| u.display_name().unwrap_or_default())
+                    .unwrap_or_else(|| "Someone".to_string())
+            })
+            .collect();
+
+        match names.as_slice() {
+            [] => String::new(),
+            [one] => format!("{} is typing…", one),
+            [first, second] => format!("{} and {} are typing…", first, second),
+            _ => "Several people are typing…".to_string(),
+        }
+    };
+
+    view! { <div class="typingIndicator">{text}</div> }
+}
@@ -0,0 +1,75 @@
+//! Per-device signing keys and the identity proofs clients attach to their requests.
+//!
+//! Each browser generates an Ed25519 keypair and registers the public half on the `User` entity it
+//! creates. To act as that user it signs a proof binding the user to its own node, the server's node
+//! and the current time; the server verifies the signature against the registered key.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Deserialize, Serialize};
+
+/// `User.public_key` of users the server owns, such as seeded users and bots. It is not a valid key, so no
+/// proof verifies against it and no client can act as them, and only the server may create users with it.
+pub const SERVER_KEY: &str = "server";
+
+/// A device's Ed25519 keypair.
+#[derive(Clone)]
+pub struct DeviceKey(SigningKey);
+
+impl DeviceKey {
+    pub fn generate() -> Result<Self, getrandom::Error> {
+        let mut secret = [0u8; 32];
+        getrandom::fill(&mut secret)?;
+        Ok(Self(SigningKey::from_bytes(&secret)))
+    }
+
+    /// Restore a key saved with `to_base64`.
+    pub fn from_base64(encoded: &str) -> Option<Self> {
+        let secret: [u8; 32] = BASE64.decode(encoded).ok()?.try_into().ok()?;
+        Some(Self(SigningKey::from_bytes(&secret)))
+    }
+
+    /// The secret key, for storing on the device. Never send this anywhere.
+    pub fn to_base64(&self) -> String { BASE64.encode(self.0.to_bytes()) }
+
+    /// Base64-encoded public key, as stored in `User.public_key`.
+    pub fn public_key(&self) -> String { BASE64.encode(self.0.verifying_key().to_bytes()) }
+
+    pub fn prove(&self, user: &str, client_node: &str, server_node: &str, issued_at: i64) -> IdentityProof {
+        let mut proof = IdentityProof {
+            user: user.to_string(),
+            client_node: client_node.to_string(),
+            server_node: server_node.to_string(),
+            issued_at,
+            signature: String::new(),
+        };
+        proof.signature = BASE64.encode(self.0.sign(&proof.signed_bytes()).to_bytes());
+        proof
+    }
+}
+
+/// Signed statement that `user` is acting through `client_node` towards `server_node`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityProof {
+    pub user: String,
+    pub client_node: String,
+    pub server_node: String,
+    /// Unix timestamp in milliseconds; the server rejects stale proofs.
+    pub issued_at: i64,
+    /// Base64-encoded Ed25519 signature over the other fields.
+    pub signature: String,
+}
+
+impl IdentityProof {
+    fn signed_bytes(&self) -> Vec<u8> {
+        format!("ankurah-template identity\n{}\n{}\n{}\n{}", self.user, self.client_node, self.server_node, self.issued_at).into_bytes()
+    }
+
+    /// Whether the signature was made by the key whose public half is `public_key`.
+    pub fn verify(&self, public_key: &str) -> bool {
+        let Some(key) = BASE64.decode(public_key).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok()) else { return false };
+        let Ok(key) = VerifyingKey::from_bytes(&key) else { return false };
+        let Some(signature) = BASE64.decode(&self.signature).ok().and_then(|bytes| Signature::from_slice(&bytes).ok()) else { return false };
+        key.verify(&self.signed_bytes(), &signature).is_ok()
+    }
+}
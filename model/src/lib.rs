@@ -1,15 +1,63 @@
 use ankurah::Model;
 use serde::{Deserialize, Serialize};
 
+pub mod identity;
+pub mod markdown;
+pub mod policy;
+pub mod search;
+
 #[derive(Model, Debug, Serialize, Deserialize)]
 pub struct User {
     pub display_name: String,
+    /// Set by the server while one of the user's clients is connected.
+    #[active_type(LWW)]
+    pub online: bool,
+    /// Unix timestamp in milliseconds of when the server last saw the user come online or go offline.
+    #[active_type(LWW)]
+    pub last_seen: i64,
+    /// Base64-encoded Ed25519 public key of the device that registered the user, or `identity::SERVER_KEY`
+    /// for users the server owns.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub public_key: String,
+    /// One of the `role` constants.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub role: String,
+    /// Banned users can still read but every write is rejected by the server.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub banned: bool,
+    /// Stable key the server finds a user it owns by, such as `fixture:<id>` for fixture users; empty for everyone else.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub server_id: String,
+}
+
+/// Values of `User.role`.
+pub mod role {
+    /// Can delete any message, ban users and change roles.
+    pub const ADMIN: &str = "admin";
+    /// Can delete any message.
+    pub const MODERATOR: &str = "moderator";
+    pub const MEMBER: &str = "member";
+}
+
+impl UserView {
+    pub fn is_admin(&self) -> bool { self.role().unwrap_or_default() == role::ADMIN }
+
+    pub fn can_moderate(&self) -> bool {
+        let current = self.role().unwrap_or_default();
+        current == role::ADMIN || current == role::MODERATOR
+    }
 }
 
 // Room model - chat rooms
 #[derive(Model, Debug, Serialize, Deserialize)]
 pub struct Room {
     pub name: String,
+    pub topic: String,
+    pub description: String,
 }
 
 #[derive(Model, Debug, Serialize, Deserialize)]
@@ -18,8 +66,157 @@ pub struct Message {
     pub user: String,
     #[active_type(LWW)]
     pub room: String,
+    /// ID of the thread root message, or empty for messages in the main room timeline.
+    #[active_type(LWW)]
+    pub thread: String,
     pub text: String,
     pub timestamp: i64,
     #[active_type(LWW)]
     pub deleted: bool,
+    /// Unix timestamp in milliseconds of the latest edit, or 0 if never edited.
+    #[active_type(LWW)]
+    pub edited_at: i64,
+    /// Comma-separated IDs of the users @mentioned in the text.
+    #[active_type(LWW)]
+    pub mentions: String,
+    /// Unix timestamp in milliseconds at which the server saw the message deleted, or 0. Only the server sets this.
+    #[active_type(LWW)]
+    pub deleted_at: i64,
+    /// Set by the server's retention job once the text, revisions and attachments have been erased.
+    #[active_type(LWW)]
+    pub purged: bool,
+    /// Unix timestamp in milliseconds at which the server first stored the message, or 0 until it has.
+    /// Unlike `timestamp`, which the sender's clock sets, only the server sets this.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub received_at: i64,
+    /// Stable key the server finds a message it posted by, such as `fixture:<id>` for welcome messages; empty
+    /// for everyone else. Only the server sets this.
+    #[serde(default)]
+    #[active_type(LWW)]
+    pub server_id: String,
+}
+
+// MessageRevision model - a prior version of a message's text, recorded on each edit
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct MessageRevision {
+    #[active_type(LWW)]
+    pub message: String,
+    #[active_type(LWW)]
+    pub editor: String,
+    /// The message text as it was before this edit.
+    #[active_type(LWW)]
+    pub text: String,
+    pub timestamp: i64,
+}
+
+// Attachment model - a file attached to a message; the bytes live in BlobChunks keyed by content hash
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct Attachment {
+    #[active_type(LWW)]
+    pub message: String,
+    /// ID of the user who attached the file. Only attachments by the message's author are shown.
+    #[active_type(LWW)]
+    pub user: String,
+    #[active_type(LWW)]
+    pub name: String,
+    #[active_type(LWW)]
+    pub mime_type: String,
+    pub size: i64,
+    /// Hex-encoded SHA-256 of the file contents.
+    #[active_type(LWW)]
+    pub content_hash: String,
+}
+
+// BlobChunk model - one slice of a blob's bytes; identical files share chunks via the content hash
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct BlobChunk {
+    #[active_type(LWW)]
+    pub hash: String,
+    pub seq: i64,
+    /// ID of the user who uploaded the chunk.
+    #[active_type(LWW)]
+    pub user: String,
+    /// Base64-encoded chunk bytes.
+    #[active_type(LWW)]
+    pub data: String,
+}
+
+// Pin model - a message pinned to the top of its room
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct Pin {
+    #[active_type(LWW)]
+    pub room: String,
+    #[active_type(LWW)]
+    pub message: String,
+    #[active_type(LWW)]
+    pub pinned_by: String,
+    /// Unix timestamp in milliseconds of the latest pin.
+    #[active_type(LWW)]
+    pub timestamp: i64,
+    #[active_type(LWW)]
+    pub deleted: bool,
+}
+
+// Reaction model - one emoji reaction by one user on one message
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct Reaction {
+    #[active_type(LWW)]
+    pub message: String,
+    #[active_type(LWW)]
+    pub user: String,
+    #[active_type(LWW)]
+    pub emoji: String,
+    #[active_type(LWW)]
+    pub deleted: bool,
+}
+
+// ReadCursor model - how far a user has read in a room (one per user and room)
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct ReadCursor {
+    #[active_type(LWW)]
+    pub user: String,
+    #[active_type(LWW)]
+    pub room: String,
+    #[active_type(LWW)]
+    pub last_read_timestamp: i64,
+    #[active_type(LWW)]
+    pub last_read_message: String,
+}
+
+// SearchQuery model - a full-text search request, answered by the server from its search index
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct SearchQuery {
+    #[active_type(LWW)]
+    pub user: String,
+    #[active_type(LWW)]
+    pub query: String,
+    /// Room ID to restrict the search to, or empty to search every room.
+    #[active_type(LWW)]
+    pub room: String,
+    pub timestamp: i64,
+    /// "pending" until the server has answered, then "done", and "expired" once the server has erased the
+    /// query and its results.
+    #[active_type(LWW)]
+    pub status: String,
+    /// JSON-encoded `search::SearchHit`s, best match first.
+    #[active_type(LWW)]
+    pub results: String,
+    /// Server time (Unix ms) the query was answered, 0 while pending.
+    #[active_type(LWW)]
+    pub answered_at: i64,
+}
+
+// Typing model - ephemeral "is typing" state, one per room and user, expired by the server
+#[derive(Model, Debug, Serialize, Deserialize)]
+pub struct Typing {
+    #[active_type(LWW)]
+    pub room: String,
+    #[active_type(LWW)]
+    pub user: String,
+    #[active_type(LWW)]
+    pub active: bool,
+    /// Unix timestamp in milliseconds after which the indicator is stale.
+    #[active_type(LWW)]
+    pub expires_at: i64,
 }
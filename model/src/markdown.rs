@@ -0,0 +1,710 @@
+//! Markdown subset used for message text.
+//!
+//! Supports bold, italic, inline code, fenced code blocks, lists, block quotes and links.
+//! The parser only produces a syntax tree of plain strings; raw HTML is never interpreted and
+//! renderers must emit every string as text. Links are limited to http(s) and mailto URLs.
+//!
+//! Inline parsing is a single pass with a stack of open delimiters, and both block quotes and open
+//! delimiters are capped in depth, so no message can make parsing slow or the tree deep.
+
+use std::collections::HashSet;
+
+/// Deepest nesting of block quotes; `>` markers beyond it are kept as text.
+const MAX_QUOTE_DEPTH: usize = 8;
+/// Most emphasis runs and brackets that can be waiting for a closer at once; more are kept as text.
+const MAX_OPEN_DELIMITERS: usize = 32;
+/// Longest URL recognized in a `[text](url)` link.
+const MAX_URL_CHARS: usize = 2048;
+
+/// A block-level element.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Block {
+    Paragraph(Vec<Inline>),
+    /// Fenced code block; `lang` is the info string after the opening fence, if any.
+    CodeBlock { lang: Option<String>, code: String },
+    /// `start` is the number of the first item for ordered lists.
+    List { ordered: bool, start: u64, items: Vec<Vec<Inline>> },
+    Quote(Vec<Block>),
+}
+
+/// An inline element within a paragraph, list item or link.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Inline {
+    Text(String),
+    Bold(Vec<Inline>),
+    Italic(Vec<Inline>),
+    Code(String),
+    Link { text: Vec<Inline>, url: String },
+    LineBreak,
+}
+
+/// Parse message text into blocks.
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines: Vec<&str> = text.lines().collect();
+    parse_blocks(&lines, 0)
+}
+
+/// Parse lines inside `depth` block quotes.
+fn parse_blocks(lines: &[&str], depth: usize) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim_start();
+
+        if trimmed.is_empty() {
+            i += 1;
+        } else if let Some(info) = trimmed.strip_prefix("```") {
+            let lang = Some(info.trim().to_string()).filter(|l| !l.is_empty());
+            let mut code = Vec::new();
+            i += 1;
+            while i < lines.len() && !lines[i].trim_start().starts_with("```") {
+                code.push(lines[i]);
+                i += 1;
+            }
+            // Skip the closing fence; an unclosed fence runs to the end of the message
+            i += 1;
+            blocks.push(Block::CodeBlock { lang, code: code.join("\n") });
+        } else if is_quote(line, depth) {
+            let mut quoted = Vec::new();
+            while i < lines.len() {
+                let Some(rest) = lines[i].trim_start().strip_prefix('>') else { break };
+                quoted.push(rest.strip_prefix(' ').unwrap_or(rest));
+                i += 1;
+            }
+            blocks.push(Block::Quote(parse_blocks(&quoted, depth + 1)));
+        } else if let Some(marker) = list_marker(line) {
+            let mut items: Vec<String> = Vec::new();
+            while i < lines.len() {
+                match list_marker(lines[i]) {
+                    Some(next) if next.ordered == marker.ordered => items.push(next.content.to_string()),
+                    Some(_) => break,
+                    // Indented lines continue the previous item
+                    None if !lines[i].trim().is_empty() && lines[i].starts_with([' ', '\t']) => {
+                        if let Some(last) = items.last_mut() {
+                            last.push('\n');
+                            last.push_str(lines[i].trim());
+                        }
+                    }
+                    None => break,
+                }
+                i += 1;
+            }
+            let items = items.iter().map(|item| parse_inlines(item)).collect();
+            blocks.push(Block::List { ordered: marker.ordered, start: marker.number, items });
+        } else {
+            let mut paragraph = vec![line];
+            i += 1;
+            while i < lines.len() && !starts_block(lines[i], depth) {
+                paragraph.push(lines[i]);
+                i += 1;
+            }
+            blocks.push(Block::Paragraph(parse_inlines(&paragraph.join("\n"))));
+        }
+    }
+    blocks
+}
+
+/// Whether a line ends the current paragraph.
+fn starts_block(line: &str, depth: usize) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.is_empty() || trimmed.starts_with("```") || is_quote(line, depth) || list_marker(line).is_some()
+}
+
+fn is_quote(line: &str, depth: usize) -> bool { depth < MAX_QUOTE_DEPTH && line.trim_start().starts_with('>') }
+
+struct ListMarker<'a> {
+    ordered: bool,
+    number: u64,
+    content: &'a str,
+}
+
+/// Recognize `- item`, `* item`, `+ item`, `1. item` and `1) item`.
+fn list_marker(line: &str) -> Option<ListMarker<'_>> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    for bullet in ["- ", "* ", "+ "] {
+        if let Some(content) = trimmed.strip_prefix(bullet) {
+            return Some(ListMarker { ordered: false, number: 1, content: content.trim_start() });
+        }
+    }
+    let digits = trimmed.chars().take_while(char::is_ascii_digit).count();
+    if digits == 0 || digits > 9 {
+        return None;
+    }
+    let rest = &trimmed[digits..];
+    let content = rest.strip_prefix(". ").or_else(|| rest.strip_prefix(") "))?;
+    Some(ListMarker { ordered: true, number: trimmed[..digits].parse().ok()?, content: content.trim_start() })
+}
+
+/// Parse inline formatting. Newlines become `LineBreak`s, since chat messages keep their line structure.
+pub fn parse_inlines(text: &str) -> Vec<Inline> {
+    let chars: Vec<char> = text.chars().collect();
+    InlineParser { chars: &chars, out: Vec::new(), open: Vec::new(), unclosed_code: HashSet::new() }.parse()
+}
+
+/// An emphasis run or link bracket waiting for its closer, with everything parsed since it opened.
+struct Open {
+    kind: Opener,
+    children: Vec<Inline>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Opener {
+    /// `len` (1 to 3) unmatched `*` or `_`.
+    Emphasis { delim: char, len: usize },
+    /// A `[`. It stops being able to start a link once a link forms inside it or a line break follows it.
+    Bracket { active: bool },
+}
+
+impl Opener {
+    fn literal(self) -> String {
+        match self {
+            Opener::Emphasis { delim, len } => delim.to_string().repeat(len),
+            Opener::Bracket { .. } => "[".to_string(),
+        }
+    }
+}
+
+/// A single left-to-right pass over the text. Openers wait on a stack; a closer matches the nearest
+/// opener of its kind, and any openers above that one turn back into text.
+struct InlineParser<'a> {
+    chars: &'a [char],
+    out: Vec<Inline>,
+    open: Vec<Open>,
+    /// Lengths of backtick runs already known to have no closing run further on.
+    unclosed_code: HashSet<usize>,
+}
+
+impl InlineParser<'_> {
+    fn parse(mut self) -> Vec<Inline> {
+        let chars = self.chars;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            match c {
+                '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                    self.push_char(chars[i + 1]);
+                    i += 2;
+                }
+                '\n' => {
+                    // Links don't span lines
+                    self.deactivate_brackets();
+                    self.push(Inline::LineBreak);
+                    i += 1;
+                }
+                '`' => {
+                    let run = count_run(chars, i, '`');
+                    match self.find_code_closer(i + run, run) {
+                        Some(close) => {
+                            let code: String = chars[i + run..close].iter().collect();
+                            // A single space padding each side lets code start or end with a backtick
+                            let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                                Some(inner) if !inner.trim().is_empty() => inner.to_string(),
+                                _ => code,
+                            };
+                            self.push(Inline::Code(code));
+                            i = close + run;
+                        }
+                        None => {
+                            self.push_text(&chars[i..i + run].iter().collect::<String>());
+                            i += run;
+                        }
+                    }
+                }
+                '*' | '_' => i = self.emphasis(i),
+                '[' => {
+                    if self.open.len() < MAX_OPEN_DELIMITERS {
+                        self.open.push(Open { kind: Opener::Bracket { active: true }, children: Vec::new() });
+                    } else {
+                        self.push_char(c);
+                    }
+                    i += 1;
+                }
+                ']' => i = self.close_bracket(i),
+                'h' if starts_word(chars, i) && (has_prefix(chars, i, "https://") || has_prefix(chars, i, "http://")) => {
+                    let in_brackets = self.open.iter().any(|open| matches!(open.kind, Opener::Bracket { .. }));
+                    let url_end = autolink_end(chars, i, in_brackets);
+                    let url: String = chars[i..url_end].iter().collect();
+                    self.push(Inline::Link { text: vec![Inline::Text(url.clone())], url });
+                    i = url_end;
+                }
+                _ => {
+                    self.push_char(c);
+                    i += 1;
+                }
+            }
+        }
+        while let Some(open) = self.open.pop() {
+            self.unwrap(open);
+        }
+        self.out
+    }
+
+    /// Handle the run of `*` or `_` at `start`, returning the index after it.
+    /// Openers must be followed by non-whitespace and closers preceded by non-whitespace, so `2 * 3 * 4` stays literal.
+    /// Underscores additionally must sit on word boundaries, so `snake_case_names` are left alone.
+    fn emphasis(&mut self, start: usize) -> usize {
+        let chars = self.chars;
+        let delim = chars[start];
+        let run = count_run(chars, start, delim);
+        let before = start.checked_sub(1).map(|j| chars[j]);
+        let after = chars.get(start + run).copied();
+        let can_open = after.is_some_and(|c| !c.is_whitespace()) && !(delim == '_' && before.is_some_and(char::is_alphanumeric));
+        let can_close = before.is_some_and(|c| !c.is_whitespace()) && !(delim == '_' && after.is_some_and(char::is_alphanumeric));
+
+        let mut remaining = run;
+        while can_close && remaining > 0 {
+            let Some(opener) = self.open.iter().rposition(|open| matches!(open.kind, Opener::Emphasis { delim: d, .. } if d == delim))
+            else {
+                break;
+            };
+            while self.open.len() > opener + 1 {
+                let unmatched = self.open.pop().unwrap();
+                self.unwrap(unmatched);
+            }
+            let open = self.open.last_mut().unwrap();
+            let Opener::Emphasis { len, .. } = &mut open.kind else { unreachable!("found an emphasis opener") };
+            // `***text***` is bold italic; otherwise a double delimiter is bold and a single one italic.
+            // What's left of a longer run on either side can still pair up, as in `***bold** italic*`.
+            let used = (*len).min(remaining).min(3);
+            *len -= used;
+            remaining -= used;
+            let inner = std::mem::take(&mut open.children);
+            let node = match used {
+                3 => Inline::Bold(vec![Inline::Italic(inner)]),
+                2 => Inline::Bold(inner),
+                _ => Inline::Italic(inner),
+            };
+            if *len == 0 {
+                self.open.pop();
+                self.push(node);
+            } else {
+                open.children.push(node);
+            }
+        }
+
+        if remaining > 0 {
+            if can_open && self.open.len() < MAX_OPEN_DELIMITERS {
+                let len = remaining.min(3);
+                self.push_text(&delim.to_string().repeat(remaining - len));
+                self.open.push(Open { kind: Opener::Emphasis { delim, len }, children: Vec::new() });
+            } else {
+                self.push_text(&delim.to_string().repeat(remaining));
+            }
+        }
+        start + run
+    }
+
+    /// Handle the `]` at `close`: finish a `[text](url)` link if one is there, otherwise keep the brackets
+    /// as text. Returns the index to continue from.
+    fn close_bracket(&mut self, close: usize) -> usize {
+        let Some(opener) = self.open.iter().rposition(|open| matches!(open.kind, Opener::Bracket { .. })) else {
+            self.push_char(']');
+            return close + 1;
+        };
+        // Emphasis opened inside the brackets can't close outside them
+        while self.open.len() > opener + 1 {
+            let unmatched = self.open.pop().unwrap();
+            self.unwrap(unmatched);
+        }
+        let bracket = self.open.pop().unwrap();
+
+        let destination = match bracket.kind {
+            Opener::Bracket { active: true } if !bracket.children.is_empty() => link_destination(self.chars, close + 1),
+            _ => None,
+        };
+        match destination {
+            Some((url, next)) => {
+                self.push(Inline::Link { text: strip_links(bracket.children), url });
+                // Links don't nest, so the brackets around this one are just text
+                self.deactivate_brackets();
+                next
+            }
+            None => {
+                self.unwrap(bracket);
+                self.push_char(']');
+                close + 1
+            }
+        }
+    }
+
+    /// Find a run of exactly `len` backticks at or after `from`.
+    fn find_code_closer(&mut self, from: usize, len: usize) -> Option<usize> {
+        // Once a run length has no closer, later runs of that length won't find one either
+        if self.unclosed_code.contains(&len) {
+            return None;
+        }
+        let close = find_backtick_run(self.chars, from, len);
+        if close.is_none() {
+            self.unclosed_code.insert(len);
+        }
+        close
+    }
+
+    fn deactivate_brackets(&mut self) {
+        for open in &mut self.open {
+            if let Opener::Bracket { active } = &mut open.kind {
+                *active = false;
+            }
+        }
+    }
+
+    /// Where parsed inlines go: into the innermost opener, or the output if nothing is open.
+    fn children(&mut self) -> &mut Vec<Inline> {
+        match self.open.last_mut() {
+            Some(open) => &mut open.children,
+            None => &mut self.out,
+        }
+    }
+
+    fn push(&mut self, inline: Inline) { push_inline(self.children(), inline) }
+
+    fn push_text(&mut self, text: &str) { push_inline(self.children(), Inline::Text(text.to_string())) }
+
+    fn push_char(&mut self, c: char) { self.push_text(c.encode_utf8(&mut [0; 4])) }
+
+    /// Give up on an opener that never closed: its delimiter becomes text, followed by what was parsed after it.
+    fn unwrap(&mut self, open: Open) {
+        self.push_text(&open.kind.literal());
+        for child in open.children {
+            self.push(child);
+        }
+    }
+}
+
+/// Append to a list of inlines, merging adjacent text.
+fn push_inline(inlines: &mut Vec<Inline>, inline: Inline) {
+    match (inlines.last_mut(), inline) {
+        (_, Inline::Text(text)) if text.is_empty() => {}
+        (Some(Inline::Text(last)), Inline::Text(text)) => last.push_str(&text),
+        (_, inline) => inlines.push(inline),
+    }
+}
+
+/// Link text can't hold another link, so bare URLs inside it keep only their text.
+fn strip_links(inlines: Vec<Inline>) -> Vec<Inline> {
+    let mut out = Vec::new();
+    for inline in inlines {
+        match inline {
+            Inline::Link { text, .. } => {
+                for inline in strip_links(text) {
+                    push_inline(&mut out, inline);
+                }
+            }
+            Inline::Bold(inner) => out.push(Inline::Bold(strip_links(inner))),
+            Inline::Italic(inner) => out.push(Inline::Italic(strip_links(inner))),
+            inline => push_inline(&mut out, inline),
+        }
+    }
+    out
+}
+
+fn count_run(chars: &[char], start: usize, c: char) -> usize { chars[start..].iter().take_while(|&&ch| ch == c).count() }
+
+/// Find a run of exactly `len` backticks at or after `from`.
+fn find_backtick_run(chars: &[char], from: usize, len: usize) -> Option<usize> {
+    let mut j = from;
+    while j < chars.len() {
+        if chars[j] == '`' {
+            let run = count_run(chars, j, '`');
+            if run == len {
+                return Some(j);
+            }
+            j += run;
+        } else {
+            j += 1;
+        }
+    }
+    None
+}
+
+fn has_prefix(chars: &[char], start: usize, prefix: &str) -> bool {
+    let len = prefix.chars().count();
+    start + len <= chars.len() && chars[start..start + len].iter().copied().eq(prefix.chars())
+}
+
+fn starts_word(chars: &[char], i: usize) -> bool { i == 0 || !chars[i - 1].is_alphanumeric() }
+
+/// Parse the `(url)` following a link's `]` at `start`, returning the URL and the index after the `)`.
+/// Parentheses inside the URL must balance, as in `(https://en.wikipedia.org/wiki/Rust_(programming_language))`.
+fn link_destination(chars: &[char], start: usize) -> Option<(String, usize)> {
+    if chars.get(start) != Some(&'(') {
+        return None;
+    }
+    let is_space = |j: usize| matches!(chars.get(j), Some(' ' | '\t'));
+    let mut j = start + 1;
+    while is_space(j) {
+        j += 1;
+    }
+    let url_start = j;
+    let mut depth = 0;
+    while let Some(&c) = chars.get(j) {
+        if c.is_whitespace() || j - url_start > MAX_URL_CHARS {
+            break;
+        }
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => break,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        j += 1;
+    }
+    let url: String = chars[url_start..j].iter().collect();
+    while is_space(j) {
+        j += 1;
+    }
+    if chars.get(j) != Some(&')') || !is_safe_url(&url) {
+        return None;
+    }
+    Some((url, j + 1))
+}
+
+/// Bare URLs end at whitespace, or at a `]` if they may be inside link text. Trailing punctuation belongs
+/// to the sentence, not the link, except a `)` that closes a `(` inside the URL.
+fn autolink_end(chars: &[char], start: usize, in_brackets: bool) -> usize {
+    let mut url_end = (start..chars.len()).find(|&j| chars[j].is_whitespace() || (in_brackets && chars[j] == ']')).unwrap_or(chars.len());
+    let mut unclosed = chars[start..url_end].iter().fold(0i64, |depth, &c| match c {
+        '(' => depth + 1,
+        ')' => depth - 1,
+        _ => depth,
+    });
+    while url_end > start {
+        match chars[url_end - 1] {
+            '.' | ',' | ';' | ':' | '!' | '?' | '\'' | '"' => {}
+            ')' if unclosed < 0 => unclosed += 1,
+            _ => break,
+        }
+        url_end -= 1;
+    }
+    url_end
+}
+
+/// Only web and mail links are rendered; anything else (`javascript:`, `data:`, ...) stays plain text.
+pub fn is_safe_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    (lower.starts_with("https://") || lower.starts_with("http://") || lower.starts_with("mailto:")) && !url.chars().any(char::is_whitespace)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline { Inline::Text(s.to_string()) }
+
+    fn link(label: &str, url: &str) -> Inline { Inline::Link { text: vec![text(label)], url: url.to_string() } }
+
+    fn paragraph(inlines: Vec<Inline>) -> Vec<Block> { vec![Block::Paragraph(inlines)] }
+
+    /// How deeply inlines nest, counting each level of formatting or link.
+    fn depth(inlines: &[Inline]) -> usize {
+        inlines
+            .iter()
+            .map(|inline| match inline {
+                Inline::Bold(inner) | Inline::Italic(inner) | Inline::Link { text: inner, .. } => 1 + depth(inner),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn quote_depth(blocks: &[Block]) -> usize {
+        blocks
+            .iter()
+            .map(|block| match block {
+                Block::Quote(inner) => 1 + quote_depth(inner),
+                _ => 0,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn emphasis() {
+        assert_eq!(parse_inlines("**bold**"), [Inline::Bold(vec![text("bold")])]);
+        assert_eq!(parse_inlines("__bold__"), [Inline::Bold(vec![text("bold")])]);
+        assert_eq!(
+            parse_inlines("*italic* and _italic_"),
+            [Inline::Italic(vec![text("italic")]), text(" and "), Inline::Italic(vec![text("italic")])]
+        );
+        assert_eq!(parse_inlines("***both***"), [Inline::Bold(vec![Inline::Italic(vec![text("both")])])]);
+        assert_eq!(parse_inlines("*a **b** c*"), [Inline::Italic(vec![text("a "), Inline::Bold(vec![text("b")]), text(" c")])]);
+        assert_eq!(parse_inlines("***bold** italic*"), [Inline::Italic(vec![Inline::Bold(vec![text("bold")]), text(" italic")])]);
+        assert_eq!(parse_inlines("a*b*c"), [text("a"), Inline::Italic(vec![text("b")]), text("c")]);
+    }
+
+    #[test]
+    fn delimiters_that_cannot_open_or_close_stay_literal() {
+        assert_eq!(parse_inlines("2 * 3 * 4"), [text("2 * 3 * 4")]);
+        assert_eq!(parse_inlines("snake_case_names"), [text("snake_case_names")]);
+        assert_eq!(parse_inlines("**unclosed"), [text("**unclosed")]);
+        assert_eq!(parse_inlines("*a **b** c"), [text("*a "), Inline::Bold(vec![text("b")]), text(" c")]);
+        assert_eq!(parse_inlines(r"\*not italic\*"), [text("*not italic*")]);
+    }
+
+    #[test]
+    fn inline_code() {
+        assert_eq!(parse_inlines("run `cargo *test*`"), [text("run "), Inline::Code("cargo *test*".to_string())]);
+        assert_eq!(parse_inlines("`` a`b ``"), [Inline::Code("a`b".to_string())]);
+        assert_eq!(parse_inlines("*a `b*` c*"), [Inline::Italic(vec![text("a "), Inline::Code("b*".to_string()), text(" c")])]);
+        assert_eq!(parse_inlines("`unclosed"), [text("`unclosed")]);
+    }
+
+    #[test]
+    fn links() {
+        assert_eq!(parse_inlines("[docs](https://example.com)"), [link("docs", "https://example.com")]);
+        assert_eq!(parse_inlines("[mail]( mailto:a@example.com )"), [link("mail", "mailto:a@example.com")]);
+        assert_eq!(
+            parse_inlines("[**bold** link](http://example.com)"),
+            [Inline::Link { text: vec![Inline::Bold(vec![text("bold")]), text(" link")], url: "http://example.com".to_string() }]
+        );
+        assert_eq!(parse_inlines("[a [b] c](https://example.com)"), [link("a [b] c", "https://example.com")]);
+        assert_eq!(parse_inlines("[](mailto:a@example.com)"), [text("[](mailto:a@example.com)")]);
+        assert_eq!(parse_inlines("[no url]"), [text("[no url]")]);
+        assert_eq!(
+            parse_inlines("[two\nlines](https://example.com)"),
+            [text("[two"), Inline::LineBreak, text("lines]("), link("https://example.com", "https://example.com"), text(")")]
+        );
+    }
+
+    #[test]
+    fn links_do_not_nest() {
+        assert_eq!(
+            parse_inlines("[a [b](https://b.example) c](https://a.example)"),
+            [text("[a "), link("b", "https://b.example"), text(" c]("), link("https://a.example", "https://a.example"), text(")")]
+        );
+        assert_eq!(
+            parse_inlines("[see https://example.com](https://example.org)"),
+            [link("see https://example.com", "https://example.org")]
+        );
+    }
+
+    #[test]
+    fn urls_may_contain_balanced_parentheses() {
+        let url = "https://en.wikipedia.org/wiki/Rust_(programming_language)";
+        assert_eq!(parse_inlines(&format!("[Rust]({})", url)), [link("Rust", url)]);
+        assert_eq!(parse_inlines(&format!("see {}.", url)), [text("see "), link(url, url), text(".")]);
+        assert_eq!(
+            parse_inlines("(see https://example.com)"),
+            [text("(see "), link("https://example.com", "https://example.com"), text(")")]
+        );
+    }
+
+    #[test]
+    fn unsafe_links_stay_text() {
+        assert_eq!(parse_inlines("[click](javascript:alert(1))"), [text("[click](javascript:alert(1))")]);
+        assert_eq!(parse_inlines("[click](JavaScript:alert(1))"), [text("[click](JavaScript:alert(1))")]);
+        assert_eq!(parse_inlines("[x](data:text/html,<b>hi</b>)"), [text("[x](data:text/html,<b>hi</b>)")]);
+        assert!(!is_safe_url("javascript:alert(1)"));
+        assert!(!is_safe_url("vbscript:msgbox"));
+        assert!(!is_safe_url("https://example.com/a b"));
+        assert!(is_safe_url("HTTPS://example.com"));
+    }
+
+    #[test]
+    fn bare_urls_become_links_without_trailing_punctuation() {
+        assert_eq!(
+            parse_inlines("go to https://example.com/a, now!"),
+            [text("go to "), link("https://example.com/a", "https://example.com/a"), text(", now!")]
+        );
+        assert_eq!(parse_inlines("nothttps://example.com"), [text("nothttps://example.com")]);
+    }
+
+    #[test]
+    fn raw_html_is_text() {
+        assert_eq!(parse("<script>alert(1)</script>"), paragraph(vec![text("<script>alert(1)</script>")]));
+        assert_eq!(
+            parse("<img src=x onerror=alert(1)> **hi**"),
+            paragraph(vec![text("<img src=x onerror=alert(1)> "), Inline::Bold(vec![text("hi")])])
+        );
+    }
+
+    #[test]
+    fn paragraphs_keep_line_breaks() {
+        assert_eq!(
+            parse("one\ntwo\n\nthree"),
+            vec![Block::Paragraph(vec![text("one"), Inline::LineBreak, text("two")]), Block::Paragraph(vec![text("three")])]
+        );
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            parse("```rust\nlet a = **1**;\n```\nafter"),
+            vec![
+                Block::CodeBlock { lang: Some("rust".to_string()), code: "let a = **1**;".to_string() },
+                Block::Paragraph(vec![text("after")])
+            ]
+        );
+        assert_eq!(parse("```\nunclosed\n<b>"), vec![Block::CodeBlock { lang: None, code: "unclosed\n<b>".to_string() }]);
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            parse("- one\n* two\n  continued\n+ three"),
+            vec![Block::List {
+                ordered: false,
+                start: 1,
+                items: vec![vec![text("one")], vec![text("two"), Inline::LineBreak, text("continued")], vec![text("three")]]
+            }]
+        );
+        assert_eq!(
+            parse("3. three\n4) four"),
+            vec![Block::List { ordered: true, start: 3, items: vec![vec![text("three")], vec![text("four")]] }]
+        );
+        assert_eq!(
+            parse("- a\n1. b"),
+            vec![
+                Block::List { ordered: false, start: 1, items: vec![vec![text("a")]] },
+                Block::List { ordered: true, start: 1, items: vec![vec![text("b")]] }
+            ]
+        );
+        assert_eq!(parse("1234567890. not a list"), paragraph(vec![text("1234567890. not a list")]));
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(
+            parse("> quoted\n> > nested\nafter"),
+            vec![
+                Block::Quote(vec![Block::Paragraph(vec![text("quoted")]), Block::Quote(vec![Block::Paragraph(vec![text("nested")])])]),
+                Block::Paragraph(vec![text("after")])
+            ]
+        );
+    }
+
+    #[test]
+    fn deeply_nested_quotes_are_capped() {
+        let blocks = parse(&format!("{}deep", ">".repeat(100_000)));
+        assert_eq!(quote_depth(&blocks), MAX_QUOTE_DEPTH);
+    }
+
+    #[test]
+    fn pathological_emphasis_is_linear_and_shallow() {
+        // Unclosed openers used to rescan the rest of the text each
+        let unclosed = "*a ".repeat(50_000);
+        assert_eq!(parse_inlines(&unclosed), [text(&unclosed)]);
+
+        let nested = format!("{}x{}", "*a ".repeat(10_000), " a*".repeat(10_000));
+        assert!(depth(&parse_inlines(&nested)) <= 2 * MAX_OPEN_DELIMITERS);
+
+        let closers = "a* b_ ".repeat(50_000);
+        assert_eq!(parse_inlines(&closers), [text(&closers)]);
+    }
+
+    #[test]
+    fn pathological_brackets_and_backticks() {
+        let brackets = "[".repeat(100_000);
+        assert_eq!(parse_inlines(&brackets), [text(&brackets)]);
+
+        let links = "[a](https://x".repeat(20_000);
+        assert_eq!(depth(&parse_inlines(&links)), 1);
+
+        let ticks: String = (1..300).map(|n| format!("{} ", "`".repeat(n))).collect();
+        assert_eq!(parse_inlines(&ticks), [text(&ticks)]);
+    }
+}
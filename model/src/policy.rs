@@ -0,0 +1,1349 @@
+//! Policy agent shared by the server and the browser client.
+//!
+//! Clients authenticate each request with an `IdentityProof` signed by their device key. The server
+//! verifies it against the user's registered public key and checks every event against the
+//! authenticated identity and its role: only a message's author may edit it, its author or a
+//! moderator may delete it, a message's `user` and `room` cannot change once it has been created,
+//! only a user may change their own `User` entity, only admins may change roles and bans, only the server
+//! records presence, and banned users may not write at all. Only the server records when a message arrived,
+//! was deleted or was purged, and purged messages never change again. Message creation can also be rate
+//! limited per user and per connection, and user registration per connection, where a connection is the
+//! address a client's WebSocket came from.
+//! Reactions, read cursors, typing indicators, revisions, attachments, blob chunks and searches belong to
+//! the user who created them, and events on collections without rules are only accepted from the server.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ankurah::{
+    ankql::ast::{ComparisonOperator, Expr, Identifier, Literal, Predicate},
+    core::{
+        node::{ContextData, NodeInner},
+        util::Iterable,
+    },
+    core::selection::filter::Filterable,
+    entity::{Entity, TemporaryEntity},
+    error::ValidationError,
+    policy::{AccessDenied, PolicyAgent},
+    proto::{self, CollectionId},
+    storage::StorageEngine,
+    EntityId, Model, Node, Value,
+};
+use async_trait::async_trait;
+
+use crate::{
+    identity::{DeviceKey, IdentityProof, SERVER_KEY},
+    role, Attachment, BlobChunk, Message, MessageRevision, Pin, Reaction, ReactionView, ReadCursor, Room, SearchQuery, Typing, User, UserView,
+};
+
+/// Who a context acts for.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Identity {
+    /// The server itself (sweepers, search, seeding). Never decoded from a client request.
+    System,
+    /// A client that has not created or loaded its user yet.
+    Anonymous { connection: Option<String> },
+    /// A client authenticated as the user with entity ID `id`.
+    User { id: String, connection: Option<String> },
+}
+
+impl Identity {
+    /// On the server, the address of the transport connection a client's request arrived on.
+    fn connection(&self) -> Option<&str> {
+        match self {
+            Identity::System => None,
+            Identity::Anonymous { connection } | Identity::User { connection, .. } => connection.as_deref(),
+        }
+    }
+}
+
+impl ContextData for Identity {}
+
+/// Context for server-side jobs.
+pub const SYSTEM_CONTEXT: Identity = Identity::System;
+/// Context for clients before their user is known.
+pub const ANONYMOUS_CONTEXT: Identity = Identity::Anonymous { connection: None };
+
+/// The collections the policy has rules for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Collection {
+    User,
+    Room,
+    Message,
+    MessageRevision,
+    Attachment,
+    BlobChunk,
+    Pin,
+    Reaction,
+    ReadCursor,
+    SearchQuery,
+    Typing,
+}
+
+impl Collection {
+    fn of(id: &CollectionId) -> Option<Self> {
+        [
+            (Self::User, User::collection()),
+            (Self::Room, Room::collection()),
+            (Self::Message, Message::collection()),
+            (Self::MessageRevision, MessageRevision::collection()),
+            (Self::Attachment, Attachment::collection()),
+            (Self::BlobChunk, BlobChunk::collection()),
+            (Self::Pin, Pin::collection()),
+            (Self::Reaction, Reaction::collection()),
+            (Self::ReadCursor, ReadCursor::collection()),
+            (Self::SearchQuery, SearchQuery::collection()),
+            (Self::Typing, Typing::collection()),
+        ]
+        .into_iter()
+        .find(|(_, collection)| collection == id)
+        .map(|(known, _)| known)
+    }
+}
+
+/// Proofs older than this are rejected unless the server has already verified them.
+const PROOF_MAX_AGE_MS: i64 = 10 * 60 * 1000;
+/// Clients sign a fresh proof well before the old one expires.
+const PROOF_REFRESH_MS: i64 = 5 * 60 * 1000;
+/// Tolerated clock difference between client and server.
+const CLOCK_SKEW_MS: i64 = 60 * 1000;
+/// Edits stamped with an `edited_at` older than this are rejected, so whatever follows edits by
+/// `edited_at` (the server's search index) only has to look this far back.
+pub const EDIT_MAX_AGE_MS: i64 = PROOF_MAX_AGE_MS;
+
+/// The device key a client signs its requests with.
+struct Signer {
+    user: String,
+    key: DeviceKey,
+    proof: Option<IdentityProof>,
+}
+
+impl Signer {
+    /// Reuse the current proof while it is fresh and for the same connection, otherwise sign a new one.
+    fn proof(&mut self, client_node: String, server_node: String) -> IdentityProof {
+        let now = chrono::Utc::now().timestamp_millis();
+        match &self.proof {
+            Some(proof)
+                if proof.client_node == client_node && proof.server_node == server_node && now - proof.issued_at < PROOF_REFRESH_MS =>
+            {
+                proof.clone()
+            }
+            _ => {
+                let proof = self.key.prove(&self.user, &client_node, &server_node, now);
+                self.proof = Some(proof.clone());
+                proof
+            }
+        }
+    }
+}
+
+/// A user's role and ban state, as the policy sees it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Standing {
+    pub role: String,
+    pub banned: bool,
+}
+
+impl Default for Standing {
+    fn default() -> Self { Self { role: role::MEMBER.to_string(), banned: false } }
+}
+
+impl Standing {
+    pub fn of(user: &UserView) -> Self { Self { role: user.role().unwrap_or_default(), banned: user.banned().unwrap_or(false) } }
+
+    fn is_admin(&self) -> bool { self.role == role::ADMIN }
+
+    fn can_moderate(&self) -> bool { self.role == role::ADMIN || self.role == role::MODERATOR }
+}
+
+/// Error text of a message rejected for exceeding the rate limit, so clients can recognize it.
+pub const RATE_LIMITED: &str = "rate limit exceeded: sending messages too quickly";
+/// Error text of a user registration rejected for exceeding the rate limit.
+pub const REGISTRATION_LIMITED: &str = "rate limit exceeded: creating users too quickly";
+
+/// Token bucket parameters: up to `burst` messages at once, refilling at `per_second` messages per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub per_second: f64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: i64,
+}
+
+impl Bucket {
+    fn refill(&mut self, limit: RateLimit, now: i64) {
+        let elapsed = (now - self.updated_at).max(0) as f64 / 1000.0;
+        self.tokens = (self.tokens + elapsed * limit.per_second).min(limit.burst as f64);
+        self.updated_at = now;
+    }
+}
+
+/// Once this many buckets exist, full ones (idle long enough to refill) are forgotten.
+const BUCKET_PRUNE_THRESHOLD: usize = 1024;
+
+/// Token buckets sharing one limit, by key.
+struct Limiter {
+    limit: RateLimit,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl Limiter {
+    fn new(limit: RateLimit) -> Arc<Self> { Arc::new(Self { limit, buckets: Mutex::new(HashMap::new()) }) }
+
+    /// Take one token from the bucket of every key, or from none of them if any is empty.
+    fn take(&self, keys: &[String]) -> bool {
+        let limit = self.limit;
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() > BUCKET_PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst as f64
+            });
+        }
+        for key in keys {
+            let bucket = buckets.entry(key.clone()).or_insert(Bucket { tokens: limit.burst as f64, updated_at: now });
+            bucket.refill(limit, now);
+            if bucket.tokens < 1.0 {
+                return false;
+            }
+        }
+        for key in keys {
+            if let Some(bucket) = buckets.get_mut(key) {
+                bucket.tokens -= 1.0;
+            }
+        }
+        true
+    }
+}
+
+/// Server side: the client nodes connected through the server's listener, the address (peer IP) each
+/// connected from, and the user each has authenticated as. Clients name their node ID when they open their
+/// WebSocket; requests from a node that isn't connected are rejected, and while it is, no other address can
+/// claim the same node ID. A user is online while any of their nodes is connected.
+#[derive(Clone, Default)]
+pub struct Connections {
+    open: Arc<Mutex<HashMap<String, Connection>>>,
+    listener: Arc<Mutex<Option<PresenceListener>>>,
+}
+
+type PresenceListener = Arc<dyn Fn(&str) + Send + Sync>;
+
+struct Connection {
+    address: String,
+    count: usize,
+    user: Option<String>,
+}
+
+impl Connections {
+    /// Register a connection from `address` for client node `node`, or return `None` if that node is
+    /// connected from another address. The node stays registered until every registration is dropped.
+    pub fn open(&self, node: &str, address: &str) -> Option<ConnectionRegistration> {
+        let mut open = self.open.lock().unwrap();
+        let connection = open.entry(node.to_string()).or_insert_with(|| Connection { address: address.to_string(), count: 0, user: None });
+        if connection.address != address {
+            return None;
+        }
+        connection.count += 1;
+        Some(ConnectionRegistration { connections: self.clone(), node: node.to_string() })
+    }
+
+    /// Call `listener` with a user's ID whenever they may have come online or gone offline; it should read
+    /// [`Connections::is_online`] rather than assume which.
+    pub fn on_presence_change(&self, listener: impl Fn(&str) + Send + Sync + 'static) {
+        *self.listener.lock().unwrap() = Some(Arc::new(listener));
+    }
+
+    /// Whether any connected node has authenticated as `user`.
+    pub fn is_online(&self, user: &str) -> bool { self.open.lock().unwrap().values().any(|c| c.user.as_deref() == Some(user)) }
+
+    fn address(&self, node: &str) -> Option<String> { self.open.lock().unwrap().get(node).map(|c| c.address.clone()) }
+
+    /// Record that `node` has authenticated as `user`.
+    fn identify(&self, node: &str, user: &str) {
+        let previous = {
+            let mut open = self.open.lock().unwrap();
+            let Some(connection) = open.get_mut(node) else { return };
+            if connection.user.as_deref() == Some(user) {
+                return;
+            }
+            connection.user.replace(user.to_string())
+        };
+        self.notify(previous.iter().map(String::as_str).chain([user]));
+    }
+
+    /// Tell the listener, outside the lock, about users whose presence may have changed.
+    fn notify<'a>(&self, users: impl IntoIterator<Item = &'a str>) {
+        let Some(listener) = self.listener.lock().unwrap().clone() else { return };
+        for user in users {
+            listener(user);
+        }
+    }
+}
+
+/// Keeps a client node registered with its address until dropped.
+pub struct ConnectionRegistration {
+    connections: Connections,
+    node: String,
+}
+
+impl Drop for ConnectionRegistration {
+    fn drop(&mut self) {
+        let user = {
+            let mut open = self.connections.open.lock().unwrap();
+            let Some(connection) = open.get_mut(&self.node) else { return };
+            connection.count -= 1;
+            if connection.count > 0 {
+                return;
+            }
+            open.remove(&self.node).and_then(|connection| connection.user)
+        };
+        self.connections.notify(user.as_deref());
+    }
+}
+
+/// The message, user and emoji of a reaction.
+type ReactionKey = (String, String, String);
+
+#[derive(Clone, Default)]
+pub struct ChatAgent {
+    /// Client side: the signed-in user's device key.
+    signer: Arc<Mutex<Option<Signer>>>,
+    /// Server side: verified proof signatures → (user ID, issued at).
+    verified: Arc<Mutex<HashMap<String, (String, i64)>>>,
+    /// Server side: the standing of authenticated users, kept current as roles and bans change.
+    standings: Arc<Mutex<HashMap<String, Standing>>>,
+    /// Server side: limit on message creation, applied per user and per connection.
+    message_limiter: Option<Arc<Limiter>>,
+    /// Server side: limit on user registration, applied per connection.
+    registration_limiter: Option<Arc<Limiter>>,
+    /// Server side: where connected clients connected from. Without it, requests are not tied to connections.
+    connections: Option<Connections>,
+    /// Server side: the live reaction for each (message, user, emoji), so nobody reacts twice with one emoji.
+    live_reactions: Option<Arc<Mutex<HashMap<ReactionKey, String>>>>,
+    /// Server side: receives the text each accepted edit replaced, to be stored as the message's edit history.
+    revision_recorder: Option<Arc<dyn Fn(MessageRevision) + Send + Sync>>,
+    /// Server side: how many events `check_event` has accepted and rejected, for metrics.
+    accepted_events: Arc<AtomicU64>,
+    rejected_events: Arc<AtomicU64>,
+}
+
+impl ChatAgent {
+    pub fn new() -> Self { Self::default() }
+
+    /// Sign requests made as `user` with this device key from now on.
+    pub fn set_signer(&self, user: String, key: DeviceKey) { *self.signer.lock().unwrap() = Some(Signer { user, key, proof: None }); }
+
+    pub fn with_message_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.message_limiter = Some(Limiter::new(limit));
+        self
+    }
+
+    pub fn with_registration_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.registration_limiter = Some(Limiter::new(limit));
+        self
+    }
+
+    /// Only accept requests from clients registered in `connections`, and attribute them to their address.
+    pub fn with_connections(mut self, connections: Connections) -> Self {
+        self.connections = Some(connections);
+        self
+    }
+
+    /// Accept at most one reaction that isn't deleted per message, user and emoji. Load the reactions already
+    /// stored with [`ChatAgent::index_reactions`]; the agent follows every reaction write it accepts from then on.
+    pub fn with_reaction_index(mut self) -> Self {
+        self.live_reactions = Some(Arc::new(Mutex::new(HashMap::new())));
+        self
+    }
+
+    /// Add stored reactions that aren't deleted to the index set up by [`ChatAgent::with_reaction_index`].
+    pub fn index_reactions(&self, reactions: &[ReactionView]) {
+        let Some(live) = &self.live_reactions else { return };
+        let mut live = live.lock().unwrap();
+        for reaction in reactions.iter().filter(|reaction| !reaction.deleted().unwrap_or(false)) {
+            let key = (reaction.message().unwrap_or_default(), reaction.user().unwrap_or_default(), reaction.emoji().unwrap_or_default());
+            live.entry(key).or_insert_with(|| reaction.id().to_base64());
+        }
+    }
+
+    /// Refuse a user's reaction that duplicates another live one, and keep the index current.
+    fn check_single_reaction<F: Fields>(&self, identity: &Identity, after: &F) -> Result<(), AccessDenied> {
+        let Some(live) = &self.live_reactions else { return Ok(()) };
+        let key = (
+            string_value(after, "message").unwrap_or_default(),
+            string_value(after, "user").unwrap_or_default(),
+            string_value(after, "emoji").unwrap_or_default(),
+        );
+        let id = after.id();
+        let mut live = live.lock().unwrap();
+        if bool_value(after, "deleted") {
+            if live.get(&key) == Some(&id) {
+                live.remove(&key);
+            }
+            return Ok(());
+        }
+        match live.get(&key) {
+            Some(other) if *other != id && *identity != Identity::System => {
+                Err(AccessDenied::ByPolicy("you already reacted to this message with this emoji"))
+            }
+            _ => {
+                live.insert(key, id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Hand the text replaced by every edit a user makes to `recorder`, as a revision by the message's author.
+    pub fn with_revision_recorder(mut self, recorder: impl Fn(MessageRevision) + Send + Sync + 'static) -> Self {
+        self.revision_recorder = Some(Arc::new(recorder));
+        self
+    }
+
+    /// Take one token from both the user's and the connection's bucket, or neither if either is empty.
+    fn take_message_token(&self, identity: &Identity) -> Result<(), AccessDenied> {
+        let (Some(limiter), Identity::User { id, .. }) = (&self.message_limiter, identity) else { return Ok(()) };
+        let mut keys = vec![format!("user:{}", id)];
+        keys.extend(identity.connection().map(|address| format!("connection:{}", address)));
+        match limiter.take(&keys) {
+            true => Ok(()),
+            false => Err(AccessDenied::ByPolicy(RATE_LIMITED)),
+        }
+    }
+
+    /// Take one token from the connection's registration bucket.
+    fn take_registration_token(&self, identity: &Identity) -> Result<(), AccessDenied> {
+        let (Some(limiter), Some(address)) = (&self.registration_limiter, identity.connection()) else { return Ok(()) };
+        match limiter.take(&[format!("connection:{}", address)]) {
+            true => Ok(()),
+            false => Err(AccessDenied::ByPolicy(REGISTRATION_LIMITED)),
+        }
+    }
+
+    /// Events accepted and rejected by this agent so far.
+    pub fn event_counts(&self) -> (u64, u64) {
+        (self.accepted_events.load(Ordering::Relaxed), self.rejected_events.load(Ordering::Relaxed))
+    }
+
+    fn check_write_event<F: Fields>(
+        &self,
+        identity: &Identity,
+        collection: &CollectionId,
+        entity_before: &F,
+        entity_after: &F,
+    ) -> Result<(), AccessDenied> {
+        // Banned users stay connected and can read, but every write is rejected
+        let standing = self.standing(identity);
+        if standing.banned {
+            return Err(AccessDenied::ByPolicy("banned users are read-only"));
+        }
+        match Collection::of(collection) {
+            Some(Collection::Message) => {
+                check_message_event(identity, &standing, entity_before, entity_after)?;
+                // Only new messages count towards the rate limit; edits and deletes don't
+                if string_value(entity_before, "user").is_none() {
+                    self.take_message_token(identity)?;
+                }
+                if let (Identity::User { .. }, Some(recorder)) = (identity, &self.revision_recorder) {
+                    replaced_revision(entity_before, entity_after).into_iter().for_each(|revision| recorder(revision));
+                }
+            }
+            Some(Collection::User) => {
+                check_user_event(identity, &standing, entity_before, entity_after)?;
+                if is_new_user(entity_before) {
+                    self.take_registration_token(identity)?;
+                }
+            }
+            Some(Collection::Reaction) => {
+                check_owned_event(identity, Collection::Reaction, entity_before, entity_after)?;
+                self.check_single_reaction(identity, entity_after)?;
+            }
+            Some(collection) => check_owned_event(identity, collection, entity_before, entity_after)?,
+            None if *identity == Identity::System => {}
+            None => return Err(AccessDenied::ByPolicy("writes to this collection are not allowed")),
+        }
+        Ok(())
+    }
+
+    /// Record a user's current role and ban state. The server calls this whenever a `User` changes, and clients
+    /// for their own user, so that their local checks agree with the server's.
+    pub fn set_standing(&self, user_id: String, standing: Standing) { self.standings.lock().unwrap().insert(user_id, standing); }
+
+    fn standing(&self, identity: &Identity) -> Standing {
+        match identity {
+            Identity::User { id, .. } => self.standings.lock().unwrap().get(id).cloned().unwrap_or_default(),
+            _ => Standing::default(),
+        }
+    }
+
+    /// Check a proof presented on `request`, returning the authenticated user ID.
+    async fn verify_proof<SE: StorageEngine>(
+        &self,
+        node: &Node<SE, Self>,
+        proof: &IdentityProof,
+        request: &proto::NodeRequest,
+    ) -> Result<String, ValidationError> {
+        if proof.client_node != request.from.to_base64() || proof.server_node != node.id.to_base64() {
+            return Err(ValidationError::ValidationFailed("identity proof was made for another connection".into()));
+        }
+
+        let now = chrono::Utc::now().timestamp_millis();
+        let cached = {
+            let mut verified = self.verified.lock().unwrap();
+            verified.retain(|_, (_, issued_at)| now - *issued_at < PROOF_MAX_AGE_MS);
+            verified.get(&proof.signature).map(|(user, _)| user.clone())
+        };
+        if let Some(user) = cached {
+            self.identified(&proof.client_node, &user);
+            return Ok(user);
+        }
+        if now - proof.issued_at >= PROOF_MAX_AGE_MS || proof.issued_at - now > CLOCK_SKEW_MS {
+            return Err(ValidationError::ValidationFailed("identity proof has expired".into()));
+        }
+
+        let user_id = EntityId::from_base64(&proof.user).map_err(|_| ValidationError::ValidationFailed("malformed user id".into()))?;
+        // Read the stored state directly: contexts are only available on nodes with a `'static` storage engine
+        let state = node
+            .collections
+            .get(&User::collection())
+            .await
+            .map_err(|_| ValidationError::ValidationFailed("failed to read users".into()))?
+            .get_state(user_id)
+            .await
+            .map_err(|_| ValidationError::ValidationFailed("unknown user".into()))?;
+        let user = TemporaryEntity::new(user_id, User::collection(), &state.payload.state)
+            .map_err(|_| ValidationError::ValidationFailed("failed to read user".into()))?;
+        if !proof.verify(&string_value(&user, "public_key").unwrap_or_default()) {
+            return Err(ValidationError::ValidationFailed("invalid identity signature".into()));
+        }
+
+        let standing = Standing { role: string_value(&user, "role").unwrap_or_default(), banned: bool_value(&user, "banned") };
+        self.set_standing(proof.user.clone(), standing);
+        self.verified.lock().unwrap().insert(proof.signature.clone(), (proof.user.clone(), proof.issued_at));
+        self.identified(&proof.client_node, &proof.user);
+        Ok(proof.user.clone())
+    }
+
+    /// Tie a connected client node to the user it has proven to be, for presence.
+    fn identified(&self, node: &str, user: &str) {
+        if let Some(connections) = &self.connections {
+            connections.identify(node, user);
+        }
+    }
+}
+
+/// The fields of an entity as the checks read them. Implemented for entities, and for plain maps in tests.
+trait Fields {
+    fn value(&self, field: &str) -> Option<Value>;
+    fn id(&self) -> String;
+}
+
+impl Fields for Entity {
+    fn value(&self, field: &str) -> Option<Value> { Filterable::value(self, field) }
+
+    fn id(&self) -> String { Entity::id(self).to_base64() }
+}
+
+impl Fields for TemporaryEntity {
+    fn value(&self, field: &str) -> Option<Value> { Filterable::value(self, field) }
+
+    fn id(&self) -> String { self.id.to_base64() }
+}
+
+/// An entity that may not exist yet: `None` has no fields.
+impl<F: Fields> Fields for Option<&F> {
+    fn value(&self, field: &str) -> Option<Value> { self.and_then(|entity| entity.value(field)) }
+
+    fn id(&self) -> String { self.map(Fields::id).unwrap_or_default() }
+}
+
+fn string_value(entity: &impl Fields, field: &str) -> Option<String> {
+    match entity.value(field) {
+        Some(Value::String(s)) if !s.is_empty() => Some(s),
+        _ => None,
+    }
+}
+
+fn bool_value(entity: &impl Fields, field: &str) -> bool { matches!(entity.value(field), Some(Value::Bool(true))) }
+
+fn i64_value(entity: &impl Fields, field: &str) -> i64 {
+    match entity.value(field) {
+        Some(Value::I64(value)) => value,
+        _ => 0,
+    }
+}
+
+/// Enforce authorship and immutability of `user` and `room` on message events.
+/// Moderators may also delete other people's messages, but not rewrite them.
+fn check_message_event<F: Fields>(identity: &Identity, standing: &Standing, before: &F, after: &F) -> Result<(), AccessDenied> {
+    let user_id = match identity {
+        Identity::System => return Ok(()),
+        Identity::Anonymous { .. } => return Err(AccessDenied::ByPolicy("sign in before writing messages")),
+        Identity::User { id, .. } => id,
+    };
+
+    // Arrival and deletion times and purges are recorded by the server, and purged messages stay erased
+    if bool_value(before, "purged") {
+        return Err(AccessDenied::ByPolicy("purged messages cannot change"));
+    }
+    if i64_value(after, "deleted_at") != i64_value(before, "deleted_at") || bool_value(after, "purged") {
+        return Err(AccessDenied::ByPolicy("only the server records deletion and purge"));
+    }
+    if i64_value(after, "received_at") != i64_value(before, "received_at") {
+        return Err(AccessDenied::ByPolicy("only the server records when a message arrived"));
+    }
+    if string_value(after, "server_id") != string_value(before, "server_id") {
+        return Err(AccessDenied::ByPolicy("only the server may set a server ID"));
+    }
+    // Deletes are final, and edit times keep to the server's clock: the search index relies on both
+    if bool_value(before, "deleted") && !bool_value(after, "deleted") {
+        return Err(AccessDenied::ByPolicy("deleted messages cannot be restored"));
+    }
+    let edited_at = i64_value(after, "edited_at");
+    if edited_at != i64_value(before, "edited_at") {
+        let now = chrono::Utc::now().timestamp_millis();
+        if now - edited_at >= EDIT_MAX_AGE_MS || edited_at - now > CLOCK_SKEW_MS {
+            return Err(AccessDenied::ByPolicy("edit times must be close to the server's clock"));
+        }
+    }
+
+    match string_value(before, "user") {
+        // A new message must be attributed to its sender
+        None => {
+            if string_value(after, "user").as_deref() != Some(user_id.as_str()) {
+                return Err(AccessDenied::ByPolicy("messages must be sent as the signed-in user"));
+            }
+        }
+        Some(author) => {
+            if string_value(after, "user").as_ref() != Some(&author) || string_value(after, "room") != string_value(before, "room") {
+                return Err(AccessDenied::ByPolicy("a message's user and room cannot change"));
+            }
+            if author != *user_id {
+                // Moderators may set `deleted` and nothing else
+                let removes_only = bool_value(after, "deleted")
+                    && check_unchanged(before, after, &["thread", "text", "timestamp", "edited_at", "mentions"]).is_ok();
+                if !(standing.can_moderate() && removes_only) {
+                    return Err(AccessDenied::ByPolicy("only the author may edit or delete a message"));
+                }
+            }
+            // A rewrite is always marked as an edit, which is also when its previous text is recorded
+            if after.value("text") != before.value("text") && i64_value(after, "edited_at") <= i64_value(before, "edited_at") {
+                return Err(AccessDenied::ByPolicy("edits must move the edit time forward"));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The revision holding the text an edit to an existing message replaced, if the event changes its text.
+fn replaced_revision<F: Fields>(before: &F, after: &F) -> Option<MessageRevision> {
+    let editor = string_value(before, "user")?;
+    if after.value("text") == before.value("text") {
+        return None;
+    }
+    Some(MessageRevision {
+        message: after.id(),
+        editor,
+        text: string_value(before, "text").unwrap_or_default(),
+        timestamp: i64_value(after, "edited_at"),
+    })
+}
+
+fn is_new_user(before: &impl Fields) -> bool { before.value("display_name").is_none() && before.value("public_key").is_none() }
+
+/// Anyone may register a user with a public key, as a member; afterwards only that user may change it,
+/// never its key, and only admins may change roles and bans. Users without a key (created before keys
+/// existed) can never be claimed, and only the server creates users with `SERVER_KEY` or sets `server_id`.
+fn check_user_event<F: Fields>(identity: &Identity, standing: &Standing, before: &F, after: &F) -> Result<(), AccessDenied> {
+    // Presence follows the user's connections to the server
+    let changes_presence =
+        bool_value(after, "online") != bool_value(before, "online") || i64_value(after, "last_seen") != i64_value(before, "last_seen");
+    if changes_presence && *identity != Identity::System {
+        return Err(AccessDenied::ByPolicy("only the server records presence"));
+    }
+    if string_value(after, "server_id") != string_value(before, "server_id") && *identity != Identity::System {
+        return Err(AccessDenied::ByPolicy("only the server may set a server ID"));
+    }
+    if is_new_user(before) {
+        if *identity == Identity::System {
+            return Ok(());
+        }
+        match string_value(after, "public_key").as_deref() {
+            None => return Err(AccessDenied::ByPolicy("users must register a public key")),
+            Some(SERVER_KEY) => return Err(AccessDenied::ByPolicy("only the server may create server-owned users")),
+            Some(_) => {}
+        }
+        if string_value(after, "role").is_some_and(|r| r != role::MEMBER) || bool_value(after, "banned") {
+            return Err(AccessDenied::ByPolicy("new users must be unbanned members"));
+        }
+        return Ok(());
+    }
+    if string_value(after, "public_key") != string_value(before, "public_key") {
+        return Err(AccessDenied::ByPolicy("a user's public key cannot change"));
+    }
+
+    let changes_standing =
+        string_value(after, "role") != string_value(before, "role") || bool_value(after, "banned") != bool_value(before, "banned");
+    match identity {
+        Identity::System => Ok(()),
+        Identity::User { .. } if standing.is_admin() => Ok(()),
+        _ if changes_standing => Err(AccessDenied::ByPolicy("only admins may change roles and bans")),
+        Identity::User { id, .. } if *id == after.id() => Ok(()),
+        _ => Err(AccessDenied::ByPolicy("only a user may change their own profile")),
+    }
+}
+
+/// Rooms are shared by every signed-in user. Pins too, but each pin or unpin is recorded as made by the
+/// signed-in user. Everything else belongs to the user in its owner field: it must be created as the
+/// signed-in user and only they may change it, without moving it to another message, room or emoji.
+/// Attachments and blob chunks never change once created, and searches only change when the server answers
+/// them. Edit history is recorded by the server alone.
+fn check_owned_event<F: Fields>(identity: &Identity, collection: Collection, before: &F, after: &F) -> Result<(), AccessDenied> {
+    let user_id = match identity {
+        Identity::System => return Ok(()),
+        Identity::Anonymous { .. } => return Err(AccessDenied::ByPolicy("sign in before writing")),
+        Identity::User { id, .. } => id.as_str(),
+    };
+
+    match collection {
+        Collection::Room => Ok(()),
+        Collection::Pin => {
+            if string_value(after, "pinned_by").as_deref() != Some(user_id) {
+                return Err(AccessDenied::ByPolicy("pins must be changed as the signed-in user"));
+            }
+            match string_value(before, "message") {
+                None => Ok(()),
+                Some(_) => check_unchanged(before, after, &["room", "message"]),
+            }
+        }
+        Collection::Reaction => match check_owner(user_id, before, after, "user")? {
+            Write::Create => Ok(()),
+            Write::Update => check_unchanged(before, after, &["message", "user", "emoji"]),
+        },
+        Collection::ReadCursor | Collection::Typing => match check_owner(user_id, before, after, "user")? {
+            Write::Create => Ok(()),
+            Write::Update => check_unchanged(before, after, &["user", "room"]),
+        },
+        Collection::MessageRevision => Err(AccessDenied::ByPolicy("only the server records edit history")),
+        Collection::Attachment | Collection::BlobChunk => match check_owner(user_id, before, after, "user")? {
+            Write::Create => Ok(()),
+            Write::Update => Err(AccessDenied::ByPolicy("this record cannot change once created")),
+        },
+        Collection::SearchQuery => match check_owner(user_id, before, after, "user")? {
+            Write::Create => {
+                if string_value(after, "status").as_deref() != Some("pending")
+                    || string_value(after, "results").is_some()
+                    || i64_value(after, "answered_at") != 0
+                {
+                    return Err(AccessDenied::ByPolicy("searches must start out pending"));
+                }
+                Ok(())
+            }
+            Write::Update => Err(AccessDenied::ByPolicy("only the server answers searches")),
+        },
+        Collection::Message | Collection::User => unreachable!("messages and users have their own checks"),
+    }
+}
+
+/// Only the user who sent a search query may read it.
+fn check_search_query_read(identity: &Identity, query: &impl Fields) -> Result<(), AccessDenied> {
+    match identity {
+        Identity::System => Ok(()),
+        Identity::User { id, .. } if string_value(query, "user").as_deref() == Some(id.as_str()) => Ok(()),
+        _ => Err(AccessDenied::ByPolicy("search queries are private to the user who sent them")),
+    }
+}
+
+/// Whether an event creates its entity or changes an existing one.
+enum Write {
+    Create,
+    Update,
+}
+
+/// Check that an entity is created as, or already belongs to, the signed-in user named in its `owner` field.
+fn check_owner<F: Fields>(user_id: &str, before: &F, after: &F, owner: &str) -> Result<Write, AccessDenied> {
+    match string_value(before, owner) {
+        None if string_value(after, owner).as_deref() == Some(user_id) => Ok(Write::Create),
+        None => Err(AccessDenied::ByPolicy("records must be created as the signed-in user")),
+        Some(current) if current == user_id => Ok(Write::Update),
+        Some(_) => Err(AccessDenied::ByPolicy("only the owner may change this record")),
+    }
+}
+
+fn check_unchanged<F: Fields>(before: &F, after: &F, fields: &[&str]) -> Result<(), AccessDenied> {
+    if fields.iter().any(|field| before.value(field) != after.value(field)) {
+        return Err(AccessDenied::ByPolicy("these fields cannot change once set"));
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl PolicyAgent for ChatAgent {
+    type ContextData = Identity;
+
+    /// Attach a signed identity proof for the signed-in user. Anonymous and system contexts send no auth data.
+    fn sign_request<SE: StorageEngine, C>(
+        &self,
+        node: &NodeInner<SE, Self>,
+        cdata: &C,
+        request: &proto::NodeRequest,
+    ) -> Result<Vec<proto::AuthData>, AccessDenied>
+    where
+        C: Iterable<Self::ContextData>,
+    {
+        let mut signer = self.signer.lock().unwrap();
+        let Some(signer) = signer.as_mut() else { return Ok(Vec::new()) };
+
+        let mut auth = Vec::new();
+        for identity in cdata.iterable() {
+            if let Identity::User { id, .. } = identity {
+                if *id != signer.user {
+                    return Err(AccessDenied::ByPolicy("no device key for this user"));
+                }
+                let proof = signer.proof(node.id.to_base64(), request.to.to_base64());
+                let bytes = serde_json::to_vec(&proof).map_err(|_| AccessDenied::ByPolicy("failed to encode identity proof"))?;
+                auth.push(proto::AuthData(bytes));
+            }
+        }
+        Ok(auth)
+    }
+
+    /// Verify the identity proofs on a request. A request without any is anonymous. On the server, the
+    /// request must come from a registered connection, whose address the identities carry.
+    async fn check_request<SE: StorageEngine, A>(
+        &self,
+        node: &Node<SE, Self>,
+        auth: &A,
+        request: &proto::NodeRequest,
+    ) -> Result<Vec<Self::ContextData>, ValidationError>
+    where
+        A: Iterable<proto::AuthData> + Send + Sync,
+    {
+        let connection = match &self.connections {
+            Some(connections) => Some(
+                connections
+                    .address(&request.from.to_base64())
+                    .ok_or_else(|| ValidationError::ValidationFailed("client is not connected through the server's listener".into()))?,
+            ),
+            None => None,
+        };
+
+        let proofs = auth
+            .iterable()
+            .map(|data| serde_json::from_slice(&data.0))
+            .collect::<Result<Vec<IdentityProof>, _>>()
+            .map_err(|_| ValidationError::ValidationFailed("malformed identity proof".into()))?;
+        let mut identities = Vec::new();
+        for proof in proofs {
+            let id = self.verify_proof(node, &proof, request).await?;
+            identities.push(Identity::User { id, connection: connection.clone() });
+        }
+        if identities.is_empty() {
+            identities.push(Identity::Anonymous { connection });
+        }
+        Ok(identities)
+    }
+
+    fn check_event<SE: StorageEngine>(
+        &self,
+        _node: &Node<SE, Self>,
+        cdata: &Self::ContextData,
+        entity_before: &Entity,
+        entity_after: &Entity,
+        _event: &proto::Event,
+    ) -> Result<Option<proto::Attestation>, AccessDenied> {
+        // The event that creates an entity is checked with the created entity as both before and after
+        let before = (entity_before != entity_after).then_some(entity_before);
+        let result = self.check_write_event(cdata, entity_after.collection(), &before, &Some(entity_after));
+        let counter = if result.is_ok() { &self.accepted_events } else { &self.rejected_events };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result.map(|()| None)
+    }
+
+    fn validate_received_event<SE: StorageEngine>(
+        &self,
+        _node: &Node<SE, Self>,
+        _received_from_node: &proto::EntityId,
+        _event: &proto::Attested<proto::Event>,
+    ) -> Result<(), AccessDenied> {
+        Ok(())
+    }
+
+    fn attest_state<SE: StorageEngine>(&self, _node: &Node<SE, Self>, _state: &proto::EntityState) -> Option<proto::Attestation> {
+        None
+    }
+
+    fn validate_received_state<SE: StorageEngine>(
+        &self,
+        _node: &Node<SE, Self>,
+        _received_from_node: &proto::EntityId,
+        _state: &proto::Attested<proto::EntityState>,
+    ) -> Result<(), AccessDenied> {
+        Ok(())
+    }
+
+    fn can_access_collection<C>(&self, _cdata: &C, _collection: &CollectionId) -> Result<(), AccessDenied>
+    where
+        C: Iterable<Self::ContextData>,
+    {
+        Ok(())
+    }
+
+    /// Search queries carry what their owner searched for and excerpts of the matches, so queries for
+    /// them only ever match the owner's own. A context acting for several identities sees what any of them may.
+    fn filter_predicate<C>(&self, cdata: &C, collection: &CollectionId, predicate: Predicate) -> Result<Predicate, AccessDenied>
+    where
+        C: Iterable<Self::ContextData>,
+    {
+        if Collection::of(collection) != Some(Collection::SearchQuery) {
+            return Ok(predicate);
+        }
+        let mut owners = Vec::new();
+        for identity in cdata.iterable() {
+            match identity {
+                Identity::System => return Ok(predicate),
+                Identity::Anonymous { .. } => {}
+                Identity::User { id, .. } => owners.push(Predicate::Comparison {
+                    left: Box::new(Expr::Identifier(Identifier::Property("user".to_string()))),
+                    operator: ComparisonOperator::Equal,
+                    right: Box::new(Expr::Literal(Literal::String(id.clone()))),
+                }),
+            }
+        }
+        match owners.into_iter().reduce(|a, b| Predicate::Or(Box::new(a), Box::new(b))) {
+            Some(owners) => Ok(Predicate::And(Box::new(predicate), Box::new(owners))),
+            None => Ok(Predicate::False),
+        }
+    }
+
+    fn check_read<C>(&self, cdata: &C, id: &proto::EntityId, collection: &CollectionId, state: &proto::State) -> Result<(), AccessDenied>
+    where
+        C: Iterable<Self::ContextData>,
+    {
+        if Collection::of(collection) != Some(Collection::SearchQuery) {
+            return Ok(());
+        }
+        let entity =
+            TemporaryEntity::new(*id, collection.clone(), state).map_err(|_| AccessDenied::ByPolicy("failed to read search query"))?;
+        let mut result = Err(AccessDenied::ByPolicy("search queries are private to the user who sent them"));
+        for identity in cdata.iterable() {
+            result = result.or_else(|_| check_search_query_read(identity, &entity));
+        }
+        result
+    }
+
+    fn check_read_event<C>(&self, _cdata: &C, _event: &proto::Attested<proto::Event>) -> Result<(), AccessDenied>
+    where
+        C: Iterable<Self::ContextData>,
+    {
+        Ok(())
+    }
+
+    /// Local writes are checked again on the server in `check_event`; this only stops anonymous contexts early.
+    fn check_write(&self, cdata: &Self::ContextData, entity: &Entity, _event: Option<&proto::Event>) -> Result<(), AccessDenied> {
+        if matches!(cdata, Identity::Anonymous { .. }) && Collection::of(entity.collection()) == Some(Collection::Message) {
+            return Err(AccessDenied::ByPolicy("sign in before writing messages"));
+        }
+        Ok(())
+    }
+
+    fn validate_causal_assertion<SE: StorageEngine>(
+        &self,
+        _node: &Node<SE, Self>,
+        _peer_id: &proto::EntityId,
+        _head_relation: &proto::CausalAssertion,
+    ) -> Result<(), AccessDenied> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: &str = "alice";
+    const BOB: &str = "bob";
+
+    /// Field values standing in for an entity.
+    #[derive(Clone, Default)]
+    struct Snapshot {
+        id: String,
+        fields: HashMap<&'static str, Value>,
+    }
+
+    impl Fields for Snapshot {
+        fn value(&self, field: &str) -> Option<Value> { self.fields.get(field).cloned() }
+
+        fn id(&self) -> String { self.id.clone() }
+    }
+
+    impl Snapshot {
+        fn new(fields: &[(&'static str, Value)]) -> Self { Self { id: "entity".to_string(), fields: fields.iter().cloned().collect() } }
+
+        fn with(&self, field: &'static str, value: Value) -> Self {
+            let mut changed = self.clone();
+            changed.fields.insert(field, value);
+            changed
+        }
+    }
+
+    fn text(value: &str) -> Value { Value::String(value.to_string()) }
+
+    fn user(id: &str) -> Identity { Identity::User { id: id.to_string(), connection: None } }
+
+    fn allowed(identity: &Identity, collection: CollectionId, before: &Snapshot, after: &Snapshot) -> bool {
+        ChatAgent::new().check_write_event(identity, &collection, before, after).is_ok()
+    }
+
+    /// Creating as the owner is allowed; creating for someone else or anonymously is not.
+    fn assert_created_by_owner(collection: CollectionId, owner: &'static str, entity: &Snapshot) {
+        let new = Snapshot::default();
+        assert!(allowed(&user(ALICE), collection.clone(), &new, &entity.with(owner, text(ALICE))));
+        assert!(!allowed(&user(ALICE), collection.clone(), &new, &entity.with(owner, text(BOB))));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, collection, &new, &entity.with(owner, text(ALICE))));
+    }
+
+    #[test]
+    fn users_register_with_a_key_that_never_changes() {
+        let new = Snapshot::default();
+        let registered = Snapshot::new(&[("display_name", text("Alice")), ("public_key", text("key")), ("role", text(role::MEMBER))]);
+        assert!(allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered.with("public_key", text(""))));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered.with("role", text(role::ADMIN))));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered.with("public_key", text(SERVER_KEY))));
+        assert!(allowed(&Identity::System, User::collection(), &new, &registered.with("public_key", text(SERVER_KEY))));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered.with("server_id", text("welcome-bot"))));
+        assert!(allowed(&ANONYMOUS_CONTEXT, User::collection(), &new, &registered.with("server_id", text(""))));
+        let owned = registered.with("public_key", text(SERVER_KEY)).with("server_id", text("welcome-bot"));
+        assert!(allowed(&Identity::System, User::collection(), &new, &owned));
+
+        let alice = Snapshot { id: ALICE.to_string(), ..registered };
+        assert!(allowed(&user(ALICE), User::collection(), &alice, &alice.with("display_name", text("Alicia"))));
+        assert!(!allowed(&user(BOB), User::collection(), &alice, &alice.with("display_name", text("Alicia"))));
+        assert!(!allowed(&user(ALICE), User::collection(), &alice, &alice.with("public_key", text("other"))));
+        assert!(!allowed(&user(ALICE), User::collection(), &alice, &alice.with("role", text(role::ADMIN))));
+        assert!(!allowed(&user(ALICE), User::collection(), &alice, &alice.with("server_id", text("welcome-bot"))));
+    }
+
+    #[test]
+    fn keyless_users_cannot_be_claimed() {
+        let keyless = Snapshot { id: BOB.to_string(), ..Snapshot::new(&[("display_name", text("Bob")), ("public_key", text(""))]) };
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &keyless, &keyless.with("public_key", text("key"))));
+        assert!(!allowed(&user(BOB), User::collection(), &keyless, &keyless.with("public_key", text("key"))));
+
+        // Users from before keys existed have no public_key field at all
+        let legacy = Snapshot { id: BOB.to_string(), ..Snapshot::new(&[("display_name", text("Bob"))]) };
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &legacy, &legacy.with("public_key", text("key"))));
+    }
+
+    #[test]
+    fn rooms_are_shared_by_signed_in_users() {
+        let room = Snapshot::new(&[("name", text("general")), ("topic", text(""))]);
+        assert!(allowed(&user(ALICE), Room::collection(), &Snapshot::default(), &room));
+        assert!(allowed(&user(BOB), Room::collection(), &room, &room.with("topic", text("Anything goes"))));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, Room::collection(), &room, &room.with("topic", text("spam"))));
+    }
+
+    #[test]
+    fn messages_are_sent_and_edited_by_their_author() {
+        let message = Snapshot::new(&[("user", text(ALICE)), ("room", text("general")), ("text", text("hi"))]);
+        assert!(allowed(&user(ALICE), Message::collection(), &Snapshot::default(), &message));
+        assert!(!allowed(&user(BOB), Message::collection(), &Snapshot::default(), &message));
+        let now = chrono::Utc::now().timestamp_millis();
+        let edited = message.with("text", text("hello")).with("edited_at", Value::I64(now));
+        assert!(allowed(&user(ALICE), Message::collection(), &message, &edited));
+        assert!(!allowed(&user(BOB), Message::collection(), &message, &edited));
+        // Rewrites that aren't marked as edits are refused
+        assert!(!allowed(&user(ALICE), Message::collection(), &message, &message.with("text", text("hello"))));
+        assert!(!allowed(&user(ALICE), Message::collection(), &edited, &edited.with("text", text("hey"))));
+        assert!(!allowed(&user(ALICE), Message::collection(), &message, &message.with("room", text("random"))));
+        // Edit times can't be backdated or run ahead of the server's clock
+        let backdated = message.with("text", text("hello")).with("edited_at", Value::I64(now - EDIT_MAX_AGE_MS));
+        assert!(!allowed(&user(ALICE), Message::collection(), &message, &backdated));
+        let ahead = message.with("text", text("hello")).with("edited_at", Value::I64(now + 2 * CLOCK_SKEW_MS));
+        assert!(!allowed(&user(ALICE), Message::collection(), &message, &ahead));
+        // Deletes are final
+        let deleted = message.with("deleted", Value::Bool(true));
+        assert!(allowed(&user(ALICE), Message::collection(), &message, &deleted));
+        assert!(!allowed(&user(ALICE), Message::collection(), &deleted, &message));
+        assert!(allowed(&Identity::System, Message::collection(), &deleted, &message));
+
+        // The server stamps arrival, so senders can't backdate a message past retention
+        assert!(!allowed(&user(ALICE), Message::collection(), &Snapshot::default(), &message.with("received_at", Value::I64(1))));
+        let received = message.with("received_at", Value::I64(1_000));
+        assert!(!allowed(&user(ALICE), Message::collection(), &received, &received.with("received_at", Value::I64(1))));
+        assert!(allowed(&Identity::System, Message::collection(), &message, &received));
+        assert!(!allowed(&user(ALICE), Message::collection(), &Snapshot::default(), &message.with("server_id", text("fixture:welcome"))));
+    }
+
+    #[test]
+    fn moderators_may_only_delete_other_peoples_messages() {
+        let agent = ChatAgent::new();
+        agent.set_standing(BOB.to_string(), Standing { role: role::MODERATOR.to_string(), banned: false });
+        let message =
+            Snapshot::new(&[("user", text(ALICE)), ("room", text("general")), ("text", text("hi")), ("deleted", Value::Bool(false))]);
+        let deleted = message.with("deleted", Value::Bool(true));
+        let check = |after: &Snapshot| agent.check_write_event(&user(BOB), &Message::collection(), &message, after).is_ok();
+
+        assert!(check(&deleted));
+        assert!(!check(&deleted.with("text", text("rewritten"))));
+        assert!(!check(&deleted.with("mentions", text(BOB))));
+        assert!(!check(&deleted.with("thread", text("m2"))));
+        assert!(!check(&message.with("text", text("rewritten"))));
+        assert!(!allowed(&user(BOB), Message::collection(), &message, &deleted));
+    }
+
+    #[test]
+    fn revisions_are_recorded_by_the_server_from_accepted_edits() {
+        let revision = Snapshot::new(&[("message", text("m1")), ("editor", text(ALICE)), ("text", text("old")), ("timestamp", Value::I64(1))]);
+        assert!(!allowed(&user(ALICE), MessageRevision::collection(), &Snapshot::default(), &revision));
+        assert!(!allowed(&user(ALICE), MessageRevision::collection(), &revision, &revision.with("text", text("forged"))));
+        assert!(allowed(&Identity::System, MessageRevision::collection(), &Snapshot::default(), &revision));
+        assert!(allowed(&Identity::System, MessageRevision::collection(), &revision, &revision.with("text", text(""))));
+
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let agent = ChatAgent::new().with_revision_recorder({
+            let recorded = recorded.clone();
+            move |revision| recorded.lock().unwrap().push((revision.message, revision.editor, revision.text, revision.timestamp))
+        });
+        let message = Snapshot::new(&[("user", text(ALICE)), ("room", text("general")), ("text", text("hi"))]);
+        let edited_at = chrono::Utc::now().timestamp_millis();
+        let edited = message.with("text", text("hello")).with("edited_at", Value::I64(edited_at));
+        let check = |identity: &Identity, before: &Snapshot, after: &Snapshot| {
+            agent.check_write_event(identity, &Message::collection(), before, after).is_ok()
+        };
+
+        assert!(check(&user(ALICE), &Snapshot::default(), &message));
+        assert!(check(&user(ALICE), &message, &message.with("deleted", Value::Bool(true))));
+        assert!(!check(&user(BOB), &message, &edited));
+        assert!(check(&Identity::System, &message, &message.with("text", text(""))));
+        assert!(recorded.lock().unwrap().is_empty());
+
+        assert!(check(&user(ALICE), &message, &edited));
+        assert_eq!(*recorded.lock().unwrap(), [("entity".to_string(), ALICE.to_string(), "hi".to_string(), edited_at)]);
+    }
+
+    #[test]
+    fn reactions_are_toggled_only_by_their_owner() {
+        let reaction = Snapshot::new(&[("message", text("m1")), ("emoji", text("👍")), ("deleted", Value::Bool(false))]);
+        assert_created_by_owner(Reaction::collection(), "user", &reaction);
+
+        let reaction = reaction.with("user", text(ALICE));
+        assert!(allowed(&user(ALICE), Reaction::collection(), &reaction, &reaction.with("deleted", Value::Bool(true))));
+        assert!(!allowed(&user(BOB), Reaction::collection(), &reaction, &reaction.with("deleted", Value::Bool(true))));
+        assert!(!allowed(&user(ALICE), Reaction::collection(), &reaction, &reaction.with("emoji", text("👎"))));
+        assert!(!allowed(&user(ALICE), Reaction::collection(), &reaction, &reaction.with("message", text("m2"))));
+    }
+
+    #[test]
+    fn each_user_has_one_live_reaction_per_emoji() {
+        let agent = ChatAgent::new().with_reaction_index();
+        let check = |before: &Snapshot, after: &Snapshot| agent.check_write_event(&user(ALICE), &Reaction::collection(), before, after).is_ok();
+        let first = Snapshot::new(&[("message", text("m1")), ("user", text(ALICE)), ("emoji", text("👍")), ("deleted", Value::Bool(false))]);
+        let second = Snapshot { id: "other".to_string(), ..first.clone() };
+
+        // Two tabs reacting at once each create a reaction; the second is refused
+        assert!(check(&Snapshot::default(), &first));
+        assert!(!check(&Snapshot::default(), &second));
+        assert!(check(&Snapshot::default(), &second.with("emoji", text("🎉"))));
+        assert!(check(&first, &first.with("deleted", Value::Bool(false))));
+
+        // Once the first is withdrawn another may take its place, and the first can't come back
+        assert!(check(&first, &first.with("deleted", Value::Bool(true))));
+        assert!(check(&Snapshot::default(), &second));
+        assert!(!check(&first.with("deleted", Value::Bool(true)), &first));
+    }
+
+    #[test]
+    fn pins_are_changed_as_the_signed_in_user() {
+        let pin = Snapshot::new(&[("room", text("general")), ("message", text("m1")), ("deleted", Value::Bool(false))]);
+        assert_created_by_owner(Pin::collection(), "pinned_by", &pin);
+
+        let pin = pin.with("pinned_by", text(ALICE));
+        let unpinned = pin.with("deleted", Value::Bool(true));
+        assert!(allowed(&user(BOB), Pin::collection(), &pin, &unpinned.with("pinned_by", text(BOB))));
+        assert!(!allowed(&user(BOB), Pin::collection(), &pin, &unpinned));
+        assert!(!allowed(&user(ALICE), Pin::collection(), &pin, &pin.with("message", text("m2"))));
+    }
+
+    #[test]
+    fn read_cursors_and_typing_belong_to_their_user() {
+        let cursor = Snapshot::new(&[("room", text("general")), ("last_read_timestamp", Value::I64(1))]);
+        assert_created_by_owner(ReadCursor::collection(), "user", &cursor);
+        let cursor = cursor.with("user", text(ALICE));
+        assert!(allowed(&user(ALICE), ReadCursor::collection(), &cursor, &cursor.with("last_read_timestamp", Value::I64(2))));
+        assert!(!allowed(&user(BOB), ReadCursor::collection(), &cursor, &cursor.with("last_read_timestamp", Value::I64(2))));
+        assert!(!allowed(&user(ALICE), ReadCursor::collection(), &cursor, &cursor.with("room", text("random"))));
+
+        let typing = Snapshot::new(&[("room", text("general")), ("active", Value::Bool(true))]);
+        assert_created_by_owner(Typing::collection(), "user", &typing);
+        let typing = typing.with("user", text(ALICE));
+        assert!(allowed(&user(ALICE), Typing::collection(), &typing, &typing.with("active", Value::Bool(false))));
+        assert!(!allowed(&user(BOB), Typing::collection(), &typing, &typing.with("active", Value::Bool(false))));
+        assert!(allowed(&Identity::System, Typing::collection(), &typing, &typing.with("active", Value::Bool(false))));
+    }
+
+    #[test]
+    fn attachments_and_chunks_are_immutable() {
+        let attachment = Snapshot::new(&[("message", text("m1")), ("name", text("a.png")), ("content_hash", text("abc"))]);
+        assert_created_by_owner(Attachment::collection(), "user", &attachment);
+        let attachment = attachment.with("user", text(ALICE));
+        assert!(!allowed(&user(ALICE), Attachment::collection(), &attachment, &attachment.with("content_hash", text("def"))));
+
+        let chunk = Snapshot::new(&[("hash", text("abc")), ("seq", Value::I64(0)), ("data", text("AAAA"))]);
+        assert_created_by_owner(BlobChunk::collection(), "user", &chunk);
+        let chunk = chunk.with("user", text(ALICE));
+        assert!(!allowed(&user(ALICE), BlobChunk::collection(), &chunk, &chunk.with("data", text("BBBB"))));
+        assert!(allowed(&Identity::System, BlobChunk::collection(), &chunk, &chunk.with("data", text(""))));
+    }
+
+    #[test]
+    fn searches_are_created_pending_and_answered_by_the_server() {
+        let query = Snapshot::new(&[("query", text("hello")), ("status", text("pending")), ("results", text(""))]);
+        assert_created_by_owner(SearchQuery::collection(), "user", &query);
+
+        let forged = query.with("user", text(ALICE)).with("status", text("done")).with("results", text("[]"));
+        assert!(!allowed(&user(ALICE), SearchQuery::collection(), &Snapshot::default(), &forged));
+        let forged = query.with("user", text(ALICE)).with("answered_at", Value::I64(1));
+        assert!(!allowed(&user(ALICE), SearchQuery::collection(), &Snapshot::default(), &forged));
+
+        let query = query.with("user", text(ALICE));
+        let answered = query.with("status", text("done")).with("results", text("[]"));
+        assert!(!allowed(&user(ALICE), SearchQuery::collection(), &query, &answered));
+        assert!(allowed(&Identity::System, SearchQuery::collection(), &query, &answered));
+    }
+
+    #[test]
+    fn searches_are_private_to_their_owner() {
+        let query = Snapshot::new(&[("user", text(ALICE)), ("query", text("hello"))]);
+        assert!(check_search_query_read(&user(ALICE), &query).is_ok());
+        assert!(check_search_query_read(&user(BOB), &query).is_err());
+        assert!(check_search_query_read(&ANONYMOUS_CONTEXT, &query).is_err());
+        assert!(check_search_query_read(&Identity::System, &query).is_ok());
+
+        let agent = ChatAgent::new();
+        let filter = |identity: &Identity, collection: CollectionId| agent.filter_predicate(identity, &collection, Predicate::True);
+        assert!(matches!(filter(&user(ALICE), SearchQuery::collection()), Ok(Predicate::And(..))));
+        assert!(matches!(filter(&ANONYMOUS_CONTEXT, SearchQuery::collection()), Ok(Predicate::False)));
+        assert!(matches!(filter(&Identity::System, SearchQuery::collection()), Ok(Predicate::True)));
+        assert!(matches!(filter(&user(ALICE), Room::collection()), Ok(Predicate::True)));
+    }
+
+    #[test]
+    fn unknown_collections_are_server_only() {
+        let widget = Snapshot::new(&[("name", text("gadget"))]);
+        assert!(!allowed(&user(ALICE), CollectionId::from("widget"), &Snapshot::default(), &widget));
+        assert!(!allowed(&ANONYMOUS_CONTEXT, CollectionId::from("widget"), &Snapshot::default(), &widget));
+        assert!(allowed(&Identity::System, CollectionId::from("widget"), &Snapshot::default(), &widget));
+    }
+
+    fn connected(id: &str, address: &str) -> Identity { Identity::User { id: id.to_string(), connection: Some(address.to_string()) } }
+
+    #[test]
+    fn messages_are_limited_per_user_and_per_connection() {
+        let agent = ChatAgent::new().with_message_rate_limit(RateLimit { burst: 2, per_second: 0.0 });
+        let send = |identity: &Identity, author: &str| {
+            let message = Snapshot::new(&[("user", text(author)), ("room", text("general"))]);
+            agent.check_write_event(identity, &Message::collection(), &Snapshot::default(), &message).is_ok()
+        };
+
+        assert!(send(&connected(ALICE, "10.0.0.1"), ALICE));
+        assert!(send(&connected(ALICE, "10.0.0.1"), ALICE));
+        assert!(!send(&connected(ALICE, "10.0.0.2"), ALICE));
+        // Another user on the same connection shares its bucket
+        assert!(!send(&connected(BOB, "10.0.0.1"), BOB));
+        assert!(send(&connected(BOB, "10.0.0.2"), BOB));
+    }
+
+    #[test]
+    fn registrations_are_limited_per_connection() {
+        let agent = ChatAgent::new().with_registration_rate_limit(RateLimit { burst: 1, per_second: 0.0 });
+        let register = |address: &str| {
+            let user = Snapshot::new(&[("display_name", text("New")), ("public_key", text("key")), ("role", text(role::MEMBER))]);
+            let identity = Identity::Anonymous { connection: Some(address.to_string()) };
+            agent.check_write_event(&identity, &User::collection(), &Snapshot::default(), &user).is_ok()
+        };
+
+        assert!(register("10.0.0.1"));
+        assert!(!register("10.0.0.1"));
+        assert!(register("10.0.0.2"));
+
+        // Profile changes are not registrations
+        let alice = Snapshot { id: ALICE.to_string(), ..Snapshot::new(&[("display_name", text("Alice")), ("public_key", text("key"))]) };
+        let renamed = alice.with("display_name", text("Alicia"));
+        assert!(agent.check_write_event(&connected(ALICE, "10.0.0.1"), &User::collection(), &alice, &renamed).is_ok());
+    }
+
+    #[test]
+    fn a_node_is_tied_to_the_address_it_connected_from() {
+        let connections = Connections::default();
+        let first = connections.open("node", "10.0.0.1");
+        assert!(first.is_some());
+        assert!(connections.open("node", "10.0.0.2").is_none());
+
+        let second = connections.open("node", "10.0.0.1");
+        assert!(second.is_some());
+        drop(first);
+        assert_eq!(connections.address("node").as_deref(), Some("10.0.0.1"));
+        drop(second);
+        assert_eq!(connections.address("node"), None);
+        assert!(connections.open("node", "10.0.0.2").is_some());
+    }
+
+    #[test]
+    fn users_are_online_while_an_authenticated_node_is_connected() {
+        let connections = Connections::default();
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        connections.on_presence_change({
+            let changes = changes.clone();
+            move |user| changes.lock().unwrap().push(user.to_string())
+        });
+
+        let phone = connections.open("phone", "10.0.0.1").unwrap();
+        let laptop = connections.open("laptop", "10.0.0.2").unwrap();
+        assert!(!connections.is_online(ALICE));
+
+        connections.identify("phone", ALICE);
+        connections.identify("phone", ALICE);
+        connections.identify("laptop", ALICE);
+        assert!(connections.is_online(ALICE));
+        drop(phone);
+        assert!(connections.is_online(ALICE));
+        drop(laptop);
+        assert!(!connections.is_online(ALICE));
+        assert_eq!(*changes.lock().unwrap(), [ALICE, ALICE, ALICE, ALICE]);
+
+        // Nodes that are not connected are never tied to a user
+        connections.identify("tablet", BOB);
+        assert!(!connections.is_online(BOB));
+    }
+
+    #[test]
+    fn only_the_server_records_presence() {
+        let alice = Snapshot { id: ALICE.to_string(), ..Snapshot::new(&[("public_key", text("key")), ("online", Value::Bool(false))]) };
+        assert!(!allowed(&user(ALICE), User::collection(), &alice, &alice.with("online", Value::Bool(true))));
+        assert!(!allowed(&user(ALICE), User::collection(), &alice, &alice.with("last_seen", Value::I64(1))));
+        assert!(allowed(&Identity::System, User::collection(), &alice, &alice.with("online", Value::Bool(true))));
+
+        let registered = Snapshot::new(&[("public_key", text("key")), ("role", text(role::MEMBER))]);
+        assert!(!allowed(&ANONYMOUS_CONTEXT, User::collection(), &Snapshot::default(), &registered.with("online", Value::Bool(true))));
+    }
+
+    #[test]
+    fn banned_users_cannot_write_anything() {
+        let agent = ChatAgent::new();
+        agent.set_standing(ALICE.to_string(), Standing { role: role::MEMBER.to_string(), banned: true });
+        let reaction = Snapshot::new(&[("message", text("m1")), ("user", text(ALICE)), ("emoji", text("👍"))]);
+        assert!(agent.check_write_event(&user(ALICE), &Reaction::collection(), &Snapshot::default(), &reaction).is_err());
+        let room = Snapshot::new(&[("name", text("general"))]);
+        assert!(agent.check_write_event(&user(ALICE), &Room::collection(), &Snapshot::default(), &room).is_err());
+    }
+}
@@ -0,0 +1,117 @@
+//! Full-text search over message text.
+//!
+//! Clients create a `SearchQuery` entity; the server answers it from its inverted index by
+//! filling in `results` with a JSON-encoded list of `SearchHit`s. The tokenizer is shared so the
+//! index and the snippet highlighting agree on what a term is.
+
+use serde::{Deserialize, Serialize};
+
+/// Terms longer than this are truncated before indexing.
+const MAX_TERM_CHARS: usize = 64;
+
+/// One ranked match for a search query.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub message: String,
+    pub room: String,
+    /// Thread root message ID, empty for main-timeline messages.
+    pub thread: String,
+    pub timestamp: i64,
+    pub score: f32,
+    /// Excerpt of the message text around the best match.
+    pub snippet: String,
+    /// Byte ranges within `snippet` that matched a query term.
+    pub highlights: Vec<(usize, usize)>,
+}
+
+impl SearchHit {
+    pub fn encode(hits: &[SearchHit]) -> String { serde_json::to_string(hits).unwrap_or_else(|_| "[]".to_string()) }
+
+    /// Decode the `results` field of a `SearchQuery`; malformed or empty results decode to no hits.
+    pub fn decode(results: &str) -> Vec<SearchHit> { serde_json::from_str(results).unwrap_or_default() }
+}
+
+/// Split text into lowercase alphanumeric terms, with the byte range each came from.
+pub fn tokenize(text: &str) -> Vec<(String, usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    for (i, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                let term: String = text[s..i].chars().flat_map(char::to_lowercase).take(MAX_TERM_CHARS).collect();
+                tokens.push((term, s, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    tokens
+}
+
+/// The distinct terms of a query, in order.
+pub fn query_terms(query: &str) -> Vec<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for (term, _, _) in tokenize(query) {
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    terms
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_lowercase_alphanumeric_runs_with_their_byte_ranges() {
+        assert_eq!(
+            tokenize("Hello, World! it's 2024"),
+            [
+                ("hello".to_string(), 0, 5),
+                ("world".to_string(), 7, 12),
+                ("it".to_string(), 14, 16),
+                ("s".to_string(), 17, 18),
+                ("2024".to_string(), 19, 23)
+            ]
+        );
+        assert!(tokenize("  ...  ").is_empty());
+    }
+
+    #[test]
+    fn ranges_are_byte_offsets_into_non_ascii_text() {
+        let text = "Café ÜBER";
+        let tokens = tokenize(text);
+        assert_eq!(tokens, [("café".to_string(), 0, 5), ("über".to_string(), 6, 11)]);
+        assert_eq!(&text[tokens[1].1..tokens[1].2], "ÜBER");
+    }
+
+    #[test]
+    fn long_terms_are_truncated() {
+        let tokens = tokenize(&"a".repeat(MAX_TERM_CHARS + 10));
+        assert_eq!(tokens[0].0.chars().count(), MAX_TERM_CHARS);
+        assert_eq!(tokens[0].2, MAX_TERM_CHARS + 10);
+    }
+
+    #[test]
+    fn query_terms_are_distinct_and_in_order() {
+        assert_eq!(query_terms("Deploy the deploy THE bot"), ["deploy", "the", "bot"]);
+    }
+
+    #[test]
+    fn hits_round_trip_and_bad_results_decode_to_nothing() {
+        let hit = SearchHit {
+            message: "m".to_string(),
+            room: "r".to_string(),
+            thread: String::new(),
+            timestamp: 1,
+            score: 0.5,
+            snippet: "hello".to_string(),
+            highlights: vec![(0, 5)],
+        };
+        assert_eq!(SearchHit::decode(&SearchHit::encode(std::slice::from_ref(&hit))), [hit]);
+        assert!(SearchHit::decode("").is_empty());
+        assert!(SearchHit::decode("not json").is_empty());
+    }
+}
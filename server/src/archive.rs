@@ -0,0 +1,273 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use ankurah::{
+    model::View,
+    proto::{Attested, CollectionId, EntityState},
+    storage::StorageEngine,
+    Context, EntityId, Node,
+};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    Message, MessageView, Room, RoomView, User, UserView,
+};
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{config::Config, search::SearchIndex};
+
+const ROOM_COLLECTION: &str = "room";
+const USER_COLLECTION: &str = "user";
+const MESSAGE_COLLECTION: &str = "message";
+
+/// One entity in an archive.
+///
+/// `state` is the entity's stored CRDT state, restored under the same ID. `fields` holds the same values
+/// in readable form; on import, any that differ from the state are written over it, so an archive can
+/// be edited (for example, to sanitize names and message text) before loading it elsewhere. The event
+/// history is left out: it holds every value the entity ever had, which an edit could not take back.
+/// Archives from before that still carry `events`; import ignores them.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    collection: String,
+    id: String,
+    fields: serde_json::Value,
+    state: EntityState,
+}
+
+/// Open the configured storage folder with a node over it; the server must not be running.
+async fn open_store(config: &Config) -> Result<(Arc<SledStorageEngine>, Node<SledStorageEngine, ChatAgent>)> {
+    let storage = Arc::new(
+        SledStorageEngine::with_homedir_folder(&config.storage_folder)
+            .with_context(|| format!("failed to open ~/{} (is the server still running?)", config.storage_folder))?,
+    );
+    let node = Node::new_durable(storage.clone(), ChatAgent::new());
+    node.system.wait_loaded().await;
+    Ok((storage, node))
+}
+
+pub async fn export(config: &Config, path: &Path, json: bool) -> Result<()> {
+    let (storage, node) = open_store(config).await?;
+    anyhow::ensure!(node.system.root().is_some(), "~/{} holds no data to export", config.storage_folder);
+    let context = node.context_async(c).await;
+
+    let mut records = export_collection::<RoomView>(&context, &storage, ROOM_COLLECTION, room_fields).await?;
+    records.extend(export_collection::<UserView>(&context, &storage, USER_COLLECTION, user_fields).await?);
+    records.extend(export_collection::<MessageView>(&context, &storage, MESSAGE_COLLECTION, message_fields).await?);
+
+    let mut out = BufWriter::new(File::create(path).with_context(|| format!("failed to create {}", path.display()))?);
+    if json {
+        serde_json::to_writer(&mut out, &records)?;
+    } else {
+        for record in &records {
+            serde_json::to_writer(&mut out, record)?;
+            out.write_all(b"\n")?;
+        }
+    }
+    out.flush()?;
+
+    info!("Exported {} entities to {}", records.len(), path.display());
+    Ok(())
+}
+
+async fn export_collection<V: View + Send + Sync + 'static>(
+    context: &Context,
+    storage: &SledStorageEngine,
+    collection: &str,
+    fields: fn(&V) -> Result<serde_json::Value>,
+) -> Result<Vec<Record>> {
+    let stored = storage.collection(&CollectionId::from(collection)).await?;
+    let mut records = Vec::new();
+    for view in context.fetch::<V>("true").await? {
+        records.push(Record {
+            collection: collection.to_string(),
+            id: view.id().to_base64(),
+            fields: fields(&view)?,
+            state: stored.get_state(view.id()).await?.payload,
+        });
+    }
+    Ok(records)
+}
+
+fn room_fields(room: &RoomView) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(Room {
+        name: room.name().unwrap_or_default(),
+        topic: room.topic().unwrap_or_default(),
+        description: room.description().unwrap_or_default(),
+    })?)
+}
+
+fn user_fields(user: &UserView) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(User {
+        display_name: user.display_name().unwrap_or_default(),
+        online: user.online().unwrap_or(false),
+        last_seen: user.last_seen().unwrap_or(0),
+        public_key: user.public_key().unwrap_or_default(),
+        role: user.role().unwrap_or_default(),
+        banned: user.banned().unwrap_or(false),
+        server_id: user.server_id().unwrap_or_default(),
+    })?)
+}
+
+fn message_fields(message: &MessageView) -> Result<serde_json::Value> {
+    Ok(serde_json::to_value(Message {
+        user: message.user().unwrap_or_default(),
+        room: message.room().unwrap_or_default(),
+        thread: message.thread().unwrap_or_default(),
+        text: message.text().unwrap_or_default(),
+        timestamp: message.timestamp().unwrap_or(0),
+        deleted: message.deleted().unwrap_or(false),
+        edited_at: message.edited_at().unwrap_or(0),
+        mentions: message.mentions().unwrap_or_default(),
+        deleted_at: message.deleted_at().unwrap_or(0),
+        purged: message.purged().unwrap_or(false),
+        received_at: message.received_at().unwrap_or(0),
+        server_id: message.server_id().unwrap_or_default(),
+    })?)
+}
+
+fn read_archive(path: &Path) -> Result<Vec<Record>> {
+    let mut reader = BufReader::new(File::open(path).with_context(|| format!("failed to open {}", path.display()))?);
+    // A JSON archive is one array; a JSONL archive has one record per line
+    if reader.fill_buf()?.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[') {
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        return Ok(serde_json::from_str(&text)?);
+    }
+    let mut records = Vec::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line).with_context(|| format!("{}:{}: invalid record", path.display(), number + 1))?);
+    }
+    Ok(records)
+}
+
+/// Load an archive. Entities that already exist in the store are left alone, so importing the
+/// same archive twice is harmless.
+pub async fn import(config: &Config, path: &Path) -> Result<()> {
+    let records = read_archive(path)?;
+    let (storage, node) = open_store(config).await?;
+    if node.system.root().is_none() {
+        node.system.create().await?;
+    }
+
+    // Edited fields are written over the archived state in a throwaway store, so the values they
+    // replace never reach this one
+    let scratch_storage = Arc::new(SledStorageEngine::new_test()?);
+    let scratch = Node::new_durable(scratch_storage.clone(), ChatAgent::new());
+    scratch.system.create().await?;
+    let scratch_context = scratch.context_async(c).await;
+
+    let (mut restored, mut edited) = (0, 0);
+    for record in &records {
+        anyhow::ensure!(
+            [ROOM_COLLECTION, USER_COLLECTION, MESSAGE_COLLECTION].contains(&record.collection.as_str()),
+            "unexpected collection {} in archive",
+            record.collection
+        );
+        let id = EntityId::from_base64(&record.id).with_context(|| format!("invalid entity ID {}", record.id))?;
+        anyhow::ensure!(record.state.entity_id == id, "record {} carries the state of another entity", record.id);
+        let collection = CollectionId::from(record.collection.as_str());
+        let stored = storage.collection(&collection).await?;
+        if stored.get_state(id).await.is_ok() {
+            continue;
+        }
+
+        let scratch_stored = scratch_storage.collection(&collection).await?;
+        scratch_stored.set_state(Attested::opt(record.state.clone(), None)).await?;
+        let changed = match record.collection.as_str() {
+            ROOM_COLLECTION => apply_room(&scratch_context, id, &record.fields).await?,
+            USER_COLLECTION => apply_user(&scratch_context, id, &record.fields).await?,
+            _ => apply_message(&scratch_context, id, &record.fields).await?,
+        };
+        let state = if changed { scratch_stored.get_state(id).await?.payload } else { record.state.clone() };
+
+        stored.set_state(Attested::opt(state, None)).await?;
+        restored += 1;
+        edited += usize::from(changed);
+    }
+
+    // Restored states bypass the search indexer, which only looks for changes since its watermark
+    if restored > 0 {
+        let index = SearchIndex::open_in_homedir(&config.search_folder())?;
+        index.forget_cursor()?;
+        index.flush().await?;
+    }
+
+    info!(
+        "Imported {} of {} entities from {} ({} already present, {} with edited fields)",
+        restored,
+        records.len(),
+        path.display(),
+        records.len() - restored,
+        edited
+    );
+    Ok(())
+}
+
+async fn apply_room(context: &Context, id: EntityId, fields: &serde_json::Value) -> Result<bool> {
+    let view = context.get::<RoomView>(id).await?;
+    if room_fields(&view)? == *fields {
+        return Ok(false);
+    }
+    let room: Room = serde_json::from_value(fields.clone())?;
+    let trx = context.begin();
+    let mutable = view.edit(&trx)?;
+    mutable.name().replace(&room.name)?;
+    mutable.topic().replace(&room.topic)?;
+    mutable.description().replace(&room.description)?;
+    trx.commit().await?;
+    Ok(true)
+}
+
+async fn apply_user(context: &Context, id: EntityId, fields: &serde_json::Value) -> Result<bool> {
+    let view = context.get::<UserView>(id).await?;
+    if user_fields(&view)? == *fields {
+        return Ok(false);
+    }
+    let user: User = serde_json::from_value(fields.clone())?;
+    let trx = context.begin();
+    let mutable = view.edit(&trx)?;
+    mutable.display_name().replace(&user.display_name)?;
+    mutable.online().set(&user.online)?;
+    mutable.last_seen().set(&user.last_seen)?;
+    mutable.public_key().set(&user.public_key)?;
+    mutable.role().set(&user.role)?;
+    mutable.banned().set(&user.banned)?;
+    mutable.server_id().set(&user.server_id)?;
+    trx.commit().await?;
+    Ok(true)
+}
+
+async fn apply_message(context: &Context, id: EntityId, fields: &serde_json::Value) -> Result<bool> {
+    let view = context.get::<MessageView>(id).await?;
+    if message_fields(&view)? == *fields {
+        return Ok(false);
+    }
+    let message: Message = serde_json::from_value(fields.clone())?;
+    let trx = context.begin();
+    let mutable = view.edit(&trx)?;
+    mutable.user().set(&message.user)?;
+    mutable.room().set(&message.room)?;
+    mutable.thread().set(&message.thread)?;
+    mutable.text().replace(&message.text)?;
+    mutable.timestamp().set(&message.timestamp)?;
+    mutable.deleted().set(&message.deleted)?;
+    mutable.edited_at().set(&message.edited_at)?;
+    mutable.mentions().set(&message.mentions)?;
+    mutable.deleted_at().set(&message.deleted_at)?;
+    mutable.purged().set(&message.purged)?;
+    mutable.received_at().set(&message.received_at)?;
+    mutable.server_id().set(&message.server_id)?;
+    trx.commit().await?;
+    Ok(true)
+}
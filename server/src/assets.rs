@@ -0,0 +1,88 @@
+use std::{borrow::Cow, path::PathBuf};
+
+#[cfg(feature = "embed-assets")]
+#[derive(rust_embed::RustEmbed)]
+#[folder = "../leptos-app/dist"]
+struct Embedded;
+
+/// Where the built Leptos bundle is served from.
+pub enum Assets {
+    /// A `trunk build` output directory on disk, read on every request.
+    Directory(PathBuf),
+    /// `leptos-app/dist` compiled into the binary with the `embed-assets` feature.
+    #[cfg(feature = "embed-assets")]
+    Embedded,
+    /// No bundle: the listener only accepts WebSocket connections.
+    None,
+}
+
+pub struct Asset {
+    pub body: Cow<'static, [u8]>,
+    pub content_type: &'static str,
+}
+
+impl Assets {
+    /// A configured directory wins over the embedded bundle.
+    pub fn new(directory: Option<PathBuf>) -> Self {
+        match directory {
+            Some(directory) => Self::Directory(directory),
+            #[cfg(feature = "embed-assets")]
+            None => Self::Embedded,
+            #[cfg(not(feature = "embed-assets"))]
+            None => Self::None,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Directory(directory) => directory.display().to_string(),
+            #[cfg(feature = "embed-assets")]
+            Self::Embedded => "embedded bundle".to_string(),
+            Self::None => "nothing".to_string(),
+        }
+    }
+
+    /// Look up the asset for a request path. Paths without a file extension fall back to
+    /// `index.html`, so deep links into the app still load it.
+    pub async fn get(&self, path: &str) -> Option<Asset> {
+        let path = path.trim_start_matches('/');
+        // Never serve anything outside the bundle
+        if path.split('/').any(|segment| segment == "..") {
+            return None;
+        }
+        let path = if path.is_empty() { "index.html" } else { path };
+
+        match self.read(path).await {
+            Some(body) => Some(Asset { body, content_type: content_type(path) }),
+            None if !path.rsplit('/').next().unwrap_or_default().contains('.') => {
+                self.read("index.html").await.map(|body| Asset { body, content_type: content_type("index.html") })
+            }
+            None => None,
+        }
+    }
+
+    async fn read(&self, path: &str) -> Option<Cow<'static, [u8]>> {
+        match self {
+            Self::Directory(directory) => tokio::fs::read(directory.join(path)).await.ok().map(Cow::Owned),
+            #[cfg(feature = "embed-assets")]
+            Self::Embedded => Embedded::get(path).map(|file| file.data),
+            Self::None => None,
+        }
+    }
+}
+
+fn content_type(path: &str) -> &'static str {
+    match path.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        // Browsers only stream-compile wasm served with its own MIME type
+        Some("wasm") => "application/wasm",
+        Some("css") => "text/css; charset=utf-8",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("ico") => "image/x-icon",
+        Some("txt") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashSet, sync::Arc};
+
+use ankurah::{Context, EntityId, Mutable, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    role, Message, MessageView, UserView,
+};
+use anyhow::{Context as _, Result};
+use async_trait::async_trait;
+use tracing::{info, warn};
+
+use crate::{
+    feed::{self, WATERMARK_LAG},
+    seed,
+};
+
+mod echo;
+mod help;
+
+use echo::EchoBot;
+use help::HelpBot;
+
+/// A bot that lives in the server process and chats through its own `User`.
+///
+/// Every running bot sees each message the server receives while it is up, except deleted ones and those
+/// posted by bots, and answers through its [`BotContext`]. Bots that post on their own schedule (deploy notices,
+/// on-call pings) can hold on to the context handed to [`Bot::start`] and spawn from there.
+#[async_trait]
+pub trait Bot: Send + Sync + 'static {
+    /// Stable key of the bot's user, stored on it as `server_id` (`bot:<id>`). The user is created on
+    /// first start and found by this key afterwards, so changing the bot's name keeps its user.
+    fn id(&self) -> &str;
+
+    /// Display name of the bot's user, updated on startup when it changes.
+    fn name(&self) -> &str;
+
+    /// Commands to list in `/help`.
+    fn commands(&self) -> Vec<BotCommand> { Vec::new() }
+
+    /// Called once the bot's user exists, before any message is delivered.
+    async fn start(&self, _bot: &BotContext) -> Result<()> { Ok(()) }
+
+    /// Called for each new message.
+    async fn on_message(&self, bot: &BotContext, message: &MessageView) -> Result<()>;
+}
+
+/// A chat command a bot answers, as listed by `/help`.
+#[derive(Debug, Clone)]
+pub struct BotCommand {
+    pub usage: &'static str,
+    pub description: &'static str,
+}
+
+/// What a bot can do in the chat: post as its user, and see every bot's commands.
+#[derive(Clone)]
+pub struct BotContext {
+    context: Context,
+    user_id: String,
+    commands: Arc<Vec<BotCommand>>,
+}
+
+impl BotContext {
+    /// Commands of every running bot.
+    pub fn commands(&self) -> &[BotCommand] { &self.commands }
+
+    /// Answer `message` in the same room, and in its thread if it is a thread reply.
+    pub async fn reply(&self, message: &MessageView, text: &str) -> Result<MessageView> {
+        self.post(&message.room().unwrap_or_default(), &message.thread().unwrap_or_default(), text).await
+    }
+
+    /// Post `text` as the bot in `room` (a room ID), in the thread of the message with ID `thread`, or
+    /// in the main timeline if `thread` is empty.
+    pub async fn post(&self, room: &str, thread: &str, text: &str) -> Result<MessageView> {
+        let now = chrono::Utc::now().timestamp_millis();
+        let trx = self.context.begin();
+        let message = trx
+            .create(&Message {
+                user: self.user_id.clone(),
+                room: room.to_string(),
+                thread: thread.to_string(),
+                text: text.to_string(),
+                timestamp: now,
+                deleted: false,
+                edited_at: 0,
+                mentions: String::new(),
+                deleted_at: 0,
+                purged: false,
+                received_at: now,
+                server_id: String::new(),
+            })
+            .await?
+            .read();
+        trx.commit().await?;
+        Ok(message)
+    }
+}
+
+/// Look up a bot that ships with the server by the name used in the `bots` setting.
+pub fn builtin(name: &str) -> Result<Arc<dyn Bot>> {
+    match name {
+        "echo" => Ok(Arc::new(EchoBot)),
+        "help" => Ok(Arc::new(HelpBot)),
+        _ => anyhow::bail!("unknown bot {:?} (expected one of: echo, help)", name),
+    }
+}
+
+/// If `text` invokes the slash command `name`, return its arguments.
+pub fn command<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let rest = text.trim().strip_prefix('/')?.strip_prefix(name)?;
+    match rest.chars().next() {
+        None => Some(""),
+        Some(next) if next.is_whitespace() => Some(rest.trim()),
+        _ => None,
+    }
+}
+
+/// Create each bot's user, then deliver new messages to every bot.
+pub async fn spawn_bots(node: &Node<SledStorageEngine, ChatAgent>, bots: Vec<Arc<dyn Bot>>) -> Result<()> {
+    let context = node.context_async(c).await;
+    let commands = Arc::new(bots.iter().flat_map(|bot| bot.commands()).collect::<Vec<_>>());
+
+    let mut ids = HashSet::new();
+    for bot in &bots {
+        anyhow::ensure!(ids.insert(bot.id().to_string()), "two bots have the id {:?}", bot.id());
+    }
+
+    let mut handles = Vec::new();
+    for bot in bots {
+        let user_id = seed::ensure_user(&context, &format!("bot:{}", bot.id()), bot.name(), role::MEMBER).await?;
+        rename_user(&context, &user_id, bot.name()).await?;
+        let handle = BotContext { context: context.clone(), user_id, commands: commands.clone() };
+        bot.start(&handle).await.with_context(|| format!("bot {} failed to start", bot.name()))?;
+        handles.push((bot, handle));
+    }
+
+    // Bots never answer each other, so two of them can't talk in circles
+    let bot_users: Arc<HashSet<String>> = Arc::new(handles.iter().map(|(_, handle)| handle.user_id.clone()).collect());
+    for (bot, handle) in handles {
+        info!("Bot {} running as user {}", bot.name(), handle.user_id);
+        let node = node.clone();
+        let bot_users = bot_users.clone();
+        tokio::spawn(async move {
+            if let Err(e) = run(&node, bot.as_ref(), &handle, &bot_users).await {
+                warn!("Bot {} stopped: {}", bot.name(), e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Give a bot's user the bot's current name.
+async fn rename_user(context: &Context, user_id: &str, name: &str) -> Result<()> {
+    let user = context.get::<UserView>(EntityId::from_base64(user_id)?).await?;
+    if user.display_name().unwrap_or_default() == name {
+        return Ok(());
+    }
+    let trx = context.begin();
+    user.edit(&trx)?.display_name().replace(name)?;
+    trx.commit().await?;
+    info!("Bot user {} renamed to '{}'", user_id, name);
+    Ok(())
+}
+
+async fn run(node: &Node<SledStorageEngine, ChatAgent>, bot: &dyn Bot, handle: &BotContext, bot_users: &HashSet<String>) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    // Messages that were already there when the bot started are not new
+    let since = chrono::Utc::now().timestamp_millis() - WATERMARK_LAG.as_millis() as i64;
+    feed::follow_messages(&context, since, true, |message| async move {
+        if message.deleted().unwrap_or(false) || bot_users.contains(&message.user().unwrap_or_default()) {
+            return;
+        }
+        if let Err(e) = bot.on_message(handle, &message).await {
+            warn!("Bot {} failed on message {}: {}", bot.name(), message.id().to_base64(), e);
+        }
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_take_the_rest_of_the_text_as_arguments() {
+        assert_eq!(command("/echo hello there", "echo"), Some("hello there"));
+        assert_eq!(command("  /echo   padded  ", "echo"), Some("padded"));
+        assert_eq!(command("/echo\nnext line", "echo"), Some("next line"));
+        assert_eq!(command("/help", "help"), Some(""));
+    }
+
+    #[test]
+    fn only_the_whole_command_name_matches() {
+        assert_eq!(command("/echoes hi", "echo"), None);
+        assert_eq!(command("/ech hi", "echo"), None);
+        assert_eq!(command("/help", "echo"), None);
+        assert_eq!(command("echo hi", "echo"), None);
+        assert_eq!(command("say /echo hi", "echo"), None);
+        assert_eq!(command("", "echo"), None);
+        assert_eq!(command("/", "echo"), None);
+    }
+}
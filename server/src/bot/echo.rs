@@ -0,0 +1,27 @@
+use ankurah_template_model::MessageView;
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{command, Bot, BotCommand, BotContext};
+
+/// Repeats whatever follows `/echo`. Handy for checking that bots are up.
+pub struct EchoBot;
+
+#[async_trait]
+impl Bot for EchoBot {
+    fn id(&self) -> &str { "echo" }
+
+    fn name(&self) -> &str { "Echo Bot" }
+
+    fn commands(&self) -> Vec<BotCommand> { vec![BotCommand { usage: "/echo <text>", description: "Repeat <text>" }] }
+
+    async fn on_message(&self, bot: &BotContext, message: &MessageView) -> Result<()> {
+        match command(&message.text().unwrap_or_default(), "echo") {
+            Some(text) if !text.is_empty() => {
+                bot.reply(message, text).await?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,29 @@
+use ankurah_template_model::MessageView;
+use anyhow::Result;
+use async_trait::async_trait;
+
+use super::{command, Bot, BotCommand, BotContext};
+
+/// Answers `/help` with the commands of every running bot.
+pub struct HelpBot;
+
+#[async_trait]
+impl Bot for HelpBot {
+    fn id(&self) -> &str { "help" }
+
+    fn name(&self) -> &str { "Help Bot" }
+
+    fn commands(&self) -> Vec<BotCommand> { vec![BotCommand { usage: "/help", description: "List the commands bots answer" }] }
+
+    async fn on_message(&self, bot: &BotContext, message: &MessageView) -> Result<()> {
+        if command(&message.text().unwrap_or_default(), "help").is_none() {
+            return Ok(());
+        }
+        let mut text = String::from("Commands:\n");
+        for BotCommand { usage, description } in bot.commands() {
+            text.push_str(&format!("\n- `{}`: {}", usage, description));
+        }
+        bot.reply(message, &text).await?;
+        Ok(())
+    }
+}
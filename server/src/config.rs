@@ -0,0 +1,438 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+use clap::{Parser, Subcommand};
+use serde::Deserialize;
+use tracing::Level;
+
+const DEFAULT_BIND: &str = "0.0.0.0:9797";
+const DEFAULT_STORAGE_FOLDER: &str = ".ankurah-template";
+const DEFAULT_LOG_LEVEL: &str = "info";
+/// Messages a user, or a single connection, may send in a burst before being limited...
+const DEFAULT_MESSAGE_BURST: u32 = 10;
+/// ...and how many per second they may keep sending after that.
+const DEFAULT_MESSAGE_RATE: f64 = 1.0;
+/// Users a single address may register in a burst...
+const DEFAULT_REGISTRATION_BURST: u32 = 5;
+/// ...and how many per minute it may keep registering after that.
+const DEFAULT_REGISTRATION_RATE: f64 = 1.0;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_PURGE_DELETED_AFTER_DAYS: u32 = 7;
+
+/// Command line flags. Each flag can also be set through its environment variable, and every
+/// setting except `--config` can be given in the TOML config file; flags and environment
+/// variables take precedence over the file.
+#[derive(Debug, Parser)]
+#[command(name = "ankurah-template-server", about = "Ankurah chat server")]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Path to a TOML config file
+    #[arg(long, env = "ANKURAH_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to serve the app and WebSocket connections on [default: 0.0.0.0:9797]
+    #[arg(long, env = "ANKURAH_BIND")]
+    bind: Option<String>,
+
+    /// Address to serve /healthz and /metrics on instead of --bind, such as an internal interface
+    /// [default: served on --bind]
+    #[arg(long, env = "ANKURAH_METRICS_BIND")]
+    metrics_bind: Option<String>,
+
+    /// Sled storage folder, relative to the home directory [default: .ankurah-template]
+    #[arg(long, env = "ANKURAH_STORAGE_FOLDER")]
+    storage_folder: Option<String>,
+
+    /// Log level: error, warn, info, debug or trace [default: info]
+    #[arg(long, env = "ANKURAH_LOG_LEVEL")]
+    log_level: Option<String>,
+
+    /// TOML fixture file of rooms, users and welcome messages to create at startup if missing
+    /// [default: a "General" room]
+    #[arg(long, env = "ANKURAH_FIXTURES")]
+    fixtures: Option<PathBuf>,
+
+    /// User IDs promoted to admin at startup
+    #[arg(long = "admin", env = "ANKURAH_ADMINS", value_delimiter = ',')]
+    admins: Vec<String>,
+
+    /// Messages a user or connection may send in a burst [default: 10]
+    #[arg(long, env = "ANKURAH_MESSAGE_BURST")]
+    message_burst: Option<u32>,
+
+    /// Sustained messages per second per user or connection [default: 1.0]
+    #[arg(long, env = "ANKURAH_MESSAGE_RATE")]
+    message_rate: Option<f64>,
+
+    /// Users one address may register in a burst [default: 5]
+    #[arg(long, env = "ANKURAH_REGISTRATION_BURST")]
+    registration_burst: Option<u32>,
+
+    /// Sustained user registrations per minute per address [default: 1.0]
+    #[arg(long, env = "ANKURAH_REGISTRATION_RATE")]
+    registration_rate: Option<f64>,
+
+    /// PEM certificate chain; together with --tls-key, serve wss:// instead of ws://
+    #[arg(long, env = "ANKURAH_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[arg(long, env = "ANKURAH_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// Built leptos-app directory to serve over HTTP [default: the embedded bundle, if compiled in]
+    #[arg(long, env = "ANKURAH_STATIC_DIR")]
+    static_dir: Option<PathBuf>,
+
+    /// Seconds to let open connections drain on SIGINT/SIGTERM before closing them [default: 10]
+    #[arg(long, env = "ANKURAH_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+
+    /// Days after deletion before a message's content is erased for good [default: 7]
+    #[arg(long, env = "ANKURAH_PURGE_DELETED_AFTER_DAYS")]
+    purge_deleted_after_days: Option<u32>,
+
+    /// Erase messages older than this many days [default: keep them forever]
+    #[arg(long, env = "ANKURAH_MESSAGE_RETENTION_DAYS")]
+    message_retention_days: Option<u32>,
+
+    /// Per-room override of --message-retention-days, as ROOM=DAYS or ROOM=forever, where ROOM is a room name or ID
+    #[arg(long = "room-retention", env = "ANKURAH_ROOM_RETENTION", value_delimiter = ',', value_parser = parse_room_retention)]
+    room_retention: Vec<(String, Option<u32>)>,
+
+    /// Built-in bots to run: echo, help
+    #[arg(long = "bot", env = "ANKURAH_BOTS", value_delimiter = ',')]
+    bots: Vec<String>,
+}
+
+/// What the binary should do; without a subcommand it runs the server.
+#[derive(Debug, Clone, Subcommand)]
+pub enum Command {
+    /// Run the chat server
+    Serve,
+    /// Write every room, user and message in the storage folder to an archive
+    Export {
+        /// Archive file to write
+        path: PathBuf,
+        /// Write a single JSON array instead of one JSON object per line
+        #[arg(long)]
+        json: bool,
+    },
+    /// Load an archive written by `export` into the storage folder, keeping entity IDs
+    Import {
+        /// Archive file to read (JSONL or JSON)
+        path: PathBuf,
+    },
+    /// Apply the fixtures, then optionally generate synthetic message history
+    Seed {
+        /// Number of synthetic messages to add
+        #[arg(long, default_value_t = 0)]
+        messages: usize,
+        /// Room to add them to [default: the first fixture room]
+        #[arg(long)]
+        room: Option<String>,
+        /// Number of synthetic users taking part in the conversation
+        #[arg(long, default_value_t = 8)]
+        authors: usize,
+    },
+}
+
+/// Settings read from the config file; anything left out falls back to the defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    bind: Option<String>,
+    metrics_bind: Option<String>,
+    storage_folder: Option<String>,
+    log_level: Option<String>,
+    fixtures: Option<PathBuf>,
+    admins: Option<Vec<String>>,
+    message_burst: Option<u32>,
+    message_rate: Option<f64>,
+    registration_burst: Option<u32>,
+    registration_rate: Option<f64>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    static_dir: Option<PathBuf>,
+    shutdown_timeout: Option<u64>,
+    purge_deleted_after_days: Option<u32>,
+    message_retention_days: Option<u32>,
+    /// Room name or ID → retention in days overriding `message_retention_days`, or "forever".
+    room_retention_days: Option<HashMap<String, FileRetention>>,
+    bots: Option<Vec<String>>,
+}
+
+/// A room's retention in the config file: a number of days, or "forever".
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum FileRetention {
+    Days(i64),
+    Keyword(String),
+}
+
+impl FileRetention {
+    fn resolve(self, room: &str) -> Result<Option<u32>> {
+        match self {
+            FileRetention::Days(days) => Ok(Some(
+                u32::try_from(days)
+                    .ok()
+                    .filter(|days| *days > 0)
+                    .with_context(|| format!("retention of room {:?} must be at least 1 day", room))?,
+            )),
+            FileRetention::Keyword(keyword) if keyword == FOREVER => Ok(None),
+            FileRetention::Keyword(keyword) => {
+                anyhow::bail!("retention of room {:?} must be a number of days or \"forever\", not {:?}", room, keyword)
+            }
+        }
+    }
+}
+
+const FOREVER: &str = "forever";
+
+/// Parse a `--room-retention` value: `ROOM=DAYS` or `ROOM=forever`.
+fn parse_room_retention(value: &str) -> Result<(String, Option<u32>), String> {
+    let (room, days) = value.rsplit_once('=').ok_or_else(|| format!("expected ROOM=DAYS or ROOM=forever, got {:?}", value))?;
+    if days == FOREVER {
+        return Ok((room.to_string(), None));
+    }
+    match days.parse::<u32>() {
+        Ok(days) if days > 0 => Ok((room.to_string(), Some(days))),
+        _ => Err(format!("retention of room {:?} must be at least 1 day or \"forever\", not {:?}", room, days)),
+    }
+}
+
+impl FileConfig {
+    fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path).with_context(|| format!("failed to read config file {}", path.display()))?;
+        toml::from_str(&text).with_context(|| format!("invalid config file {}", path.display()))
+    }
+}
+
+/// The resolved server configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub command: Command,
+    pub bind: String,
+    /// Where `/healthz` and `/metrics` are served instead of `bind`; `None` serves them on `bind`.
+    pub metrics_bind: Option<String>,
+    pub storage_folder: String,
+    pub log_level: Level,
+    pub fixtures: Option<PathBuf>,
+    pub admins: Vec<String>,
+    pub message_burst: u32,
+    pub message_rate: f64,
+    pub registration_burst: u32,
+    /// Per minute.
+    pub registration_rate: f64,
+    pub tls: Option<TlsConfig>,
+    pub static_dir: Option<PathBuf>,
+    pub shutdown_timeout: Duration,
+    pub retention: RetentionConfig,
+    pub bots: Vec<String>,
+}
+
+/// Certificate and key files for terminating TLS.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// How long messages are kept.
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Grace period between a message's deletion and its purge.
+    pub purge_deleted_after_days: u32,
+    /// Age at which messages expire; `None` keeps them forever.
+    pub message_retention_days: Option<u32>,
+    /// Per-room overrides of `message_retention_days`, by room name or ID.
+    pub room_retention_days: HashMap<String, Option<u32>>,
+}
+
+impl Config {
+    /// Parse the command line and environment, then fill the gaps from the config file and defaults.
+    pub fn load() -> Result<Self> { Self::resolve(Cli::parse()) }
+
+    fn resolve(cli: Cli) -> Result<Self> {
+        let file = match &cli.config {
+            Some(path) => FileConfig::load(path)?,
+            None => FileConfig::default(),
+        };
+
+        let log_level = cli.log_level.or(file.log_level).unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string());
+        let log_level = log_level.parse().map_err(|_| anyhow::anyhow!("invalid log level {:?}", log_level))?;
+
+        let admins = Some(cli.admins).filter(|admins| !admins.is_empty()).or(file.admins);
+        let bots = Some(cli.bots).filter(|bots| !bots.is_empty()).or(file.bots);
+
+        let tls = match (cli.tls_cert.or(file.tls_cert), cli.tls_key.or(file.tls_key)) {
+            (Some(cert), Some(key)) => Some(TlsConfig { cert, key }),
+            (None, None) => None,
+            _ => anyhow::bail!("tls_cert and tls_key must be set together"),
+        };
+
+        let room_retention_days = match (cli.room_retention, file.room_retention_days) {
+            (rooms, _) if !rooms.is_empty() => rooms.into_iter().collect(),
+            (_, Some(rooms)) => {
+                rooms.into_iter().map(|(room, retention)| Ok((room.clone(), retention.resolve(&room)?))).collect::<Result<_>>()?
+            }
+            _ => HashMap::new(),
+        };
+
+        let bind = cli.bind.or(file.bind).unwrap_or_else(|| DEFAULT_BIND.to_string());
+        let metrics_bind = cli.metrics_bind.or(file.metrics_bind);
+        anyhow::ensure!(metrics_bind.as_ref() != Some(&bind), "metrics_bind must differ from bind");
+
+        Ok(Self {
+            command: cli.command.unwrap_or(Command::Serve),
+            bind,
+            metrics_bind,
+            storage_folder: cli.storage_folder.or(file.storage_folder).unwrap_or_else(|| DEFAULT_STORAGE_FOLDER.to_string()),
+            log_level,
+            fixtures: cli.fixtures.or(file.fixtures),
+            admins: admins.unwrap_or_default(),
+            message_burst: at_least_one("message_burst", cli.message_burst.or(file.message_burst).unwrap_or(DEFAULT_MESSAGE_BURST))?,
+            message_rate: positive_rate("message_rate", cli.message_rate.or(file.message_rate).unwrap_or(DEFAULT_MESSAGE_RATE))?,
+            registration_burst: at_least_one(
+                "registration_burst",
+                cli.registration_burst.or(file.registration_burst).unwrap_or(DEFAULT_REGISTRATION_BURST),
+            )?,
+            registration_rate: positive_rate(
+                "registration_rate",
+                cli.registration_rate.or(file.registration_rate).unwrap_or(DEFAULT_REGISTRATION_RATE),
+            )?,
+            tls,
+            static_dir: cli.static_dir.or(file.static_dir),
+            shutdown_timeout: Duration::from_secs(cli.shutdown_timeout.or(file.shutdown_timeout).unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS)),
+            retention: RetentionConfig {
+                purge_deleted_after_days: at_least_one(
+                    "purge_deleted_after_days",
+                    cli.purge_deleted_after_days.or(file.purge_deleted_after_days).unwrap_or(DEFAULT_PURGE_DELETED_AFTER_DAYS),
+                )?,
+                message_retention_days: cli
+                    .message_retention_days
+                    .or(file.message_retention_days)
+                    .map(|days| at_least_one("message_retention_days", days))
+                    .transpose()?,
+                room_retention_days,
+            },
+            bots: bots.unwrap_or_default(),
+        })
+    }
+
+    /// The search index lives next to the entity store, so instances with different storage folders stay separate.
+    pub fn search_folder(&self) -> String { format!("{}-search", self.storage_folder) }
+}
+
+/// Burst sizes and day counts of zero would block or erase everything.
+fn at_least_one(name: &str, value: u32) -> Result<u32> {
+    anyhow::ensure!(value > 0, "{} must be at least 1", name);
+    Ok(value)
+}
+
+fn positive_rate(name: &str, value: f64) -> Result<f64> {
+    anyhow::ensure!(value.is_finite() && value > 0.0, "{} must be a positive number, not {}", name, value);
+    Ok(value)
+}
+
+/// Where a folder relative to the home directory lives on disk.
+pub fn home_path(folder: &str) -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").context("HOME is not set")?;
+    Ok(PathBuf::from(home).join(folder))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, sync::Mutex};
+
+    use super::*;
+
+    /// Clap reads the real environment, so tests that parse flags take turns.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    fn resolve(args: &[&str], file: &str) -> Result<Config> {
+        let mut config = tempfile::NamedTempFile::new().unwrap();
+        config.write_all(file.as_bytes()).unwrap();
+        let path = config.path().to_str().unwrap();
+        Config::resolve(Cli::try_parse_from(["server", "--config", path].iter().chain(args))?)
+    }
+
+    #[test]
+    fn flags_override_environment_and_environment_overrides_file() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        std::env::set_var("ANKURAH_MESSAGE_BURST", "20");
+        std::env::set_var("ANKURAH_MESSAGE_RATE", "3.0");
+        let file = "message_burst = 30\nmessage_rate = 4.0\nregistration_burst = 2\n";
+        let config = resolve(&["--message-burst", "15"], file);
+        std::env::remove_var("ANKURAH_MESSAGE_BURST");
+        std::env::remove_var("ANKURAH_MESSAGE_RATE");
+
+        let config = config.unwrap();
+        assert_eq!(config.message_burst, 15);
+        assert_eq!(config.message_rate, 3.0);
+        assert_eq!(config.registration_burst, 2);
+        assert_eq!(config.registration_rate, DEFAULT_REGISTRATION_RATE);
+    }
+
+    #[test]
+    fn room_retention_from_flags_replaces_the_files() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let file = "message_retention_days = 365\n[room_retention_days]\n\"General\" = 90\n\"Release notes\" = \"forever\"\n";
+
+        let config = resolve(&[], file).unwrap();
+        assert_eq!(config.retention.message_retention_days, Some(365));
+        assert_eq!(config.retention.room_retention_days, HashMap::from([("General".into(), Some(90)), ("Release notes".into(), None)]));
+
+        let config = resolve(&["--room-retention", "Random=7,a=b=forever"], file).unwrap();
+        assert_eq!(config.retention.room_retention_days, HashMap::from([("Random".into(), Some(7)), ("a=b".into(), None)]));
+
+        let config = resolve(&[], "").unwrap();
+        assert_eq!(config.retention.message_retention_days, None);
+        assert!(config.retention.room_retention_days.is_empty());
+    }
+
+    #[test]
+    fn limits_and_retention_must_be_positive() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        for file in [
+            "message_burst = 0",
+            "message_rate = 0.0",
+            "message_rate = -1.0",
+            "message_rate = nan",
+            "registration_burst = 0",
+            "registration_rate = -0.5",
+            "purge_deleted_after_days = 0",
+            "message_retention_days = 0",
+            "[room_retention_days]\nGeneral = 0",
+            "[room_retention_days]\nGeneral = -3",
+            "[room_retention_days]\nGeneral = \"never\"",
+        ] {
+            assert!(resolve(&[], file).is_err(), "{} was accepted", file);
+        }
+        for args in [
+            ["--message-rate", "-2"],
+            ["--purge-deleted-after-days", "0"],
+            ["--room-retention", "General=0"],
+            ["--room-retention", "General"],
+        ] {
+            assert!(resolve(&args, "").is_err(), "{:?} was accepted", args);
+        }
+    }
+
+    #[test]
+    fn metrics_can_move_to_a_separate_address() {
+        let _env = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        assert_eq!(resolve(&[], "").unwrap().metrics_bind, None);
+
+        let file = "bind = \"0.0.0.0:9797\"\nmetrics_bind = \"127.0.0.1:9798\"\n";
+        assert_eq!(resolve(&[], file).unwrap().metrics_bind.as_deref(), Some("127.0.0.1:9798"));
+        assert_eq!(resolve(&["--metrics-bind", "10.0.0.5:9100"], file).unwrap().metrics_bind.as_deref(), Some("10.0.0.5:9100"));
+        assert!(resolve(&["--metrics-bind", "0.0.0.0:9797"], file).is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, future::Future, time::Duration};
+
+use ankurah::{changes::ChangeSet, signals::Subscribe, Context, EntityId, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    MessageView,
+};
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// How far the feed's watermark trails the server's clock: the longest a message may take from being
+/// stamped with `received_at` to reaching the feed's query.
+pub const WATERMARK_LAG: Duration = Duration::from_secs(60);
+/// The feed's query is re-selected once its watermark can move this far.
+const ADVANCE_STEP: Duration = Duration::from_secs(60);
+
+/// A time the server records on messages: when it received one, and when it saw one deleted.
+#[derive(Clone, Copy)]
+enum Stamp {
+    Arrival,
+    Deletion,
+}
+
+impl Stamp {
+    /// The messages still waiting for this stamp. Stamped messages leave the query, so it only ever holds a few.
+    fn pending(self) -> &'static str {
+        match self {
+            Stamp::Arrival => "received_at = 0",
+            Stamp::Deletion => "deleted = true AND deleted_at = 0",
+        }
+    }
+
+    fn is_pending(self, message: &MessageView) -> bool {
+        match self {
+            Stamp::Arrival => message.received_at().unwrap_or(0) == 0,
+            Stamp::Deletion => message.deleted().unwrap_or(false) && message.deleted_at().unwrap_or(0) == 0,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Stamp::Arrival => "Arrival",
+            Stamp::Deletion => "Deletion",
+        }
+    }
+}
+
+/// Stamp `Message.received_at` on every message stored without it, which is every message a client sends.
+/// Messages already stored without it when the server starts (from before it stamped arrivals, or from an
+/// older archive) are stamped with their own `timestamp`, so they don't all look as if they just arrived.
+pub fn spawn_arrival_stamper(node: Node<SledStorageEngine, ChatAgent>) { spawn_stamper(node, Stamp::Arrival) }
+
+/// Stamp `Message.deleted_at` on every deleted message as soon as the server sees it deleted, so retention
+/// and the search index can go by the server's clock.
+pub fn spawn_deletion_stamper(node: Node<SledStorageEngine, ChatAgent>) { spawn_stamper(node, Stamp::Deletion) }
+
+fn spawn_stamper(node: Node<SledStorageEngine, ChatAgent>, kind: Stamp) {
+    tokio::spawn(async move {
+        if let Err(e) = run_stamper(&node, kind).await {
+            warn!("{} stamper stopped: {}", kind.name(), e);
+        }
+    });
+}
+
+async fn run_stamper(node: &Node<SledStorageEngine, ChatAgent>, kind: Stamp) -> Result<()> {
+    let context = node.context_async(c).await;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    for message in context.fetch::<MessageView>(kind.pending()).await? {
+        let _ = sender.send((message, true));
+    }
+
+    let pending = context.query::<MessageView>(kind.pending())?;
+    let _guard = pending.subscribe(move |changeset: ChangeSet<MessageView>| {
+        for message in changeset.adds() {
+            let _ = sender.send((message, false));
+        }
+    });
+
+    while let Some((message, existing)) = receiver.recv().await {
+        // The same message can arrive from both the catch-up fetch and the subscription
+        if !kind.is_pending(&message) {
+            continue;
+        }
+        if let Err(e) = stamp(&context, &message, kind, existing).await {
+            warn!("Failed to stamp message {}: {}", message.id().to_base64(), e);
+        }
+    }
+    Ok(())
+}
+
+/// `existing` messages were already stored when the stamper started.
+async fn stamp(context: &Context, message: &MessageView, kind: Stamp, existing: bool) -> Result<()> {
+    let now = chrono::Utc::now().timestamp_millis();
+    let trx = context.begin();
+    let mutable = message.edit(&trx)?;
+    match kind {
+        Stamp::Arrival if existing => mutable.received_at().set(&sent_at(message, now))?,
+        Stamp::Arrival => mutable.received_at().set(&now)?,
+        Stamp::Deletion => mutable.deleted_at().set(&now)?,
+    }
+    trx.commit().await?;
+    Ok(())
+}
+
+/// When a message already stored was sent, by its sender's clock, but never later than `now`.
+fn sent_at(message: &MessageView, now: i64) -> i64 {
+    match message.timestamp().unwrap_or(0) {
+        timestamp if timestamp > 0 => timestamp.min(now),
+        _ => now,
+    }
+}
+
+/// Call `on_message` once for each message the server received after `since`, as messages arrive, one at
+/// a time. With `skip_existing`, messages already stored when the feed starts are not delivered.
+///
+/// A live query of every message, or a set of every message ID delivered, would grow with the history.
+/// The feed instead follows messages received after a watermark that trails the server's clock by
+/// [`WATERMARK_LAG`], and only remembers the messages above it. It goes by `received_at` rather than the
+/// sender's `timestamp`, so a sender's clock can't hide a new message below the watermark.
+pub async fn follow_messages<F, Fut>(context: &Context, since: i64, skip_existing: bool, mut on_message: F) -> Result<()>
+where
+    F: FnMut(MessageView) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut watermark = since;
+    let mut seen: HashMap<EntityId, i64> = HashMap::new();
+    if skip_existing {
+        for message in context.fetch::<MessageView>(after(watermark).as_str()).await? {
+            seen.insert(message.id(), message.received_at().unwrap_or(0));
+        }
+    }
+
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let messages = context.query::<MessageView>(after(watermark).as_str())?;
+    let _guard = messages.subscribe(move |changeset: ChangeSet<MessageView>| {
+        for message in changeset.adds() {
+            let _ = sender.send(message);
+        }
+    });
+
+    while let Some(message) = receiver.recv().await {
+        let received_at = message.received_at().unwrap_or(0);
+        if received_at > watermark && seen.insert(message.id(), received_at).is_none() {
+            on_message(message).await;
+        }
+
+        let trailing = chrono::Utc::now().timestamp_millis() - WATERMARK_LAG.as_millis() as i64;
+        if trailing - watermark >= ADVANCE_STEP.as_millis() as i64 {
+            watermark = trailing;
+            seen.retain(|_, received_at| *received_at > watermark);
+            messages.update_selection(after(watermark).as_str())?;
+        }
+    }
+    Ok(())
+}
+
+fn after(watermark: i64) -> String { format!("received_at > {}", watermark) }
@@ -0,0 +1,431 @@
+use std::{
+    borrow::Cow,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ankurah::EntityId;
+use ankurah_template_model::policy::Connections;
+use anyhow::{Context as _, Result};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::watch,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::debug;
+
+use crate::{assets::Assets, metrics::Metrics, shutdown, websocket::NodeGuard};
+
+/// Clients that connect but never finish the TLS handshake are dropped after this long.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Idle keep-alive connections are closed after this long.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// While shutting down, a WebSocket connection is closed once the server has sent nothing for this long.
+const DRAIN_QUIET: Duration = Duration::from_millis(500);
+const MAX_HEAD_BYTES: usize = 16 * 1024;
+const MAX_HEADERS: usize = 64;
+/// How many loopback addresses to try before giving up on starting the WebSocket server.
+pub const BACKEND_BIND_ATTEMPTS: u32 = 5;
+
+/// The server's public listener. Plain HTTP requests get the Leptos bundle; WebSocket upgrade
+/// requests, on any path, are handed to the Ankurah WebSocket server listening on loopback, so
+/// one port serves both the app and its sync connection. `/healthz` and `/metrics` are answered
+/// before looking for an asset, unless [`HttpListener::without_operational_endpoints`] moved them to an
+/// [`InternalListener`].
+///
+/// A WebSocket request names its client node in the `node` query parameter. The node is registered
+/// with the peer address for as long as the connection is open, which is how the policy agent knows
+/// where a request came from, and every message the client sends must be from that node.
+pub struct HttpListener {
+    listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    routes: Routes,
+    metrics: Arc<Metrics>,
+    connections: Connections,
+    backend: Backend,
+}
+
+impl HttpListener {
+    pub async fn bind(
+        address: &str,
+        tls: Option<TlsAcceptor>,
+        assets: Assets,
+        metrics: Arc<Metrics>,
+        connections: Connections,
+        backend: Backend,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind(address).await.with_context(|| format!("failed to bind {}", address))?;
+        Ok(Self { listener, tls, routes: Routes { assets, operational: true }, metrics, connections, backend })
+    }
+
+    /// Answer `/healthz` and `/metrics` like any other missing asset, for when an [`InternalListener`] serves them.
+    pub fn without_operational_endpoints(mut self) -> Self {
+        self.routes.operational = false;
+        self
+    }
+
+    /// Accept connections until shutdown is requested, then close the listening socket.
+    pub async fn run(self, mut stopping: watch::Receiver<bool>) {
+        let routes = Arc::new(self.routes);
+        loop {
+            let accepted = tokio::select! {
+                accepted = self.listener.accept() => accepted,
+                _ = shutdown::requested(&mut stopping) => return,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("Accept failed: {}", e);
+                    continue;
+                }
+            };
+            let tls = self.tls.clone();
+            let routes = routes.clone();
+            let metrics = self.metrics.clone();
+            let connections = self.connections.clone();
+            let backend = self.backend.clone();
+            let stopping = stopping.clone();
+            tokio::spawn(async move {
+                let result = match tls {
+                    Some(acceptor) => match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => serve_connection(stream, peer, &routes, &metrics, &connections, &backend, stopping).await,
+                        Ok(Err(e)) => Err(e.into()),
+                        Err(_) => Err(anyhow::anyhow!("TLS handshake timed out")),
+                    },
+                    None => serve_connection(stream, peer, &routes, &metrics, &connections, &backend, stopping).await,
+                };
+                if let Err(e) = result {
+                    debug!("Connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+/// The loopback address the WebSocket server listens on behind the public listener.
+///
+/// The WebSocket server binds the address itself, so a free port is found by binding and releasing it,
+/// and another process can take the port in between. When that happens the server is started again on
+/// a new address with [`Backend::renew`], and the public listener forwards to wherever it is now.
+#[derive(Clone)]
+pub struct Backend(Arc<Mutex<SocketAddr>>);
+
+impl Backend {
+    pub fn new() -> Result<Self> { Ok(Self(Arc::new(Mutex::new(free_loopback_address()?)))) }
+
+    pub fn address(&self) -> SocketAddr { *self.0.lock().unwrap() }
+
+    /// Move to another free port after the current one turned out to be taken.
+    pub fn renew(&self) -> Result<SocketAddr> {
+        let address = free_loopback_address()?;
+        *self.0.lock().unwrap() = address;
+        Ok(address)
+    }
+}
+
+fn free_loopback_address() -> Result<SocketAddr> {
+    let probe = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(probe.local_addr()?)
+}
+
+/// Whether `error` came from binding an address something else is listening on.
+pub fn address_in_use(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| cause.downcast_ref::<std::io::Error>().is_some_and(|e| e.kind() == std::io::ErrorKind::AddrInUse))
+}
+
+/// What the connection loop needs from a parsed request head, copied out of the read buffer.
+struct RequestHead {
+    method: String,
+    path: String,
+    websocket: bool,
+    keep_alive: bool,
+    /// The head re-serialised for the WebSocket server, which expects the upgrade at `/ws`.
+    forwarded: Vec<u8>,
+}
+
+async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    peer: SocketAddr,
+    routes: &Routes,
+    metrics: &Arc<Metrics>,
+    connections: &Connections,
+    backend: &Backend,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        let head = tokio::select! {
+            head = tokio::time::timeout(IDLE_TIMEOUT, read_head(&mut stream, &mut buf)) => head??,
+            // Don't wait for another request on a keep-alive connection
+            _ = shutdown::requested(&mut stopping) => return Ok(()),
+        };
+        let Some(head_len) = head else { return Ok(()) };
+        let Some(request) = parse_head(&buf[..head_len]) else {
+            return Response::text(400, "Bad Request", "bad request\n").write(&mut stream, false, false).await;
+        };
+
+        if request.websocket {
+            // The Ankurah clients append `/ws` to the URL they are given, so the ID ends at the next `/`
+            let node = query_param(&request.path, "node").and_then(|node| node.split('/').next()).unwrap_or_default();
+            let Ok(node_id) = EntityId::from_base64(node) else {
+                return Response::text(400, "Bad Request", "missing or malformed node id\n").write(&mut stream, false, false).await;
+            };
+            let Some(_registration) = connections.open(&node_id.to_base64(), &peer.ip().to_string()) else {
+                return Response::text(409, "Conflict", "node is connected from another address\n").write(&mut stream, false, false).await;
+            };
+            let mut guard = NodeGuard::new(node_id);
+            let early = guard.push(&buf[head_len..])?;
+            let _connection = metrics.websocket_opened();
+            return forward(stream, [request.forwarded, early].concat(), guard, backend.address(), stopping).await;
+        }
+
+        let response = routes.route(&request.method, &request.path, metrics).await;
+        response.write(&mut stream, request.method == "HEAD", request.keep_alive).await?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+        buf.drain(..head_len);
+    }
+}
+
+/// Read until the end of a request head, returning its length, or `None` if the client closed
+/// the connection between requests.
+async fn read_head<S: AsyncRead + Unpin>(stream: &mut S, buf: &mut Vec<u8>) -> Result<Option<usize>> {
+    loop {
+        if let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+            return Ok(Some(end + 4));
+        }
+        anyhow::ensure!(buf.len() < MAX_HEAD_BYTES, "request head too large");
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            anyhow::ensure!(buf.is_empty(), "connection closed mid-request");
+            return Ok(None);
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+fn parse_head(head: &[u8]) -> Option<RequestHead> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut request = httparse::Request::new(&mut headers);
+    request.parse(head).ok()?;
+    let method = request.method?.to_string();
+    let path = request.path?.to_string();
+
+    let has_token = |name: &str, token: &str| {
+        request.headers.iter().filter(|header| header.name.eq_ignore_ascii_case(name)).any(|header| {
+            String::from_utf8_lossy(header.value).split(',').any(|value| value.trim().eq_ignore_ascii_case(token))
+        })
+    };
+    // Requests with a body are answered and then closed rather than parsing the body
+    let has_body = request.headers.iter().any(|header| {
+        header.name.eq_ignore_ascii_case("transfer-encoding")
+            || (header.name.eq_ignore_ascii_case("content-length") && header.value.iter().any(|b| *b != b'0'))
+    });
+    let websocket = has_token("upgrade", "websocket");
+    let keep_alive = request.version == Some(1) && !has_token("connection", "close") && !has_body;
+
+    // Extensions are not offered to the WebSocket server: the listener has to read the frames it forwards
+    let mut forwarded = format!("{} /ws HTTP/1.1\r\n", method).into_bytes();
+    for header in request.headers.iter().filter(|header| !header.name.eq_ignore_ascii_case("sec-websocket-extensions")) {
+        forwarded.extend_from_slice(header.name.as_bytes());
+        forwarded.extend_from_slice(b": ");
+        forwarded.extend_from_slice(header.value);
+        forwarded.extend_from_slice(b"\r\n");
+    }
+    forwarded.extend_from_slice(b"\r\n");
+
+    Some(RequestHead { method, path, websocket, keep_alive, forwarded })
+}
+
+/// Relay a WebSocket connection to the Ankurah server until either side closes it, passing the client's
+/// frames through `guard`. The connection is closed as soon as the guard refuses one.
+async fn forward<S: AsyncRead + AsyncWrite + Unpin>(
+    client: S,
+    head: Vec<u8>,
+    mut guard: NodeGuard,
+    backend: SocketAddr,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut server = TcpStream::connect(backend).await?;
+    server.write_all(&head).await?;
+    let (mut client_read, mut client_write) = tokio::io::split(client);
+    let (mut server_read, mut server_write) = server.into_split();
+    tokio::select! {
+        result = relay_guarded(&mut client_read, &mut server_write, &mut guard) => return result,
+        result = tokio::io::copy(&mut server_read, &mut client_write) => {
+            result?;
+            return Ok(());
+        }
+        _ = shutdown::requested(&mut stopping) => {}
+    }
+
+    // Shutting down: take no more requests from the client, but pass on the server's replies to
+    // the ones it already has (commit acknowledgements) until it goes quiet
+    let mut buf = vec![0u8; 8192];
+    while let Ok(read) = tokio::time::timeout(DRAIN_QUIET, server_read.read(&mut buf)).await {
+        let read = read?;
+        if read == 0 {
+            break;
+        }
+        client_write.write_all(&buf[..read]).await?;
+    }
+    client_write.flush().await?;
+    Ok(())
+}
+
+/// Copy what the client sends to the server once `guard` has let it through.
+async fn relay_guarded<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(client: &mut R, server: &mut W, guard: &mut NodeGuard) -> Result<()> {
+    let mut buf = vec![0u8; 8192];
+    loop {
+        let read = client.read(&mut buf).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        server.write_all(&guard.push(&buf[..read])?).await?;
+    }
+}
+
+/// The value of `name` in the query string of `path`. Node IDs are base64, so no percent-decoding is done.
+fn query_param<'a>(path: &'a str, name: &str) -> Option<&'a str> {
+    let query = path.split('#').next()?.split_once('?')?.1;
+    query.split('&').find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// What plain HTTP requests on the public listener are answered with.
+struct Routes {
+    assets: Assets,
+    /// Whether `/healthz` and `/metrics` are answered here.
+    operational: bool,
+}
+
+impl Routes {
+    async fn route(&self, method: &str, path: &str, metrics: &Metrics) -> Response {
+        if method != "GET" && method != "HEAD" {
+            return Response::text(405, "Method Not Allowed", "method not allowed\n");
+        }
+        let path = path.split(['?', '#']).next().unwrap_or_default();
+        if let Some(response) = self.operational.then(|| operational_route(path, metrics)).flatten() {
+            return response;
+        }
+        match self.assets.get(path).await {
+            Some(asset) => Response { status: 200, reason: "OK", content_type: asset.content_type, body: asset.body },
+            None => not_found(),
+        }
+    }
+}
+
+/// `/healthz` and `/metrics`, or `None` for any other path.
+fn operational_route(path: &str, metrics: &Metrics) -> Option<Response> {
+    match path {
+        "/healthz" if metrics.is_ready() => Some(Response::text(200, "OK", "ok\n")),
+        "/healthz" => Some(Response::text(503, "Service Unavailable", "system not ready\n")),
+        "/metrics" => {
+            let body = Cow::Owned(metrics.render().into_bytes());
+            Some(Response { status: 200, reason: "OK", content_type: "text/plain; version=0.0.4; charset=utf-8", body })
+        }
+        _ => None,
+    }
+}
+
+fn not_found() -> Response { Response::text(404, "Not Found", "not found\n") }
+
+/// Serves only `/healthz` and `/metrics`, over plain HTTP, on an address of their own, so monitoring
+/// can stay on an internal network while the public listener serves the app.
+pub struct InternalListener {
+    listener: TcpListener,
+    metrics: Arc<Metrics>,
+}
+
+impl InternalListener {
+    pub async fn bind(address: &str, metrics: Arc<Metrics>) -> Result<Self> {
+        let listener = TcpListener::bind(address).await.with_context(|| format!("failed to bind {}", address))?;
+        Ok(Self { listener, metrics })
+    }
+
+    /// Accept connections until shutdown is requested, then close the listening socket.
+    pub async fn run(self, mut stopping: watch::Receiver<bool>) {
+        loop {
+            let accepted = tokio::select! {
+                accepted = self.listener.accept() => accepted,
+                _ = shutdown::requested(&mut stopping) => return,
+            };
+            let (stream, peer) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    debug!("Accept failed: {}", e);
+                    continue;
+                }
+            };
+            let metrics = self.metrics.clone();
+            let stopping = stopping.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_internal(stream, &metrics, stopping).await {
+                    debug!("Connection from {} closed: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+async fn serve_internal<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    metrics: &Metrics,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let mut buf = Vec::new();
+    loop {
+        let head = tokio::select! {
+            head = tokio::time::timeout(IDLE_TIMEOUT, read_head(&mut stream, &mut buf)) => head??,
+            _ = shutdown::requested(&mut stopping) => return Ok(()),
+        };
+        let Some(head_len) = head else { return Ok(()) };
+        let Some(request) = parse_head(&buf[..head_len]) else {
+            return Response::text(400, "Bad Request", "bad request\n").write(&mut stream, false, false).await;
+        };
+
+        let path = request.path.split(['?', '#']).next().unwrap_or_default();
+        let response = match request.method.as_str() {
+            "GET" | "HEAD" => operational_route(path, metrics).unwrap_or_else(not_found),
+            _ => Response::text(405, "Method Not Allowed", "method not allowed\n"),
+        };
+        response.write(&mut stream, request.method == "HEAD", request.keep_alive).await?;
+        if !request.keep_alive {
+            return Ok(());
+        }
+        buf.drain(..head_len);
+    }
+}
+
+struct Response {
+    status: u16,
+    reason: &'static str,
+    content_type: &'static str,
+    body: Cow<'static, [u8]>,
+}
+
+impl Response {
+    fn text(status: u16, reason: &'static str, body: &'static str) -> Self {
+        Self { status, reason, content_type: "text/plain; charset=utf-8", body: Cow::Borrowed(body.as_bytes()) }
+    }
+
+    async fn write<S: AsyncWrite + Unpin>(&self, stream: &mut S, head_only: bool, keep_alive: bool) -> Result<()> {
+        let head = format!(
+            "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-cache\r\nConnection: {}\r\n\r\n",
+            self.status,
+            self.reason,
+            self.content_type,
+            self.body.len(),
+            if keep_alive { "keep-alive" } else { "close" },
+        );
+        stream.write_all(head.as_bytes()).await?;
+        if !head_only {
+            stream.write_all(&self.body).await?;
+        }
+        stream.flush().await?;
+        Ok(())
+    }
+}
@@ -1,53 +1,177 @@
-use ankurah::{policy::DEFAULT_CONTEXT as c, Node, PermissiveAgent};
+use ankurah::Node;
 use ankurah_storage_sled::SledStorageEngine;
-use ankurah_template_model::{Room, RoomView};
+use ankurah_template_model::{
+    policy::{ChatAgent, Connections, RateLimit, SYSTEM_CONTEXT},
+    ReactionView,
+};
 use ankurah_websocket_server::WebsocketServer;
-use anyhow::Result;
+use anyhow::{Context as _, Result};
+use config::{Command, Config};
 use std::sync::Arc;
-use tracing::{info, Level};
+use tracing::{info, warn};
+
+mod archive;
+mod assets;
+mod bot;
+mod config;
+mod feed;
+mod http;
+mod metrics;
+mod moderation;
+mod presence;
+mod retention;
+mod revisions;
+mod search;
+mod seed;
+mod shutdown;
+mod tls;
+mod typing;
+mod websocket;
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init(); // initialize tracing
+    let config = Config::load()?;
+    tracing_subscriber::fmt().with_max_level(config.log_level).init(); // initialize tracing
+
+    match &config.command {
+        Command::Serve => serve(&config).await,
+        Command::Export { path, json } => archive::export(&config, path, *json).await,
+        Command::Import { path } => archive::import(&config, path).await,
+        Command::Seed { messages, room, authors } => seed_store(&config, *messages, room.as_deref(), *authors).await,
+    }
+}
+
+async fn serve(config: &Config) -> Result<()> {
+    let fixtures = seed::Fixtures::load(config.fixtures.as_deref())?;
+    let bots = config.bots.iter().map(|name| bot::builtin(name.trim())).collect::<Result<Vec<_>>>()?;
 
     // Initialize storage engine
-    let storage = SledStorageEngine::with_homedir_folder(".ankurah-template")?;
-    let node = Node::new_durable(Arc::new(storage), PermissiveAgent::new());
+    let storage = Arc::new(SledStorageEngine::with_homedir_folder(&config.storage_folder)?);
+    // Requests are attributed to the address of the connection they arrive on, as registered by the listener
+    let connections = Connections::default();
+    let (revisions, revision_receiver) = tokio::sync::mpsc::unbounded_channel();
+    let agent = ChatAgent::new()
+        .with_message_rate_limit(RateLimit { burst: config.message_burst, per_second: config.message_rate })
+        .with_registration_rate_limit(RateLimit { burst: config.registration_burst, per_second: config.registration_rate / 60.0 })
+        .with_connections(connections.clone())
+        .with_reaction_index()
+        .with_revision_recorder(move |revision| {
+            let _ = revisions.send(revision);
+        });
+    let node = Node::new_durable(storage.clone(), agent.clone());
+    let metrics = Arc::new(metrics::Metrics::new(
+        node.clone(),
+        agent.clone(),
+        vec![("entities", config::home_path(&config.storage_folder)?), ("search", config::home_path(&config.search_folder())?)],
+    ));
 
     node.system.wait_loaded().await;
     if node.system.root().is_none() {
         node.system.create().await?;
     }
 
-    // Ensure "General" room exists
-    ensure_general_room(&node).await?;
+    // Create the fixture rooms, users and welcome messages that don't exist yet
+    seed::apply(&node, &fixtures).await?;
 
-    let mut server = WebsocketServer::new(node);
-    server.run("0.0.0.0:9797").await?;
+    // Let the policy agent refuse a second live reaction with the same emoji from the same user
+    let reactions = node.context_async(SYSTEM_CONTEXT).await.fetch::<ReactionView>("deleted = false").await?;
+    agent.index_reactions(&reactions);
 
-    Ok(())
-}
+    // Bootstrap admins and keep the policy agent's roles and bans current
+    moderation::promote_admins(&node, &config.admins).await?;
+    moderation::spawn_standing_tracker(node.clone(), agent);
+
+    // Users are online while one of their nodes is connected
+    presence::spawn_presence_tracker(node.clone(), connections.clone()).await?;
+
+    // Keep the edit history of messages, from the edits the policy agent accepts
+    revisions::spawn_revision_recorder(node.clone(), revision_receiver);
+
+    // Record when the server received and saw deleted each message, for retention, the message feeds and search
+    feed::spawn_arrival_stamper(node.clone());
+    feed::spawn_deletion_stamper(node.clone());
+
+    // Full-text search: index messages as they change and answer clients' SearchQuery entities
+    let search_index = Arc::new(search::SearchIndex::open_in_homedir(&config.search_folder())?);
+    search::spawn_search_indexer(node.clone(), search_index.clone());
+    search::spawn_search_responder(node.clone(), search_index.clone());
+    search::spawn_search_expiry(node.clone());
+
+    // Count messages per room for /metrics
+    metrics::spawn_message_counter(metrics.clone());
 
-async fn ensure_general_room(node: &Node<SledStorageEngine, PermissiveAgent>) -> Result<()> {
-    let context = node.context_async(c).await;
+    // Expire typing indicators left behind by crashed or disconnected tabs
+    typing::spawn_typing_sweeper(node.clone());
 
-    // Query for a room named "General"
-    let rooms = context.fetch::<RoomView>("name = 'General'").await?;
+    // Erase deleted messages once their grace period is over, and messages past their room's retention
+    retention::spawn_retention_sweeper(node.clone(), storage.clone(), config.retention.clone());
 
-    if rooms.is_empty() {
-        info!("Creating 'General' room");
+    // Server-side bots answer messages as their own users
+    bot::spawn_bots(&node, bots).await?;
 
-        let trx = context.begin();
-        trx.create(&Room {
-            name: "General".to_string(),
-        })
-        .await?;
-        trx.commit().await?;
+    // The public listener serves the app and hands WebSocket upgrades to the Ankurah server on loopback
+    let tls = config.tls.as_ref().map(tls::acceptor).transpose()?;
+    let assets = assets::Assets::new(config.static_dir.clone());
+    let backend = http::Backend::new()?;
+    info!("Listening on {} ({}, serving {})", config.bind, if tls.is_some() { "https/wss" } else { "http/ws" }, assets.describe());
+    let listener = http::HttpListener::bind(&config.bind, tls, assets, metrics.clone(), connections, backend.clone()).await?;
+    let (stop, stopping) = shutdown::channel();
 
-        info!("'General' room created");
+    // Monitoring can be kept off the public address
+    if let Some(address) = &config.metrics_bind {
+        info!("Serving /healthz and /metrics on {}", address);
+        let internal = http::InternalListener::bind(address, metrics.clone()).await?;
+        tokio::spawn(internal.run(stopping.clone()));
+        tokio::spawn(listener.without_operational_endpoints().run(stopping));
     } else {
-        info!("'General' room already exists");
+        tokio::spawn(listener.run(stopping));
     }
 
+    let mut server = WebsocketServer::new(node);
+    let serve = async {
+        let mut attempts = 1;
+        loop {
+            let address = backend.address();
+            let Err(e) = server.run(&address.to_string()).await else { return Ok::<_, anyhow::Error>(()) };
+            if attempts == http::BACKEND_BIND_ATTEMPTS || !http::address_in_use(&e) {
+                return Err(e);
+            }
+            attempts += 1;
+            warn!("Loopback address {} was taken before the WebSocket server bound it; moving to {}", address, backend.renew()?);
+        }
+    };
+    tokio::select! {
+        result = serve => result?,
+        result = shutdown::signal_received() => result?,
+    }
+
+    // Stop accepting, let open connections receive replies to what they already sent, then make sure
+    // every committed write is on disk before exiting
+    info!("Shutting down");
+    let _ = stop.send(true);
+    shutdown::drain(&metrics, config.shutdown_timeout).await;
+    let db = storage.database.lock().unwrap().db.clone();
+    db.flush_async().await?;
+    search_index.flush().await?;
+    info!("Storage flushed, exiting");
+
+    Ok(())
+}
+
+/// Apply the fixtures to the storage folder and add synthetic history; the server must not be running.
+async fn seed_store(config: &Config, messages: usize, room: Option<&str>, authors: usize) -> Result<()> {
+    let fixtures = seed::Fixtures::load(config.fixtures.as_deref())?;
+    let storage = SledStorageEngine::with_homedir_folder(&config.storage_folder)?;
+    let node = Node::new_durable(Arc::new(storage), ChatAgent::new());
+    node.system.wait_loaded().await;
+    if node.system.root().is_none() {
+        node.system.create().await?;
+    }
+
+    seed::apply(&node, &fixtures).await?;
+    if messages > 0 {
+        let room = room.or(fixtures.rooms.first().map(|room| room.name.as_str())).context("no room to seed; pass --room")?;
+        seed::generate_history(&node, room, messages, authors).await?;
+    }
     Ok(())
 }
@@ -0,0 +1,141 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use ankurah::Node;
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    MessageView,
+};
+use anyhow::Result;
+use tracing::warn;
+
+use crate::feed::{self, WATERMARK_LAG};
+
+/// Counters and gauges exposed on `/metrics` in the Prometheus text format.
+pub struct Metrics {
+    node: Node<SledStorageEngine, ChatAgent>,
+    agent: ChatAgent,
+    /// Sled databases to report the on-disk size of, by label.
+    databases: Vec<(&'static str, PathBuf)>,
+    websocket_connections: AtomicI64,
+    /// Room ID → messages created in it.
+    messages_per_room: Mutex<BTreeMap<String, u64>>,
+}
+
+/// Counts an open WebSocket connection until dropped.
+pub struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) { self.0.websocket_connections.fetch_sub(1, Ordering::Relaxed); }
+}
+
+impl Metrics {
+    pub fn new(node: Node<SledStorageEngine, ChatAgent>, agent: ChatAgent, databases: Vec<(&'static str, PathBuf)>) -> Self {
+        Self { node, agent, databases, websocket_connections: AtomicI64::new(0), messages_per_room: Mutex::new(BTreeMap::new()) }
+    }
+
+    pub fn websocket_opened(self: &Arc<Self>) -> ConnectionGuard {
+        self.websocket_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+
+    pub fn websocket_connections(&self) -> i64 { self.websocket_connections.load(Ordering::Relaxed) }
+
+    /// Whether the node has loaded its system root and can serve clients.
+    pub fn is_ready(&self) -> bool { self.node.system.root().is_some() }
+
+    fn count_message(&self, room: String) { *self.messages_per_room.lock().unwrap().entry(room).or_default() += 1; }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let (accepted, rejected) = self.agent.event_counts();
+
+        metric(&mut out, "chat_websocket_connections", "gauge", "Open WebSocket connections.");
+        let _ = writeln!(out, "chat_websocket_connections {}", self.websocket_connections());
+
+        metric(&mut out, "chat_messages_created_total", "counter", "Messages created, by room ID.");
+        for (room, count) in self.messages_per_room.lock().unwrap().iter() {
+            let _ = writeln!(out, "chat_messages_created_total{{room=\"{}\"}} {}", escape_label(room), count);
+        }
+
+        // The policy agent is asked about each event, not each transaction, so these count events
+        metric(
+            &mut out,
+            "chat_events_accepted_total",
+            "counter",
+            "Entity change events accepted by the policy agent. Counts events, not transactions: \
+             a transaction adds one per entity it changes.",
+        );
+        let _ = writeln!(out, "chat_events_accepted_total {}", accepted);
+        metric(
+            &mut out,
+            "chat_events_rejected_total",
+            "counter",
+            "Entity change events rejected by the policy agent. Counts events, not transactions: \
+             the rest of a transaction is not checked after its first rejected event.",
+        );
+        let _ = writeln!(out, "chat_events_rejected_total {}", rejected);
+
+        metric(&mut out, "chat_sled_size_bytes", "gauge", "On-disk size of each sled database.");
+        for (db, path) in &self.databases {
+            let _ = writeln!(out, "chat_sled_size_bytes{{db=\"{}\"}} {}", db, directory_size(path));
+        }
+
+        metric(&mut out, "chat_system_ready", "gauge", "1 once the node's system root is loaded.");
+        let _ = writeln!(out, "chat_system_ready {}", u8::from(self.is_ready()));
+        out
+    }
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn escape_label(value: &str) -> String { value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n") }
+
+fn directory_size(path: &Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(path) else { return 0 };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(metadata) if metadata.is_dir() => directory_size(&entry.path()),
+            Ok(metadata) => metadata.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Count messages per room: everything already stored, then each new message as it is created.
+pub fn spawn_message_counter(metrics: Arc<Metrics>) {
+    tokio::spawn(async move {
+        if let Err(e) = count_messages(metrics).await {
+            warn!("Message counter stopped: {}", e);
+        }
+    });
+}
+
+async fn count_messages(metrics: Arc<Metrics>) -> Result<()> {
+    let context = metrics.node.context_async(c).await;
+
+    // Count the stored history once; the feed counts everything received after it, and messages the
+    // server hasn't stamped yet once they are
+    let since = chrono::Utc::now().timestamp_millis() - WATERMARK_LAG.as_millis() as i64;
+    for message in context.fetch::<MessageView>(format!("received_at > 0 AND received_at <= {}", since).as_str()).await? {
+        metrics.count_message(message.room().unwrap_or_default());
+    }
+
+    feed::follow_messages(&context, since, false, |message| {
+        metrics.count_message(message.room().unwrap_or_default());
+        async {}
+    })
+    .await
+}
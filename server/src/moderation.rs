@@ -0,0 +1,61 @@
+use ankurah::{changes::ChangeSet, signals::Subscribe, EntityId, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, Standing, SYSTEM_CONTEXT as c},
+    role, UserView,
+};
+use anyhow::Result;
+use tracing::{info, warn};
+
+/// Keep the policy agent's view of every user's role and ban current, so a ban takes effect
+/// on the banned user's very next write rather than when their identity proof expires.
+pub fn spawn_standing_tracker(node: Node<SledStorageEngine, ChatAgent>, agent: ChatAgent) {
+    tokio::spawn(async move {
+        if let Err(e) = track_standings(&node, agent).await {
+            warn!("Standing tracker stopped: {}", e);
+        }
+    });
+}
+
+async fn track_standings(node: &Node<SledStorageEngine, ChatAgent>, agent: ChatAgent) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    for user in context.fetch::<UserView>("true").await? {
+        agent.set_standing(user.id().to_base64(), Standing::of(&user));
+    }
+
+    let users = context.query::<UserView>("true")?;
+    let _guard = users.subscribe(move |changeset: ChangeSet<UserView>| {
+        for user in changeset.adds().into_iter().chain(changeset.updates()) {
+            agent.set_standing(user.id().to_base64(), Standing::of(&user));
+        }
+    });
+
+    std::future::pending::<()>().await;
+    Ok(())
+}
+
+/// Promote the configured admin users, so a fresh deployment has someone who can moderate.
+/// Admins can then change other users' roles from the message context menu.
+pub async fn promote_admins(node: &Node<SledStorageEngine, ChatAgent>, ids: &[String]) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    for id in ids.iter().map(|id| id.trim()).filter(|id| !id.is_empty()) {
+        let user = match EntityId::from_base64(id) {
+            Ok(entity_id) => context.get::<UserView>(entity_id).await.ok(),
+            Err(_) => None,
+        };
+        let Some(user) = user else {
+            warn!("Configured admin {} is not a known user", id);
+            continue;
+        };
+        if user.is_admin() {
+            continue;
+        }
+        let trx = context.begin();
+        user.edit(&trx)?.role().set(&role::ADMIN.to_string())?;
+        trx.commit().await?;
+        info!("Promoted {} to admin", id);
+    }
+    Ok(())
+}
@@ -0,0 +1,57 @@
+use ankurah::{EntityId, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, Connections, SYSTEM_CONTEXT as c},
+    UserView,
+};
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Keep `User.online` in step with the connections registry: a user comes online when one of their nodes
+/// connects and authenticates, and goes offline when the last one disconnects, however it went away.
+///
+/// Users still marked online from before a restart have no connection anymore, so they are marked offline first.
+pub async fn spawn_presence_tracker(node: Node<SledStorageEngine, ChatAgent>, connections: Connections) -> Result<()> {
+    let context = node.context_async(c).await;
+    let stale = context.fetch::<UserView>("online = true").await?;
+    if !stale.is_empty() {
+        let trx = context.begin();
+        for user in &stale {
+            user.edit(&trx)?.online().set(&false)?;
+        }
+        trx.commit().await?;
+        info!("Marked {} user(s) offline after restart", stale.len());
+    }
+
+    // Changes are written one at a time and each reads the registry when written, so the last write for a
+    // user always matches their current state even when connects and disconnects race
+    let (sender, mut receiver) = mpsc::unbounded_channel::<String>();
+    connections.on_presence_change(move |user| {
+        let _ = sender.send(user.to_string());
+    });
+    tokio::spawn(async move {
+        while let Some(user) = receiver.recv().await {
+            let online = connections.is_online(&user);
+            if let Err(e) = set_presence(&node, &user, online).await {
+                warn!("Failed to update presence of user {}: {}", user, e);
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn set_presence(node: &Node<SledStorageEngine, ChatAgent>, user: &str, online: bool) -> Result<()> {
+    let context = node.context_async(c).await;
+    let user = context.get::<UserView>(EntityId::from_base64(user)?).await?;
+    if user.online().unwrap_or(false) == online {
+        return Ok(());
+    }
+
+    let trx = context.begin();
+    let mutable = user.edit(&trx)?;
+    mutable.online().set(&online)?;
+    mutable.last_seen().set(&chrono::Utc::now().timestamp_millis())?;
+    trx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,245 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use ankurah::{proto::CollectionId, storage::StorageEngine, Context, EntityId, Model, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    search::SearchHit,
+    Attachment, AttachmentView, BlobChunk, BlobChunkView, Message, MessageRevision, MessageRevisionView, MessageView, PinView, RoomView,
+    SearchQuery, SearchQueryView,
+};
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::{config::RetentionConfig, search::erase_query};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const DAY_MS: i64 = 24 * 60 * 60 * 1000;
+
+/// Periodically erase message content that should no longer be kept: messages deleted longer ago
+/// than the grace period, and messages the server received longer ago than their room's retention period.
+///
+/// Ankurah entities can't be removed outright, so a purge empties everything that held the message's
+/// content (its text and mentions, the text of its edit history, its attachments and, unless another
+/// message shares them, the attachment bytes, and any search answer quoting it) and marks it `purged`.
+/// Clients receive the erasure like any other update. The stored event history of everything erased is
+/// then dropped, since those events still carry the content they once wrote.
+pub fn spawn_retention_sweeper(node: Node<SledStorageEngine, ChatAgent>, storage: Arc<SledStorageEngine>, retention: RetentionConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sweep(&node, &storage, &retention).await {
+                warn!("Retention sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Retention in days for a room's messages, if they expire at all.
+fn room_retention_days(retention: &RetentionConfig, room: &RoomView) -> Option<u32> {
+    retention
+        .room_retention_days
+        .get(&room.id().to_base64())
+        .or_else(|| retention.room_retention_days.get(&room.name().unwrap_or_default()))
+        .copied()
+        .unwrap_or(retention.message_retention_days)
+}
+
+async fn sweep(node: &Node<SledStorageEngine, ChatAgent>, storage: &SledStorageEngine, retention: &RetentionConfig) -> Result<()> {
+    let context = node.context_async(c).await;
+    let now = chrono::Utc::now().timestamp_millis();
+
+    // The deletion stamper starts the grace period of deleted messages; stop it for ones the server restored
+    for message in context.fetch::<MessageView>("deleted = false AND deleted_at > 0").await? {
+        let trx = context.begin();
+        message.edit(&trx)?.deleted_at().set(&0)?;
+        trx.commit().await?;
+    }
+
+    let purge_before = now - i64::from(retention.purge_deleted_after_days) * DAY_MS;
+    let mut due = context
+        .fetch::<MessageView>(format!("deleted = true AND purged = false AND deleted_at > 0 AND deleted_at <= {}", purge_before).as_str())
+        .await?;
+    // Senders stamp `timestamp` with their own clock, so messages expire by when the server received them
+    for room in context.fetch::<RoomView>("true").await? {
+        let Some(days) = room_retention_days(retention, &room) else { continue };
+        let expire_before = now - i64::from(days) * DAY_MS;
+        due.extend(
+            context
+                .fetch::<MessageView>(
+                    format!(
+                        "room = '{}' AND purged = false AND received_at > 0 AND received_at < {}",
+                        room.id().to_base64(),
+                        expire_before
+                    )
+                    .as_str(),
+                )
+                .await?,
+        );
+    }
+
+    let mut seen = HashSet::new();
+    let mut purged = 0;
+    for message in due.into_iter().filter(|message| seen.insert(message.id())) {
+        match purge_message(&context, storage, &message, now).await {
+            Ok(()) => purged += 1,
+            Err(e) => warn!("Failed to purge message {}: {}", message.id().to_base64(), e),
+        }
+    }
+    if purged > 0 {
+        info!("Purged {} message(s)", purged);
+    }
+    Ok(())
+}
+
+async fn purge_message(context: &Context, storage: &SledStorageEngine, message: &MessageView, now: i64) -> Result<()> {
+    let id = message.id().to_base64();
+    let trx = context.begin();
+    let mut erased = Erased::default();
+    erased.add(Message::collection(), message.id());
+
+    let mutable = message.edit(&trx)?;
+    mutable.text().replace("")?;
+    mutable.mentions().set(&String::new())?;
+    mutable.deleted().set(&true)?;
+    if message.deleted_at().unwrap_or(0) == 0 {
+        mutable.deleted_at().set(&now)?;
+    }
+    mutable.purged().set(&true)?;
+
+    for revision in context.fetch::<MessageRevisionView>(format!("message = '{}'", id).as_str()).await? {
+        revision.edit(&trx)?.text().set(&String::new())?;
+        erased.add(MessageRevision::collection(), revision.id());
+    }
+    for pin in context.fetch::<PinView>(format!("message = '{}' AND deleted = false", id).as_str()).await? {
+        pin.edit(&trx)?.deleted().set(&true)?;
+    }
+    for attachment in context.fetch::<AttachmentView>(format!("message = '{}'", id).as_str()).await? {
+        let hash = attachment.content_hash().unwrap_or_default();
+        let mutable = attachment.edit(&trx)?;
+        mutable.name().set(&String::new())?;
+        mutable.content_hash().set(&String::new())?;
+        erased.add(Attachment::collection(), attachment.id());
+
+        // Identical files share chunks, so keep them while another message still attaches the same file
+        let shared = context
+            .fetch::<AttachmentView>(format!("content_hash = '{}'", hash).as_str())
+            .await?
+            .iter()
+            .any(|other| other.message().unwrap_or_default() != id);
+        if !hash.is_empty() && !shared {
+            for chunk in context.fetch::<BlobChunkView>(format!("hash = '{}'", hash).as_str()).await? {
+                chunk.edit(&trx)?.data().set(&String::new())?;
+                erased.add(BlobChunk::collection(), chunk.id());
+            }
+        }
+    }
+
+    // Search answers quote a snippet of each hit
+    for query in context.fetch::<SearchQueryView>("status = 'done'").await? {
+        if SearchHit::decode(&query.results().unwrap_or_default()).iter().any(|hit| hit.message == id) {
+            erase_query(&*query.edit(&trx)?)?;
+            erased.add(SearchQuery::collection(), query.id());
+        }
+    }
+
+    trx.commit().await?;
+    erased.forget_history(storage).await
+}
+
+/// The sled tree ankurah-storage-sled keeps the events of every collection in, keyed by event ID. The storage
+/// engine has no API to delete events, and this layout is not part of its API, so `Cargo.toml` pins its exact
+/// version and `purged_history_is_gone_after_reopening` checks it.
+const EVENTS_TREE: &str = "events";
+
+/// Entities a purge erased, by collection.
+#[derive(Default)]
+struct Erased(Vec<(CollectionId, EntityId)>);
+
+impl Erased {
+    fn add(&mut self, collection: CollectionId, id: EntityId) { self.0.push((collection, id)); }
+
+    /// Drop the stored events of the erased entities, keeping only their current, erased state. Ankurah
+    /// keeps every event an entity received, so without this the content would stay on disk in the
+    /// events that wrote it. Nothing can extend an erased entity's history afterwards: purged messages
+    /// can't change, revisions, attachments and chunks never change, and erased queries are never answered.
+    async fn forget_history(&self, storage: &SledStorageEngine) -> Result<()> {
+        let events = storage.database.lock().unwrap().open_tree(EVENTS_TREE)?;
+        for (collection, id) in &self.0 {
+            let stored = storage.collection(collection).await?;
+            for event in stored.dump_entity_events(*id).await? {
+                events.remove(event.payload.id().as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ankurah::Mutable;
+
+    use super::*;
+
+    const SECRET: &[u8] = b"launch codes";
+
+    /// Whether any tree of the store still holds `needle` anywhere in a stored value.
+    fn store_contains(storage: &SledStorageEngine, needle: &[u8]) -> Result<bool> {
+        let database = storage.database.lock().unwrap();
+        for name in database.tree_names() {
+            for item in database.open_tree(name)?.iter() {
+                let (_, value) = item?;
+                if value.windows(needle.len()).any(|window| window == needle) {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    #[tokio::test]
+    async fn purged_history_is_gone_after_reopening() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let id = {
+            let storage = Arc::new(SledStorageEngine::with_path(dir.path().to_path_buf())?);
+            let node = Node::new_durable(storage.clone(), ChatAgent::new());
+            node.system.create().await?;
+            let context = node.context_async(c).await;
+
+            let trx = context.begin();
+            let message = trx
+                .create(&Message {
+                    user: "alice".to_string(),
+                    room: "general".to_string(),
+                    thread: String::new(),
+                    text: "the launch codes are 1234".to_string(),
+                    timestamp: 1,
+                    deleted: false,
+                    edited_at: 0,
+                    mentions: String::new(),
+                    deleted_at: 0,
+                    purged: false,
+                    received_at: 1,
+                    server_id: String::new(),
+                })
+                .await?
+                .read();
+            trx.commit().await?;
+            assert!(store_contains(&storage, SECRET)?);
+
+            purge_message(&context, &storage, &message, 2).await?;
+            let db = storage.database.lock().unwrap().db.clone();
+            db.flush_async().await?;
+            message.id()
+        };
+
+        // Reopening fails while anything still holds the store, so this also checks the node let go of it
+        let storage = SledStorageEngine::with_path(dir.path().to_path_buf())?;
+        assert!(!store_contains(&storage, SECRET)?);
+        assert!(storage.collection(&Message::collection()).await?.dump_entity_events(id).await?.is_empty());
+        let state = storage.collection(&Message::collection()).await?.get_state(id).await?;
+        assert_eq!(state.payload.entity_id, id);
+        Ok(())
+    }
+}
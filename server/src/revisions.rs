@@ -0,0 +1,41 @@
+use ankurah::{EntityId, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    MessageRevision, MessageRevisionView, MessageView,
+};
+use anyhow::Result;
+use tokio::sync::mpsc;
+use tracing::warn;
+
+/// Store the edit history of messages. The policy agent passes on the text each accepted edit replaced
+/// (see [`ChatAgent::with_revision_recorder`]) and it is written here as a revision by the message's author,
+/// so clients can neither skip nor forge it.
+pub fn spawn_revision_recorder(node: Node<SledStorageEngine, ChatAgent>, mut receiver: mpsc::UnboundedReceiver<MessageRevision>) {
+    tokio::spawn(async move {
+        while let Some(revision) = receiver.recv().await {
+            let message = revision.message.clone();
+            if let Err(e) = record(&node, revision).await {
+                warn!("Failed to record a revision of message {}: {}", message, e);
+            }
+        }
+    });
+}
+
+async fn record(node: &Node<SledStorageEngine, ChatAgent>, revision: MessageRevision) -> Result<()> {
+    let context = node.context_async(c).await;
+    // A purge may have overtaken the edit, and a client may send the same edit twice
+    let message = context.get::<MessageView>(EntityId::from_base64(&revision.message)?).await?;
+    if message.purged().unwrap_or(false) {
+        return Ok(());
+    }
+    let predicate = format!("message = '{}' AND timestamp = {}", revision.message, revision.timestamp);
+    if !context.fetch::<MessageRevisionView>(predicate.as_str()).await?.is_empty() {
+        return Ok(());
+    }
+
+    let trx = context.begin();
+    trx.create(&revision).await?;
+    trx.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,567 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use ankurah::{changes::ChangeSet, signals::Subscribe, Context, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, EDIT_MAX_AGE_MS, SYSTEM_CONTEXT as c},
+    search::{query_terms, tokenize, SearchHit},
+    MessageView, SearchQueryMut, SearchQueryView,
+};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use crate::feed::WATERMARK_LAG;
+
+/// Most hits returned for a single query.
+const MAX_HITS: usize = 25;
+/// Most distinct terms the trailing (possibly half-typed) query term expands to.
+const MAX_PREFIX_TERMS: usize = 50;
+/// Bytes of context kept before the first match in a snippet.
+const SNIPPET_LEAD: usize = 40;
+const SNIPPET_LEN: usize = 160;
+/// Answered queries are erased this long after the server answered them. Clients only show the
+/// answer to the query they sent last, so by then nobody is looking at it.
+const QUERY_TTL: Duration = Duration::from_secs(60 * 60);
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// The indexer's watermark is moved up, and saved, once it can move this far.
+const ADVANCE_STEP: Duration = Duration::from_secs(60);
+/// Key of the indexer's watermark in the `meta` tree.
+const CURSOR_KEY: &[u8] = b"cursor";
+
+/// What the index remembers about a message: enough to filter, rank and excerpt hits,
+/// and to remove the message's postings again when it is edited or deleted.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct IndexedMessage {
+    room: String,
+    thread: String,
+    timestamp: i64,
+    text: String,
+}
+
+/// Inverted index over `Message.text`, kept in its own sled database.
+///
+/// - `postings`: `term \0 message_id` → term frequency (u32, big endian)
+/// - `messages`: `message_id` → JSON `IndexedMessage`
+/// - `meta`: `cursor` → the indexer's watermark (i64, big endian); every message changed before it is indexed
+pub struct SearchIndex {
+    postings: sled::Tree,
+    messages: sled::Tree,
+    meta: sled::Tree,
+}
+
+impl SearchIndex {
+    pub fn open_in_homedir(folder: &str) -> Result<Self> {
+        let home = std::env::var_os("HOME").map(PathBuf::from).ok_or_else(|| anyhow!("HOME is not set"))?;
+        Self::open(sled::open(home.join(folder))?)
+    }
+
+    fn open(db: sled::Db) -> Result<Self> {
+        Ok(Self { postings: db.open_tree("postings")?, messages: db.open_tree("messages")?, meta: db.open_tree("meta")? })
+    }
+
+    pub fn message_count(&self) -> usize { self.messages.len() }
+
+    /// Write everything buffered in memory to disk.
+    pub async fn flush(&self) -> Result<()> {
+        self.postings.flush_async().await?;
+        self.messages.flush_async().await?;
+        self.meta.flush_async().await?;
+        Ok(())
+    }
+
+    /// The watermark saved by the indexer, or `None` if the index was never built.
+    fn cursor(&self) -> Result<Option<i64>> {
+        Ok(match self.meta.get(CURSOR_KEY)? {
+            Some(bytes) => Some(i64::from_be_bytes(bytes.as_ref().try_into().map_err(|_| anyhow!("malformed search index cursor"))?)),
+            None => None,
+        })
+    }
+
+    fn set_cursor(&self, cursor: i64) -> Result<()> {
+        self.meta.insert(CURSOR_KEY, &cursor.to_be_bytes())?;
+        Ok(())
+    }
+
+    /// Make the next indexer run read every stored message again, for messages written to the store
+    /// without going through the node (archive imports).
+    pub fn forget_cursor(&self) -> Result<()> {
+        self.meta.remove(CURSOR_KEY)?;
+        Ok(())
+    }
+
+    /// Bring the index up to date with a message. Deleted and purged messages are dropped from the index.
+    pub fn index_message(&self, message: &MessageView) -> Result<()> {
+        let id = message.id().to_base64();
+        if message.deleted().unwrap_or(false) || message.purged().unwrap_or(false) {
+            return self.remove_message(&id);
+        }
+
+        self.index_entry(
+            &id,
+            IndexedMessage {
+                room: message.room().unwrap_or_default(),
+                thread: message.thread().unwrap_or_default(),
+                timestamp: message.timestamp().unwrap_or(0),
+                text: message.text().unwrap_or_default(),
+            },
+        )
+    }
+
+    fn index_entry(&self, id: &str, entry: IndexedMessage) -> Result<()> {
+        let previous = self.load(id)?;
+        if previous.as_ref() == Some(&entry) {
+            return Ok(());
+        }
+        if let Some(previous) = previous {
+            self.remove_postings(id, &previous.text)?;
+        }
+
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for (term, _, _) in tokenize(&entry.text) {
+            *frequencies.entry(term).or_default() += 1;
+        }
+        let mut batch = sled::Batch::default();
+        for (term, frequency) in frequencies {
+            batch.insert(posting_key(&term, id), &frequency.to_be_bytes());
+        }
+        self.postings.apply_batch(batch)?;
+        self.messages.insert(id.as_bytes(), serde_json::to_vec(&entry)?)?;
+        Ok(())
+    }
+
+    pub fn remove_message(&self, id: &str) -> Result<()> {
+        if let Some(previous) = self.load(id)? {
+            self.remove_postings(id, &previous.text)?;
+            self.messages.remove(id.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Find messages containing every query term, best first. The last term also matches as a prefix,
+    /// so results keep up while the user is still typing.
+    pub fn search(&self, query: &str, room: Option<&str>, limit: usize) -> Result<Vec<SearchHit>> {
+        let terms = query_terms(query);
+        let Some(last) = terms.last().cloned() else { return Ok(Vec::new()) };
+
+        let mut scores: Option<HashMap<String, f32>> = None;
+        for term in &terms {
+            let matches = self.score_term(term, *term == last)?;
+            let combined = match scores {
+                None => matches,
+                Some(previous) => previous.into_iter().filter_map(|(id, score)| matches.get(&id).map(|m| (id, score + m))).collect(),
+            };
+            if combined.is_empty() {
+                return Ok(Vec::new());
+            }
+            scores = Some(combined);
+        }
+
+        let mut hits = Vec::new();
+        for (id, score) in scores.unwrap_or_default() {
+            let Some(entry) = self.load(&id)? else { continue };
+            if room.is_some_and(|room| room != entry.room) {
+                continue;
+            }
+            let (snippet, highlights) = snippet(&entry.text, &terms);
+            hits.push(SearchHit {
+                message: id,
+                room: entry.room,
+                thread: entry.thread,
+                timestamp: entry.timestamp,
+                score,
+                snippet,
+                highlights,
+            });
+        }
+        // Equal scores fall back to the newest message first
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then(b.timestamp.cmp(&a.timestamp)));
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// TF-IDF score of each message matching a term. With `prefix`, longer terms match too,
+    /// weighted a little below an exact match; a message scores by its best matching term.
+    fn score_term(&self, term: &str, prefix: bool) -> Result<HashMap<String, f32>> {
+        let scan = if prefix { term.as_bytes().to_vec() } else { posting_key(term, "") };
+        let mut by_term: HashMap<String, Vec<(String, u32)>> = HashMap::new();
+        for item in self.postings.scan_prefix(scan) {
+            let (key, value) = item?;
+            let Some((found, id)) = split_posting_key(&key) else { continue };
+            // Terms are scanned in order, so once a new term doesn't fit, none of the rest will either
+            if !by_term.contains_key(&found) && by_term.len() >= MAX_PREFIX_TERMS {
+                break;
+            }
+            let frequency = u32::from_be_bytes(value.as_ref().try_into().unwrap_or([0, 0, 0, 1]));
+            by_term.entry(found).or_default().push((id, frequency));
+        }
+
+        let total = self.messages.len().max(1) as f32;
+        let mut scores: HashMap<String, f32> = HashMap::new();
+        for (found, postings) in by_term {
+            let idf = (1.0 + total / postings.len() as f32).ln();
+            let weight = if found == term { 1.0 } else { 0.8 };
+            for (id, frequency) in postings {
+                let score = (1.0 + (frequency as f32).ln()) * idf * weight;
+                let best = scores.entry(id).or_default();
+                *best = best.max(score);
+            }
+        }
+        Ok(scores)
+    }
+
+    fn load(&self, id: &str) -> Result<Option<IndexedMessage>> {
+        Ok(match self.messages.get(id.as_bytes())? {
+            Some(bytes) => Some(serde_json::from_slice(&bytes)?),
+            None => None,
+        })
+    }
+
+    fn remove_postings(&self, id: &str, text: &str) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        for (term, _, _) in tokenize(text) {
+            batch.remove(posting_key(&term, id));
+        }
+        self.postings.apply_batch(batch)?;
+        Ok(())
+    }
+}
+
+fn posting_key(term: &str, id: &str) -> Vec<u8> {
+    let mut key = Vec::with_capacity(term.len() + 1 + id.len());
+    key.extend_from_slice(term.as_bytes());
+    key.push(0);
+    key.extend_from_slice(id.as_bytes());
+    key
+}
+
+fn split_posting_key(key: &[u8]) -> Option<(String, String)> {
+    let split = key.iter().position(|&b| b == 0)?;
+    Some((String::from_utf8(key[..split].to_vec()).ok()?, String::from_utf8(key[split + 1..].to_vec()).ok()?))
+}
+
+/// Excerpt of `text` starting shortly before the first matching term, with the byte ranges of every match in it.
+fn snippet(text: &str, terms: &[String]) -> (String, Vec<(usize, usize)>) {
+    let last = terms.last();
+    let matches: Vec<(usize, usize)> = tokenize(text)
+        .into_iter()
+        .filter(|(token, _, _)| terms.contains(token) || last.is_some_and(|last| token.starts_with(last.as_str())))
+        .map(|(_, start, end)| (start, end))
+        .collect();
+
+    let first = matches.first().map(|&(start, _)| start).unwrap_or(0);
+    let mut start = floor_char_boundary(text, first.saturating_sub(SNIPPET_LEAD));
+    // Begin on a word boundary rather than mid-word
+    if start > 0 {
+        if let Some(space) = text[start..first].find(char::is_whitespace) {
+            start += space + 1;
+        }
+    }
+    let end = floor_char_boundary(text, (start + SNIPPET_LEN).min(text.len()));
+
+    let prefix = if start > 0 { "…" } else { "" };
+    let suffix = if end < text.len() { "…" } else { "" };
+    // Keep snippets on one line; replacing newlines with spaces keeps byte offsets intact
+    let snippet = format!("{}{}{}", prefix, text[start..end].replace('\n', " "), suffix);
+    let highlights = matches
+        .into_iter()
+        .filter(|&(s, e)| s >= start && e <= end)
+        .map(|(s, e)| (s - start + prefix.len(), e - start + prefix.len()))
+        .collect();
+    (snippet, highlights)
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Keep the index current by following the messages that arrived, were edited or were deleted after a
+/// watermark, the way [`crate::feed::follow_messages`] follows arrivals.
+///
+/// `received_at` and `deleted_at` are stamped by the server, but `edited_at` comes from the editor's clock,
+/// which the policy holds to within [`EDIT_MAX_AGE_MS`] of the server's; the watermark trails by that much
+/// more. It is saved with the index, so a restart only catches up on what changed since, and the whole
+/// message table is only read when the index is first built.
+pub fn spawn_search_indexer(node: Node<SledStorageEngine, ChatAgent>, index: Arc<SearchIndex>) {
+    tokio::spawn(async move {
+        if let Err(e) = run_indexer(&node, index).await {
+            warn!("Search indexer stopped: {}", e);
+        }
+    });
+}
+
+async fn run_indexer(node: &Node<SledStorageEngine, ChatAgent>, index: Arc<SearchIndex>) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    let mut watermark = match index.cursor()? {
+        // Messages changed while the server was down
+        Some(cursor) => {
+            for message in context.fetch::<MessageView>(changed_after(cursor).as_str()).await? {
+                index_logged(&index, &message);
+            }
+            cursor
+        }
+        // A new index takes in every stored message once
+        None => {
+            let watermark = trailing_now();
+            for message in context.fetch::<MessageView>("true").await? {
+                index_logged(&index, &message);
+            }
+            index.set_cursor(watermark)?;
+            watermark
+        }
+    };
+    info!("Search index holds {} message(s)", index.message_count());
+
+    // Messages only leave the query when the watermark passes them, so removes need no handling
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let messages = context.query::<MessageView>(changed_after(watermark).as_str())?;
+    let _guard = messages.subscribe(move |changeset: ChangeSet<MessageView>| {
+        for message in changeset.adds().into_iter().chain(changeset.updates()) {
+            let _ = sender.send(message);
+        }
+    });
+
+    let mut advance = tokio::time::interval(ADVANCE_STEP);
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => index_logged(&index, &message),
+                None => return Ok(()),
+            },
+            _ = advance.tick() => {
+                let trailing = trailing_now();
+                if trailing - watermark >= ADVANCE_STEP.as_millis() as i64 {
+                    // Everything delivered so far is indexed before the saved watermark passes it
+                    while let Ok(message) = receiver.try_recv() {
+                        index_logged(&index, &message);
+                    }
+                    watermark = trailing;
+                    messages.update_selection(changed_after(watermark).as_str())?;
+                    index.set_cursor(watermark)?;
+                }
+            }
+        }
+    }
+}
+
+fn index_logged(index: &SearchIndex, message: &MessageView) {
+    if let Err(e) = index.index_message(message) {
+        warn!("Failed to index message {}: {}", message.id().to_base64(), e);
+    }
+}
+
+fn trailing_now() -> i64 { chrono::Utc::now().timestamp_millis() - EDIT_MAX_AGE_MS - WATERMARK_LAG.as_millis() as i64 }
+
+fn changed_after(watermark: i64) -> String {
+    format!("received_at > {0} OR edited_at > {0} OR deleted_at > {0}", watermark)
+}
+
+/// Answer `SearchQuery` entities created by clients. This is the query channel: a client creates a pending
+/// query, the server writes the ranked hits back onto it, and the client sees them through its own subscription.
+pub fn spawn_search_responder(node: Node<SledStorageEngine, ChatAgent>, index: Arc<SearchIndex>) {
+    tokio::spawn(async move {
+        if let Err(e) = run_responder(&node, index).await {
+            warn!("Search responder stopped: {}", e);
+        }
+    });
+}
+
+async fn run_responder(node: &Node<SledStorageEngine, ChatAgent>, index: Arc<SearchIndex>) -> Result<()> {
+    let context = node.context_async(c).await;
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+
+    // Queries left pending while the server was down
+    for query in context.fetch::<SearchQueryView>("status = 'pending'").await? {
+        let _ = sender.send(query);
+    }
+
+    let pending = context.query::<SearchQueryView>("status = 'pending'")?;
+    let _guard = pending.subscribe(move |changeset: ChangeSet<SearchQueryView>| {
+        for query in changeset.adds() {
+            let _ = sender.send(query);
+        }
+    });
+
+    while let Some(query) = receiver.recv().await {
+        // The same query can arrive from both the catch-up fetch and the subscription
+        if query.status().unwrap_or_default() != "pending" {
+            continue;
+        }
+        if let Err(e) = answer_query(&context, &index, &query).await {
+            warn!("Failed to answer search query: {}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn answer_query(context: &Context, index: &SearchIndex, query: &SearchQueryView) -> Result<()> {
+    let text = query.query()?;
+    let room = query.room()?;
+    let hits = index.search(&text, Some(room.as_str()).filter(|r| !r.is_empty()), MAX_HITS)?;
+
+    let trx = context.begin();
+    let mutable = query.edit(&trx)?;
+    mutable.results().set(&SearchHit::encode(&hits))?;
+    mutable.status().set(&"done".to_string())?;
+    mutable.answered_at().set(&chrono::Utc::now().timestamp_millis())?;
+    trx.commit().await?;
+    Ok(())
+}
+
+/// Periodically erase answered queries once they are older than `QUERY_TTL`: what someone searched for
+/// and the excerpts of what it matched shouldn't outlive the search.
+pub fn spawn_search_expiry(node: Node<SledStorageEngine, ChatAgent>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(EXPIRY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = expire_queries(&node).await {
+                warn!("Search query expiry failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn expire_queries(node: &Node<SledStorageEngine, ChatAgent>) -> Result<()> {
+    let context = node.context_async(c).await;
+    let before = chrono::Utc::now().timestamp_millis() - QUERY_TTL.as_millis() as i64;
+    let expired = context.fetch::<SearchQueryView>(format!("status = 'done' AND answered_at < {}", before).as_str()).await?;
+    if expired.is_empty() {
+        return Ok(());
+    }
+    let trx = context.begin();
+    for query in &expired {
+        erase_query(&*query.edit(&trx)?)?;
+    }
+    trx.commit().await?;
+    info!("Expired {} search query(s)", expired.len());
+    Ok(())
+}
+
+/// Erase a query's text and results, leaving it `expired`.
+pub fn erase_query(query: &SearchQueryMut) -> Result<()> {
+    query.query().set(&String::new())?;
+    query.results().set(&String::new())?;
+    query.status().set(&"expired".to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SearchIndex { SearchIndex::open(sled::Config::new().temporary(true).open().unwrap()).unwrap() }
+
+    fn add(index: &SearchIndex, id: &str, room: &str, timestamp: i64, text: &str) {
+        let entry = IndexedMessage { room: room.to_string(), thread: String::new(), timestamp, text: text.to_string() };
+        index.index_entry(id, entry).unwrap();
+    }
+
+    fn ids(hits: &[SearchHit]) -> Vec<&str> { hits.iter().map(|hit| hit.message.as_str()).collect() }
+
+    #[test]
+    fn every_term_must_match() {
+        let index = index();
+        add(&index, "a", "general", 1, "The deploy went fine");
+        add(&index, "b", "general", 2, "deploy failed again");
+        add(&index, "c", "general", 3, "lunch?");
+
+        assert_eq!(ids(&index.search("deploy", None, 10).unwrap()), ["b", "a"]);
+        assert_eq!(ids(&index.search("deploy failed", None, 10).unwrap()), ["b"]);
+        assert!(index.search("deploy lunch", None, 10).unwrap().is_empty());
+        assert!(index.search("", None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_last_term_matches_as_a_prefix() {
+        let index = index();
+        add(&index, "a", "general", 1, "deployment notes");
+        add(&index, "b", "general", 2, "deploy now");
+
+        // The exact match outranks the longer word
+        assert_eq!(ids(&index.search("depl", None, 10).unwrap()), ["b", "a"]);
+        assert_eq!(ids(&index.search("deploy", None, 10).unwrap()), ["b", "a"]);
+        assert!(index.search("depl notes", None, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn prefixes_expand_to_a_bounded_number_of_terms() {
+        let index = index();
+        for i in 0..MAX_PREFIX_TERMS + 10 {
+            add(&index, &format!("m{}", i), "general", i as i64, &format!("term{:03}", i));
+        }
+        assert_eq!(index.search("term", None, 1000).unwrap().len(), MAX_PREFIX_TERMS);
+    }
+
+    #[test]
+    fn hits_are_filtered_by_room_and_limited() {
+        let index = index();
+        add(&index, "a", "general", 1, "release");
+        add(&index, "b", "random", 2, "release");
+        add(&index, "c", "general", 3, "release");
+
+        assert_eq!(ids(&index.search("release", Some("general"), 10).unwrap()), ["c", "a"]);
+        assert_eq!(ids(&index.search("release", None, 1).unwrap()), ["c"]);
+    }
+
+    #[test]
+    fn edits_and_removals_update_the_postings() {
+        let index = index();
+        add(&index, "a", "general", 1, "old words");
+        add(&index, "a", "general", 1, "new words");
+        assert!(index.search("old", None, 10).unwrap().is_empty());
+        assert_eq!(ids(&index.search("new", None, 10).unwrap()), ["a"]);
+
+        index.remove_message("a").unwrap();
+        assert!(index.search("words", None, 10).unwrap().is_empty());
+        assert_eq!(index.message_count(), 0);
+        assert_eq!(index.postings.len(), 0);
+    }
+
+    #[test]
+    fn the_cursor_and_postings_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        {
+            let index = SearchIndex::open(sled::open(dir.path()).unwrap()).unwrap();
+            assert_eq!(index.cursor().unwrap(), None);
+            add(&index, "a", "general", 1, "release notes");
+            index.set_cursor(42).unwrap();
+        }
+
+        let index = SearchIndex::open(sled::open(dir.path()).unwrap()).unwrap();
+        assert_eq!(index.cursor().unwrap(), Some(42));
+        assert_eq!(ids(&index.search("release", None, 10).unwrap()), ["a"]);
+    }
+
+    #[test]
+    fn snippets_highlight_matches_within_the_excerpt() {
+        let terms = query_terms("quick fox");
+        let (text, highlights) = snippet("The quick brown fox", &terms);
+        assert_eq!(text, "The quick brown fox");
+        assert_eq!(highlights, [(4, 9), (16, 19)]);
+    }
+
+    #[test]
+    fn long_snippets_start_near_the_first_match_on_a_word_boundary() {
+        let text = format!("{} needle {}", "word ".repeat(40), "tail ".repeat(60));
+        let (excerpt, highlights) = snippet(&text, &query_terms("needle"));
+        assert!(excerpt.starts_with("…word"));
+        assert!(excerpt.ends_with('…'));
+        let &[(start, end)] = highlights.as_slice() else { panic!("expected one highlight: {:?}", highlights) };
+        assert_eq!(&excerpt[start..end], "needle");
+    }
+
+    #[test]
+    fn snippets_respect_char_boundaries_and_newlines() {
+        let text = format!("{}\nmatch", "é".repeat(100));
+        let (excerpt, highlights) = snippet(&text, &query_terms("match"));
+        assert!(!excerpt.contains('\n'));
+        let &[(start, end)] = highlights.as_slice() else { panic!("expected one highlight: {:?}", highlights) };
+        assert_eq!(&excerpt[start..end], "match");
+    }
+}
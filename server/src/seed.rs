@@ -0,0 +1,327 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+use ankurah::{Context, Mutable, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    identity::SERVER_KEY,
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    role, Message, MessageView, Room, RoomView, User, UserView,
+};
+use anyhow::{Context as _, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use serde::Deserialize;
+use tracing::info;
+
+/// Fixtures applied when no fixture file is configured.
+const DEFAULT_FIXTURES: &str = include_str!("../fixtures.toml");
+
+/// Synthetic messages created per transaction.
+const SEED_BATCH: usize = 500;
+/// Share of synthetic messages that reply in the thread of a recent message.
+const THREAD_REPLY_CHANCE: f64 = 0.08;
+
+/// Rooms, users and welcome messages the server should always have.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fixtures {
+    #[serde(default)]
+    pub users: Vec<UserFixture>,
+    #[serde(default)]
+    pub rooms: Vec<RoomFixture>,
+}
+
+/// A user owned by the server: nobody can sign in as it, but it can author fixture messages.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserFixture {
+    /// Stable key the user is found by on later runs, so its display name can change.
+    pub id: String,
+    pub display_name: String,
+    #[serde(default = "member")]
+    pub role: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoomFixture {
+    pub name: String,
+    #[serde(default)]
+    pub topic: String,
+    #[serde(default)]
+    pub description: String,
+    /// Welcome messages, posted in order when missing.
+    #[serde(default)]
+    pub messages: Vec<MessageFixture>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MessageFixture {
+    /// Stable key the message is found by on later runs, so its text can change.
+    pub id: String,
+    /// `id` of one of the fixture users.
+    pub from: String,
+    pub text: String,
+}
+
+fn member() -> String { role::MEMBER.to_string() }
+
+impl Fixtures {
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (text, source) = match path {
+            Some(path) => (std::fs::read_to_string(path).with_context(|| format!("failed to read fixtures {}", path.display()))?, path),
+            None => (DEFAULT_FIXTURES.to_string(), Path::new("built-in fixtures")),
+        };
+        let fixtures: Self = toml::from_str(&text).with_context(|| format!("invalid fixtures in {}", source.display()))?;
+        let mut ids = HashSet::new();
+        for user in &fixtures.users {
+            anyhow::ensure!(!user.id.is_empty(), "fixture user {:?} has an empty id", user.display_name);
+            anyhow::ensure!(ids.insert(user.id.as_str()), "two fixture users have the id {:?}", user.id);
+        }
+        let mut message_ids = HashSet::new();
+        for message in fixtures.rooms.iter().flat_map(|room| &room.messages) {
+            anyhow::ensure!(ids.contains(message.from.as_str()), "fixture message from {:?} names no fixture user", message.from);
+            anyhow::ensure!(!message.id.is_empty(), "fixture message {:?} has an empty id", message.text);
+            anyhow::ensure!(message_ids.insert(message.id.as_str()), "two fixture messages have the id {:?}", message.id);
+        }
+        Ok(fixtures)
+    }
+}
+
+/// Quote a string for use in a query predicate.
+fn quoted(value: &str) -> String { format!("'{}'", value.replace('\'', "''")) }
+
+/// Create whatever the fixtures describe that the store doesn't have yet. Existing rooms are matched by
+/// name, and users and messages by `id`, and left as they are, so this is safe to run on every startup.
+pub async fn apply(node: &Node<SledStorageEngine, ChatAgent>, fixtures: &Fixtures) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    let mut user_ids = HashMap::new();
+    for user in &fixtures.users {
+        let id = ensure_user(&context, &format!("fixture:{}", user.id), &user.display_name, &user.role).await?;
+        user_ids.insert(user.id.as_str(), id);
+    }
+
+    for room in &fixtures.rooms {
+        let room_id = ensure_room(&context, room).await?;
+        if room.messages.is_empty() {
+            continue;
+        }
+
+        // Only fixture users post welcome messages, so this never reads the room's whole history
+        let authors = room.messages.iter().map(|fixture| format!("user = {}", quoted(&user_ids[fixture.from.as_str()]))).collect::<Vec<_>>();
+        let predicate = format!("room = {} AND ({})", quoted(&room_id), authors.join(" OR "));
+        let posted = context.fetch::<MessageView>(predicate.as_str()).await?;
+
+        for fixture in &room.messages {
+            let server_id = format!("fixture:{}", fixture.id);
+            let user_id = &user_ids[fixture.from.as_str()];
+            if posted.iter().any(|message| message.server_id().unwrap_or_default() == server_id) {
+                continue;
+            }
+            // Welcome messages posted before they had IDs are recognised by author and text once
+            let unkeyed = posted.iter().find(|message| {
+                message.server_id().unwrap_or_default().is_empty()
+                    && message.user().unwrap_or_default() == *user_id
+                    && message.text().unwrap_or_default() == fixture.text
+            });
+            let trx = context.begin();
+            match unkeyed {
+                Some(message) => message.edit(&trx)?.server_id().set(&server_id)?,
+                None => {
+                    let now = chrono::Utc::now().timestamp_millis();
+                    trx.create(&Message {
+                        user: user_id.clone(),
+                        room: room_id.clone(),
+                        thread: String::new(),
+                        text: fixture.text.clone(),
+                        timestamp: now,
+                        deleted: false,
+                        edited_at: 0,
+                        mentions: String::new(),
+                        deleted_at: 0,
+                        purged: false,
+                        received_at: now,
+                        server_id,
+                    })
+                    .await?;
+                    info!("Posted welcome message {} from {} in '{}'", fixture.id, fixture.from, room.name);
+                }
+            }
+            trx.commit().await?;
+        }
+    }
+
+    Ok(())
+}
+
+async fn ensure_room(context: &Context, room: &RoomFixture) -> Result<String> {
+    if let Some(existing) = context.fetch::<RoomView>(format!("name = {}", quoted(&room.name)).as_str()).await?.first() {
+        return Ok(existing.id().to_base64());
+    }
+
+    let trx = context.begin();
+    let created = trx
+        .create(&Room { name: room.name.clone(), topic: room.topic.clone(), description: room.description.clone() })
+        .await?
+        .read();
+    trx.commit().await?;
+    info!("'{}' room created", room.name);
+    Ok(created.id().to_base64())
+}
+
+/// Find the server-owned user with `server_id`, or create it as `display_name`. Nobody can sign in as
+/// it, and clients can neither register a user with `SERVER_KEY` nor set a `server_id`, so no one else's
+/// user is ever picked up here.
+pub async fn ensure_user(context: &Context, server_id: &str, display_name: &str, role: &str) -> Result<String> {
+    let predicate = format!("public_key = {} AND server_id = {}", quoted(SERVER_KEY), quoted(server_id));
+    if let Some(existing) = context.fetch::<UserView>(predicate.as_str()).await?.first() {
+        return Ok(existing.id().to_base64());
+    }
+
+    let trx = context.begin();
+    let created = trx
+        .create(&User {
+            display_name: display_name.to_string(),
+            online: false,
+            last_seen: 0,
+            public_key: SERVER_KEY.to_string(),
+            role: role.to_string(),
+            banned: false,
+            server_id: server_id.to_string(),
+        })
+        .await?
+        .read();
+    trx.commit().await?;
+    info!("User '{}' created as {}", display_name, server_id);
+    Ok(created.id().to_base64())
+}
+
+const NAMES: &[&str] = &["Ada", "Bea", "Cyril", "Dana", "Emeka", "Farah", "Gus", "Hiro", "Ines", "Jonas", "Kemi", "Lars", "Mei", "Nico"];
+const OPENERS: &[&str] =
+    &["I think", "Looks like", "Heads up:", "FYI", "Not sure, but", "Quick update:", "As far as I can tell", "Honestly"];
+const SUBJECTS: &[&str] = &[
+    "the deploy",
+    "the build",
+    "the staging box",
+    "the search index",
+    "the release notes",
+    "the migration",
+    "the websocket reconnect",
+    "scroll pagination",
+    "the new room",
+    "yesterday's incident",
+];
+const ENDINGS: &[&str] = &[
+    "is flaky again",
+    "looks good to me",
+    "needs another look",
+    "is done",
+    "broke on CI",
+    "should land today",
+    "is ready for review",
+    "still times out",
+    "works on my machine",
+    "can wait until Monday",
+];
+const QUESTIONS: &[&str] = &["Has anyone looked at", "Who owns", "Can someone check", "Any news on", "Should we roll back"];
+const REACTIONS: &[&str] = &["+1", "nice!", "on it", "thanks!", "agreed", "lol", "👍", "same here", "good catch"];
+
+fn synthetic_text(rng: &mut StdRng) -> String {
+    if rng.gen_bool(0.2) {
+        return REACTIONS.choose(rng).unwrap().to_string();
+    }
+    if rng.gen_bool(0.2) {
+        return format!("{} {}?", QUESTIONS.choose(rng).unwrap(), SUBJECTS.choose(rng).unwrap());
+    }
+    let sentences = rng.gen_range(1..=3);
+    let separator = if rng.gen_bool(0.15) { "\n" } else { " " };
+    (0..sentences)
+        .map(|_| format!("{} {} {}.", OPENERS.choose(rng).unwrap(), SUBJECTS.choose(rng).unwrap(), ENDINGS.choose(rng).unwrap()))
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+/// Milliseconds between consecutive synthetic messages: mostly a lively conversation, with the odd
+/// quiet afternoon or overnight gap.
+fn synthetic_gap(rng: &mut StdRng) -> i64 {
+    match rng.gen_range(0..100) {
+        0..=79 => rng.gen_range(2_000..90_000),
+        80..=97 => rng.gen_range(5 * 60_000..60 * 60_000),
+        _ => rng.gen_range(6 * 3_600_000..16 * 3_600_000),
+    }
+}
+
+/// Add `count` messages of synthetic conversation to `room_name`, ending now, from `authors`
+/// synthetic users. Authors post in short bursts and sometimes reply in threads.
+pub async fn generate_history(node: &Node<SledStorageEngine, ChatAgent>, room_name: &str, count: usize, authors: usize) -> Result<()> {
+    let context = node.context_async(c).await;
+    let room = context
+        .fetch::<RoomView>(format!("name = {}", quoted(room_name)).as_str())
+        .await?
+        .first()
+        .map(|room| room.id().to_base64())
+        .with_context(|| format!("no room named '{}'; add it to the fixtures", room_name))?;
+
+    let mut rng = StdRng::from_entropy();
+    let mut users = Vec::new();
+    for i in 0..authors.max(1) {
+        let name = format!("{} (synthetic {})", NAMES[i % NAMES.len()], i + 1);
+        users.push(ensure_user(&context, &format!("synthetic:{}", i + 1), &name, role::MEMBER).await?);
+    }
+
+    // Lay the timestamps out backwards from now so the newest message is the latest one
+    let mut timestamp = chrono::Utc::now().timestamp_millis();
+    let mut timestamps = Vec::with_capacity(count);
+    for _ in 0..count {
+        timestamps.push(timestamp);
+        timestamp -= synthetic_gap(&mut rng);
+    }
+    timestamps.reverse();
+
+    let mut author = 0;
+    let mut recent_roots: Vec<String> = Vec::new();
+    for (batch, chunk) in timestamps.chunks(SEED_BATCH).enumerate() {
+        let trx = context.begin();
+        for &timestamp in chunk {
+            if rng.gen_bool(0.4) {
+                author = rng.gen_range(0..users.len());
+            }
+            let thread = match recent_roots.choose(&mut rng) {
+                Some(root) if rng.gen_bool(THREAD_REPLY_CHANCE) => root.clone(),
+                _ => String::new(),
+            };
+            let message = trx
+                .create(&Message {
+                    user: users[author].clone(),
+                    room: room.clone(),
+                    thread: thread.clone(),
+                    text: synthetic_text(&mut rng),
+                    timestamp,
+                    deleted: false,
+                    edited_at: 0,
+                    mentions: String::new(),
+                    deleted_at: 0,
+                    purged: false,
+                    // As if the server had received the history as it happened, so retention applies to it
+                    received_at: timestamp,
+                    server_id: String::new(),
+                })
+                .await?
+                .read();
+            if thread.is_empty() {
+                recent_roots.push(message.id().to_base64());
+                if recent_roots.len() > 20 {
+                    recent_roots.remove(0);
+                }
+            }
+        }
+        trx.commit().await?;
+        info!("Seeded {} of {} messages in '{}'", (batch * SEED_BATCH + chunk.len()).min(count), count, room_name);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::metrics::Metrics;
+
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Resolve on the first SIGINT (Ctrl-C) or SIGTERM.
+pub async fn signal_received() -> std::io::Result<()> {
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        _ = interrupt.recv() => info!("Received SIGINT"),
+        _ = terminate.recv() => info!("Received SIGTERM"),
+    }
+    Ok(())
+}
+
+/// Broadcasts the start of shutdown to the listener and every open connection.
+pub fn channel() -> (watch::Sender<bool>, watch::Receiver<bool>) { watch::channel(false) }
+
+/// Wait for a shutdown broadcast on `receiver`.
+pub async fn requested(receiver: &mut watch::Receiver<bool>) {
+    // An error means the sender is gone, which only happens once the server is exiting anyway
+    let _ = receiver.wait_for(|stopping| *stopping).await;
+}
+
+/// Wait until every WebSocket connection has finished draining, or `timeout` has passed.
+pub async fn drain(metrics: &Metrics, timeout: Duration) {
+    let deadline = Instant::now() + timeout;
+    while metrics.websocket_connections() > 0 {
+        if Instant::now() >= deadline {
+            warn!("{} connection(s) still open after {:?}; closing them", metrics.websocket_connections(), timeout);
+            return;
+        }
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,27 @@
+use std::{fs::File, io::BufReader, sync::Arc};
+
+use anyhow::{Context as _, Result};
+use tokio_rustls::{
+    rustls::{crypto::ring, ServerConfig},
+    TlsAcceptor,
+};
+use tracing::info;
+
+use crate::config::TlsConfig;
+
+/// Build a TLS acceptor from the configured PEM certificate chain and private key.
+pub fn acceptor(tls: &TlsConfig) -> Result<TlsAcceptor> {
+    let mut cert_reader = BufReader::new(File::open(&tls.cert).with_context(|| format!("failed to open {}", tls.cert.display()))?);
+    let certs = rustls_pemfile::certs(&mut cert_reader).collect::<Result<Vec<_>, _>>()?;
+    anyhow::ensure!(!certs.is_empty(), "no certificates in {}", tls.cert.display());
+
+    let mut key_reader = BufReader::new(File::open(&tls.key).with_context(|| format!("failed to open {}", tls.key.display()))?);
+    let key = rustls_pemfile::private_key(&mut key_reader)?.with_context(|| format!("no private key in {}", tls.key.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    info!("Loaded TLS certificate from {}", tls.cert.display());
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
@@ -0,0 +1,45 @@
+use std::time::Duration;
+
+use ankurah::Node;
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    policy::{ChatAgent, SYSTEM_CONTEXT as c},
+    TypingView,
+};
+use anyhow::Result;
+use tracing::{debug, warn};
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Periodically deactivate typing indicators whose `expires_at` has passed, so a crashed
+/// or disconnected tab never leaves "is typing…" behind.
+pub fn spawn_typing_sweeper(node: Node<SledStorageEngine, ChatAgent>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = sweep_expired_typing(&node).await {
+                warn!("Typing sweep failed: {}", e);
+            }
+        }
+    });
+}
+
+async fn sweep_expired_typing(node: &Node<SledStorageEngine, ChatAgent>) -> Result<()> {
+    let context = node.context_async(c).await;
+
+    let now = chrono::Utc::now().timestamp_millis();
+    let expired = context.fetch::<TypingView>(format!("active = true AND expires_at < {}", now).as_str()).await?;
+    if expired.is_empty() {
+        return Ok(());
+    }
+
+    let trx = context.begin();
+    for typing in &expired {
+        typing.edit(&trx)?.active().set(&false)?;
+    }
+    trx.commit().await?;
+
+    debug!("Expired {} typing indicator(s)", expired.len());
+    Ok(())
+}
@@ -0,0 +1,234 @@
+//! Checks the WebSocket traffic the public listener forwards to the Ankurah server.
+//!
+//! The listener registers a connection under the node ID a client names in its URL, but the Ankurah
+//! server learns the client's node from the messages it sends. [`NodeGuard`] reads the client's frames
+//! on their way through and makes sure every message is sent as the node the connection was
+//! registered for, so a client can't register one node and then speak as another.
+
+use ankurah::{proto, EntityId};
+use anyhow::{Context as _, Result};
+
+/// Largest message a client may send, the WebSocket server's own default limit.
+const MAX_MESSAGE_BYTES: usize = 64 << 20;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Passes on a client's frames once the messages they carry have been checked against its node ID.
+/// Frames of a fragmented message are held back until the whole message has arrived.
+pub struct NodeGuard {
+    node: EntityId,
+    /// Bytes read from the client that don't make up a whole frame yet.
+    pending: Vec<u8>,
+    /// The frames of the fragmented message in progress, as received.
+    held: Vec<u8>,
+    /// The unmasked payload of the fragmented message in progress.
+    message: Vec<u8>,
+    fragmented: bool,
+}
+
+/// The header of a client frame.
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    mask: [u8; 4],
+    header_len: usize,
+    payload_len: usize,
+}
+
+impl Frame {
+    /// Parse the header at the start of `bytes`, or return `None` if the frame isn't complete yet.
+    fn parse(bytes: &[u8]) -> Result<Option<Self>> {
+        if bytes.len() < 2 {
+            return Ok(None);
+        }
+        anyhow::ensure!(bytes[0] & 0x70 == 0, "frame uses an extension");
+        anyhow::ensure!(bytes[1] & 0x80 != 0, "client frame is not masked");
+        let (payload_len, len_bytes) = match bytes[1] & 0x7F {
+            126 if bytes.len() >= 4 => (u16::from_be_bytes([bytes[2], bytes[3]]) as u64, 2),
+            127 if bytes.len() >= 10 => (u64::from_be_bytes(bytes[2..10].try_into()?), 8),
+            126 | 127 => return Ok(None),
+            len => (len as u64, 0),
+        };
+        anyhow::ensure!(payload_len <= MAX_MESSAGE_BYTES as u64, "message too large");
+        let header_len = 2 + len_bytes + 4;
+        if bytes.len() < header_len + payload_len as usize {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            fin: bytes[0] & 0x80 != 0,
+            opcode: bytes[0] & 0x0F,
+            mask: bytes[header_len - 4..header_len].try_into()?,
+            header_len,
+            payload_len: payload_len as usize,
+        }))
+    }
+
+    fn len(&self) -> usize { self.header_len + self.payload_len }
+
+    fn payload(&self, bytes: &[u8]) -> Vec<u8> {
+        bytes[self.header_len..self.len()].iter().enumerate().map(|(i, byte)| byte ^ self.mask[i % 4]).collect()
+    }
+}
+
+impl NodeGuard {
+    pub fn new(node: EntityId) -> Self { Self { node, pending: Vec::new(), held: Vec::new(), message: Vec::new(), fragmented: false } }
+
+    /// Take bytes read from the client and return those that may be passed on. Fails if the client sent
+    /// a message as another node, or anything the guard can't check.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<Vec<u8>> {
+        self.pending.extend_from_slice(bytes);
+        let mut passed = Vec::new();
+        let mut start = 0;
+        while let Some(frame) = Frame::parse(&self.pending[start..])? {
+            let raw = &self.pending[start..start + frame.len()];
+            match frame.opcode {
+                OPCODE_CLOSE | OPCODE_PING | OPCODE_PONG => passed.extend_from_slice(raw),
+                OPCODE_TEXT | OPCODE_BINARY if !self.fragmented && frame.fin => {
+                    self.check(&frame.payload(raw))?;
+                    passed.extend_from_slice(raw);
+                }
+                OPCODE_TEXT | OPCODE_BINARY if !self.fragmented => {
+                    self.fragmented = true;
+                    self.held.extend_from_slice(raw);
+                    self.message = frame.payload(raw);
+                }
+                OPCODE_CONTINUATION if self.fragmented => {
+                    self.held.extend_from_slice(raw);
+                    self.message.extend(frame.payload(raw));
+                    anyhow::ensure!(self.message.len() <= MAX_MESSAGE_BYTES, "message too large");
+                    if frame.fin {
+                        self.check(&self.message)?;
+                        passed.append(&mut self.held);
+                        self.message.clear();
+                        self.fragmented = false;
+                    }
+                }
+                opcode => anyhow::bail!("unexpected frame with opcode {}", opcode),
+            }
+            start += frame.len();
+        }
+        self.pending.drain(..start);
+        Ok(passed)
+    }
+
+    fn check(&self, payload: &[u8]) -> Result<()> {
+        let message: proto::Message = bincode::deserialize(payload).context("message is not an Ankurah message")?;
+        let sender = sender(&message);
+        anyhow::ensure!(sender == self.node, "node {} sent a message as node {}", self.node, sender);
+        Ok(())
+    }
+}
+
+/// The node a message says it is from.
+fn sender(message: &proto::Message) -> EntityId {
+    match message {
+        proto::Message::Presence(presence) => presence.node_id,
+        proto::Message::PeerMessage(message) => match message {
+            proto::NodeMessage::Request { request, .. } => request.from,
+            proto::NodeMessage::Response(response) => response.from,
+            proto::NodeMessage::Update(update) => update.from,
+            proto::NodeMessage::UpdateAck(ack) => ack.from,
+            proto::NodeMessage::UnsubscribeQuery { from, .. } => *from,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MASK: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+    fn frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&MASK);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ MASK[i % 4]));
+        frame
+    }
+
+    fn presence(node: EntityId) -> Vec<u8> {
+        bincode::serialize(&proto::Message::Presence(proto::Presence { node_id: node, durable: false, system_root: None })).unwrap()
+    }
+
+    fn unsubscribe(node: EntityId) -> Vec<u8> {
+        let message = proto::NodeMessage::UnsubscribeQuery { from: node, query_id: proto::QueryId::new() };
+        bincode::serialize(&proto::Message::PeerMessage(message)).unwrap()
+    }
+
+    #[test]
+    fn messages_from_the_registered_node_pass_unchanged() {
+        let node = EntityId::new();
+        let mut guard = NodeGuard::new(node);
+        let bytes = [frame(true, OPCODE_BINARY, &presence(node)), frame(true, OPCODE_PING, b"ping"), frame(true, OPCODE_BINARY, &unsubscribe(node))]
+            .concat();
+
+        // Frames split across reads are passed on once complete
+        let (first, rest) = bytes.split_at(5);
+        let mut passed = guard.push(first).unwrap();
+        assert!(passed.is_empty());
+        passed.extend(guard.push(rest).unwrap());
+        assert_eq!(passed, bytes);
+    }
+
+    #[test]
+    fn messages_sent_as_another_node_are_refused() {
+        let mut guard = NodeGuard::new(EntityId::new());
+        assert!(guard.push(&frame(true, OPCODE_BINARY, &presence(EntityId::new()))).is_err());
+
+        let node = EntityId::new();
+        let mut guard = NodeGuard::new(node);
+        guard.push(&frame(true, OPCODE_BINARY, &presence(node))).unwrap();
+        assert!(guard.push(&frame(true, OPCODE_BINARY, &unsubscribe(EntityId::new()))).is_err());
+    }
+
+    #[test]
+    fn fragmented_messages_are_held_until_they_can_be_checked() {
+        let node = EntityId::new();
+        let payload = presence(node);
+        let (head, tail) = payload.split_at(payload.len() / 2);
+        let mut guard = NodeGuard::new(node);
+        assert!(guard.push(&frame(false, OPCODE_BINARY, head)).unwrap().is_empty());
+        let pong = frame(true, OPCODE_PONG, b"");
+        assert_eq!(guard.push(&pong).unwrap(), pong);
+        assert_eq!(guard.push(&frame(true, OPCODE_CONTINUATION, tail)).unwrap(), [frame(false, OPCODE_BINARY, head), frame(true, OPCODE_CONTINUATION, tail)].concat());
+
+        let payload = presence(EntityId::new());
+        let (head, tail) = payload.split_at(payload.len() / 2);
+        let mut guard = NodeGuard::new(node);
+        guard.push(&frame(false, OPCODE_BINARY, head)).unwrap();
+        assert!(guard.push(&frame(true, OPCODE_CONTINUATION, tail)).is_err());
+    }
+
+    #[test]
+    fn frames_the_guard_cannot_check_are_refused() {
+        let node = EntityId::new();
+        // Not an Ankurah message
+        assert!(NodeGuard::new(node).push(&frame(true, OPCODE_TEXT, b"hello")).is_err());
+        // Compressed by an extension
+        let mut compressed = frame(true, OPCODE_BINARY, &presence(node));
+        compressed[0] |= 0x40;
+        assert!(NodeGuard::new(node).push(&compressed).is_err());
+        // Unmasked
+        let mut unmasked = frame(true, OPCODE_BINARY, &presence(node));
+        unmasked[1] &= 0x7F;
+        assert!(NodeGuard::new(node).push(&unmasked).is_err());
+        // A continuation without a message to continue
+        assert!(NodeGuard::new(node).push(&frame(true, OPCODE_CONTINUATION, &presence(node))).is_err());
+    }
+}
@@ -0,0 +1,133 @@
+//! Exports a store, sanitizes a message in the archive, imports it into another store and exports that
+//! again, then checks that everything came across and that the original text went nowhere.
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+
+const SECRET: &str = "the vault code is tangerine-4417";
+const REDACTED: &str = "[redacted]";
+
+const FIXTURES: &str = r#"
+[[users]]
+id = "archivist"
+display_name = "Archivist"
+
+[[rooms]]
+name = "General"
+
+[[rooms.messages]]
+id = "vault-code"
+from = "archivist"
+text = "the vault code is tangerine-4417"
+"#;
+
+/// Run the server binary with `args` against `storage`, which is relative to `home` or absolute.
+fn run(home: &Path, storage: &Path, fixtures: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new(env!("CARGO_BIN_EXE_ankurah-template-server"))
+        .env("HOME", home)
+        .env_remove("ANKURAH_CONFIG")
+        .args(["--storage-folder", storage.to_str().context("non-UTF-8 temp dir")?])
+        .args(["--fixtures", fixtures.to_str().context("non-UTF-8 temp dir")?])
+        .args(["--log-level", "warn"])
+        .args(args)
+        .status()
+        .context("failed to run the server")?;
+    anyhow::ensure!(status.success(), "{:?} exited with {}", args, status);
+    Ok(())
+}
+
+fn read_records(path: &Path) -> Result<Vec<Value>> {
+    let text = std::fs::read_to_string(path)?;
+    text.lines().filter(|line| !line.trim().is_empty()).map(|line| Ok(serde_json::from_str(line)?)).collect()
+}
+
+fn write_records(path: &Path, records: &[Value]) -> Result<()> {
+    let lines: Vec<String> = records.iter().map(serde_json::to_string).collect::<Result<_, _>>()?;
+    Ok(std::fs::write(path, lines.join("\n") + "\n")?)
+}
+
+/// Readable fields of each record, by collection and ID.
+fn fields(records: &[Value]) -> BTreeMap<(String, String), Value> {
+    records.iter().map(|record| ((record["collection"].to_string(), record["id"].to_string()), record["fields"].clone())).collect()
+}
+
+fn files_containing(dir: &Path, needle: &[u8]) -> Vec<PathBuf> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            found.extend(files_containing(&path, needle));
+        } else if std::fs::read(&path).is_ok_and(|bytes| bytes.windows(needle.len()).any(|window| window == needle)) {
+            found.push(path);
+        }
+    }
+    found
+}
+
+#[test]
+fn sanitized_archives_import_without_the_original_text() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let fixtures = dir.path().join("fixtures.toml");
+    std::fs::write(&fixtures, FIXTURES)?;
+    let (source, target) = (dir.path().join("source"), dir.path().join("target"));
+    let archives = dir.path().join("archives");
+    std::fs::create_dir(&archives)?;
+
+    run(dir.path(), &source, &fixtures, &["seed", "--messages", "50", "--authors", "3"])?;
+    let exported = archives.join("exported.jsonl");
+    run(dir.path(), &source, &fixtures, &["export", exported.to_str().unwrap()])?;
+
+    let mut records = read_records(&exported)?;
+    assert!(records.iter().all(|record| record.get("events").is_none()), "archives carry no event history");
+    let secret = records.iter_mut().find(|record| record["fields"]["text"] == SECRET).context("the fixture message was not exported")?;
+    secret["fields"]["text"] = Value::from(REDACTED);
+    let sanitized = archives.join("sanitized.jsonl");
+    write_records(&sanitized, &records)?;
+
+    // Importing twice changes nothing the second time
+    run(dir.path(), &target, &fixtures, &["import", sanitized.to_str().unwrap()])?;
+    run(dir.path(), &target, &fixtures, &["import", sanitized.to_str().unwrap()])?;
+    let reexported = archives.join("reexported.jsonl");
+    run(dir.path(), &target, &fixtures, &["export", reexported.to_str().unwrap()])?;
+
+    assert_eq!(fields(&read_records(&reexported)?), fields(&records));
+    assert!(!std::fs::read_to_string(&reexported)?.contains(SECRET));
+    assert_eq!(files_containing(&target, SECRET.as_bytes()), Vec::<PathBuf>::new());
+    Ok(())
+}
+
+#[test]
+fn server_ids_and_fields_missing_from_older_archives_are_imported() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let fixtures = dir.path().join("fixtures.toml");
+    std::fs::write(&fixtures, FIXTURES)?;
+    let (source, target) = (dir.path().join("source"), dir.path().join("target"));
+    let exported = dir.path().join("exported.jsonl");
+    run(dir.path(), &source, &fixtures, &["seed", "--messages", "0"])?;
+    run(dir.path(), &source, &fixtures, &["export", exported.to_str().unwrap()])?;
+
+    let mut records = read_records(&exported)?;
+    let user = records.iter_mut().find(|record| record["fields"]["server_id"] == "fixture:archivist").context("the fixture user was not exported")?;
+    user["fields"]["server_id"] = Value::from("fixture:curator");
+    // Archives from before `received_at` existed don't have it
+    let message = records.iter_mut().find(|record| record["fields"]["text"] == SECRET).context("the fixture message was not exported")?;
+    message["fields"].as_object_mut().context("fields are not an object")?.remove("received_at");
+    let edited = dir.path().join("edited.jsonl");
+    write_records(&edited, &records)?;
+
+    run(dir.path(), &target, &fixtures, &["import", edited.to_str().unwrap()])?;
+    let reexported = dir.path().join("reexported.jsonl");
+    run(dir.path(), &target, &fixtures, &["export", reexported.to_str().unwrap()])?;
+
+    let records = read_records(&reexported)?;
+    assert!(records.iter().any(|record| record["fields"]["server_id"] == "fixture:curator"));
+    let message = records.iter().find(|record| record["fields"]["text"] == SECRET).context("the fixture message was not imported")?;
+    assert_eq!(message["fields"]["received_at"], 0);
+    Ok(())
+}
@@ -0,0 +1,165 @@
+//! Terminates a running server while a client is writing messages, then checks that every message
+//! the server acknowledged is still in its store.
+#![cfg(unix)]
+
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    process::{Child, Command, ExitStatus},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use ankurah::{Context, EntityId, Mutable, Node};
+use ankurah_storage_sled::SledStorageEngine;
+use ankurah_template_model::{
+    identity::DeviceKey,
+    policy::{ChatAgent, Identity, ANONYMOUS_CONTEXT, SYSTEM_CONTEXT},
+    role, Message, MessageView, User,
+};
+use ankurah_websocket_client::WebsocketClient;
+use anyhow::{Context as _, Result};
+
+/// Acknowledged messages to wait for before sending SIGTERM, so the signal lands mid-stream.
+const MESSAGES_BEFORE_SIGTERM: usize = 20;
+const MAX_MESSAGES: usize = 10_000;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const EXIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn free_port() -> Result<u16> { Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port()) }
+
+fn spawn_server(home: &Path, storage: &Path, port: u16) -> Result<Child> {
+    Command::new(env!("CARGO_BIN_EXE_ankurah-template-server"))
+        .env("HOME", home)
+        .env_remove("ANKURAH_CONFIG")
+        .args(["--bind", &format!("127.0.0.1:{}", port)])
+        .args(["--storage-folder", storage.to_str().context("non-UTF-8 temp dir")?])
+        .args(["--log-level", "warn"])
+        // The writer sends as fast as it can; keep the rate limit out of the way
+        .args(["--message-burst", &MAX_MESSAGES.to_string()])
+        .args(["--shutdown-timeout", "5"])
+        .spawn()
+        .context("failed to start the server")
+}
+
+fn is_healthy(port: u16) -> bool {
+    let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) else { return false };
+    let mut response = String::new();
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").is_ok()
+        && stream.read_to_string(&mut response).is_ok()
+        && response.starts_with("HTTP/1.1 200")
+}
+
+async fn wait_until_healthy(port: u16) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while !is_healthy(port) {
+        anyhow::ensure!(tokio::time::Instant::now() < deadline, "server did not become healthy");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+/// Register a user the way the app does and return a context acting as them.
+async fn sign_in(node: &Node<SledStorageEngine, ChatAgent>, agent: &ChatAgent) -> Result<(Context, String)> {
+    let key = DeviceKey::generate()?;
+    let trx = node.context(ANONYMOUS_CONTEXT)?.begin();
+    let user = trx
+        .create(&User {
+            display_name: "Shutdown test".to_string(),
+            online: false,
+            last_seen: 0,
+            public_key: key.public_key(),
+            role: role::MEMBER.to_string(),
+            banned: false,
+            server_id: String::new(),
+        })
+        .await?
+        .read();
+    trx.commit().await?;
+
+    let user_id = user.id().to_base64();
+    agent.set_signer(user_id.clone(), key);
+    Ok((node.context(Identity::User { id: user_id.clone(), connection: None })?, user_id))
+}
+
+/// Send messages one at a time until the server stops accepting them, recording each acknowledged one.
+async fn write_until_refused(context: Context, user_id: String, committed: Arc<Mutex<Vec<EntityId>>>) -> Result<()> {
+    for i in 0..MAX_MESSAGES {
+        let trx = context.begin();
+        let message = trx
+            .create(&Message {
+                user: user_id.clone(),
+                room: "shutdown-test".to_string(),
+                thread: String::new(),
+                text: format!("message {}", i),
+                timestamp: chrono::Utc::now().timestamp_millis(),
+                deleted: false,
+                edited_at: 0,
+                mentions: String::new(),
+                deleted_at: 0,
+                purged: false,
+                received_at: 0,
+                server_id: String::new(),
+            })
+            .await?
+            .read();
+        if trx.commit().await.is_err() {
+            break;
+        }
+        committed.lock().unwrap().push(message.id());
+    }
+    Ok(())
+}
+
+async fn wait_for_exit(mut server: Child) -> Result<ExitStatus> {
+    let exit = tokio::task::spawn_blocking(move || server.wait());
+    Ok(tokio::time::timeout(EXIT_TIMEOUT, exit).await.context("server did not exit after SIGTERM")???)
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn sigterm_mid_write_loses_no_committed_message() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let server_storage = dir.path().join("server");
+    let port = free_port()?;
+    let server = spawn_server(dir.path(), &server_storage, port)?;
+    wait_until_healthy(port).await?;
+
+    let agent = ChatAgent::new();
+    let client_storage = SledStorageEngine::with_homedir_folder(dir.path().join("client").to_str().context("non-UTF-8 temp dir")?)?;
+    let node = Node::new(Arc::new(client_storage), agent.clone());
+    let _client = WebsocketClient::new(node.clone(), &format!("ws://127.0.0.1:{}/ws?node={}", port, node.id.to_base64())).await?;
+    node.system.wait_system_ready().await;
+    let (context, user_id) = sign_in(&node, &agent).await?;
+
+    let committed = Arc::new(Mutex::new(Vec::new()));
+    let writer = tokio::spawn(write_until_refused(context, user_id, committed.clone()));
+
+    // Let the writer get going, then terminate the server while it is still sending
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while committed.lock().unwrap().len() < MESSAGES_BEFORE_SIGTERM {
+        anyhow::ensure!(tokio::time::Instant::now() < deadline, "writer made no progress");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    let status = Command::new("kill").args(["-TERM", &server.id().to_string()]).status()?;
+    anyhow::ensure!(status.success(), "failed to send SIGTERM");
+
+    let exit = wait_for_exit(server).await?;
+    assert!(exit.success(), "server exited with {}", exit);
+    // Commits in flight when the connection closed may never resolve; the ones recorded so far are what counts
+    writer.abort();
+    let committed = committed.lock().unwrap().clone();
+    assert!(committed.len() >= MESSAGES_BEFORE_SIGTERM);
+
+    // Reopen the server's store and look for every acknowledged message
+    let storage = SledStorageEngine::with_homedir_folder(server_storage.to_str().context("non-UTF-8 temp dir")?)?;
+    let node = Node::new_durable(Arc::new(storage), ChatAgent::new());
+    node.system.wait_loaded().await;
+    let stored: HashSet<EntityId> =
+        node.context_async(SYSTEM_CONTEXT).await.fetch::<MessageView>("true").await?.iter().map(|message| message.id()).collect();
+    let lost = committed.iter().filter(|id| !stored.contains(id)).count();
+    assert_eq!(lost, 0, "{} of {} committed messages were lost", lost, committed.len());
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+//! Starts a server with `--metrics-bind` and checks that `/healthz` and `/metrics` are answered only on
+//! that address, while the public address keeps serving everything else.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    process::{Child, Command},
+    time::Duration,
+};
+
+use anyhow::{Context as _, Result};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn free_port() -> Result<u16> { Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port()) }
+
+/// Kills the server when the test ends, however it ends.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_server(home: &Path, port: u16, metrics_port: u16) -> Result<Server> {
+    let child = Command::new(env!("CARGO_BIN_EXE_ankurah-template-server"))
+        .env("HOME", home)
+        .env_remove("ANKURAH_CONFIG")
+        .args(["--bind", &format!("127.0.0.1:{}", port)])
+        .args(["--metrics-bind", &format!("127.0.0.1:{}", metrics_port)])
+        .args(["--storage-folder", home.join("server").to_str().context("non-UTF-8 temp dir")?])
+        .args(["--log-level", "warn"])
+        .spawn()
+        .context("failed to start the server")?;
+    Ok(Server(child))
+}
+
+/// The status line of the response to `GET path`, or `None` if nothing answered.
+fn status(port: u16, path: &str) -> Option<String> {
+    let mut stream = TcpStream::connect(("127.0.0.1", port)).ok()?;
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).ok()?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).ok()?;
+    response.lines().next().map(str::to_string)
+}
+
+async fn wait_until_healthy(port: u16) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while status(port, "/healthz").as_deref() != Some("HTTP/1.1 200 OK") {
+        anyhow::ensure!(tokio::time::Instant::now() < deadline, "server did not become healthy");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn monitoring_is_served_only_on_the_metrics_address() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let (port, metrics_port) = (free_port()?, free_port()?);
+    let _server = spawn_server(dir.path(), port, metrics_port)?;
+    wait_until_healthy(metrics_port).await?;
+
+    assert_eq!(status(metrics_port, "/metrics").as_deref(), Some("HTTP/1.1 200 OK"));
+    assert_eq!(status(metrics_port, "/index.html").as_deref(), Some("HTTP/1.1 404 Not Found"));
+    assert_eq!(status(port, "/healthz").as_deref(), Some("HTTP/1.1 404 Not Found"));
+    assert_eq!(status(port, "/metrics").as_deref(), Some("HTTP/1.1 404 Not Found"));
+    Ok(())
+}
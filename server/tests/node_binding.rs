@@ -0,0 +1,100 @@
+//! Connects a client that names one node in its URL and then speaks as another, and checks that the
+//! listener disconnects it while a client speaking as the node it named stays connected.
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::Path,
+    process::{Child, Command},
+    time::Duration,
+};
+
+use ankurah::{proto, EntityId};
+use anyhow::{Context as _, Result};
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{tungstenite::Message as WsMessage, MaybeTlsStream, WebSocketStream};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a client that broke the rules is given to be disconnected.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+fn free_port() -> Result<u16> { Ok(std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port()) }
+
+/// Kills the server when the test ends, however it ends.
+struct Server(Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn spawn_server(home: &Path, port: u16) -> Result<Server> {
+    let child = Command::new(env!("CARGO_BIN_EXE_ankurah-template-server"))
+        .env("HOME", home)
+        .env_remove("ANKURAH_CONFIG")
+        .args(["--bind", &format!("127.0.0.1:{}", port)])
+        .args(["--storage-folder", home.join("server").to_str().context("non-UTF-8 temp dir")?])
+        .args(["--log-level", "warn"])
+        .spawn()
+        .context("failed to start the server")?;
+    Ok(Server(child))
+}
+
+fn is_healthy(port: u16) -> bool {
+    let Ok(mut stream) = TcpStream::connect(("127.0.0.1", port)) else { return false };
+    let mut response = String::new();
+    stream.write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").is_ok()
+        && stream.read_to_string(&mut response).is_ok()
+        && response.starts_with("HTTP/1.1 200")
+}
+
+async fn wait_until_healthy(port: u16) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + STARTUP_TIMEOUT;
+    while !is_healthy(port) {
+        anyhow::ensure!(tokio::time::Instant::now() < deadline, "server did not become healthy");
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Open a WebSocket that names `claimed` in its URL and announce it as `sender`.
+async fn connect_as(port: u16, claimed: EntityId, sender: EntityId) -> Result<Socket> {
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://127.0.0.1:{}/ws?node={}", port, claimed.to_base64())).await?;
+    let presence = proto::Message::Presence(proto::Presence { node_id: sender, durable: false, system_root: None });
+    socket.send(WsMessage::Binary(bincode::serialize(&presence)?.into())).await?;
+    Ok(socket)
+}
+
+/// Whether the socket is still open after [`CLOSE_TIMEOUT`], skipping whatever the server sends meanwhile.
+async fn stays_open(socket: &mut Socket) -> bool {
+    let closed = async {
+        while let Some(Ok(message)) = socket.next().await {
+            if let WsMessage::Close(_) = message {
+                break;
+            }
+        }
+    };
+    tokio::time::timeout(CLOSE_TIMEOUT, closed).await.is_err()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn clients_speaking_as_another_node_are_disconnected() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let port = free_port()?;
+    let _server = spawn_server(dir.path(), port)?;
+    wait_until_healthy(port).await?;
+
+    let node = EntityId::new();
+    let mut honest = connect_as(port, node, node).await?;
+    assert!(stays_open(&mut honest).await, "a client speaking as the node it named was disconnected");
+
+    // Naming an unused node in the URL and then speaking as another, already connected one
+    let mut impostor = connect_as(port, EntityId::new(), node).await?;
+    assert!(!stays_open(&mut impostor).await, "a client speaking as another node was kept");
+
+    Ok(())
+}
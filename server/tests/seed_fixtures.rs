@@ -0,0 +1,70 @@
+//! Applies fixtures to a store repeatedly, rewording a welcome message in between, and checks that each
+//! welcome message is posted exactly once.
+
+use std::{path::Path, process::Command};
+
+use anyhow::{Context as _, Result};
+use serde_json::Value;
+
+const FIXTURES: &str = r#"
+[[users]]
+id = "greeter"
+display_name = "Greeter"
+
+[[rooms]]
+name = "General"
+
+[[rooms.messages]]
+id = "hello"
+from = "greeter"
+text = "Hello!"
+
+[[rooms]]
+name = "Quiet"
+"#;
+
+fn run(home: &Path, fixtures: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new(env!("CARGO_BIN_EXE_ankurah-template-server"))
+        .env("HOME", home)
+        .env_remove("ANKURAH_CONFIG")
+        .args(["--storage-folder", home.join("store").to_str().context("non-UTF-8 temp dir")?])
+        .args(["--fixtures", fixtures.to_str().context("non-UTF-8 temp dir")?])
+        .args(["--log-level", "warn"])
+        .args(args)
+        .status()
+        .context("failed to run the server")?;
+    anyhow::ensure!(status.success(), "{:?} exited with {}", args, status);
+    Ok(())
+}
+
+/// The `server_id` and text of every message in the store.
+fn messages(home: &Path, fixtures: &Path) -> Result<Vec<(String, String)>> {
+    let archive = home.join("archive.jsonl");
+    run(home, fixtures, &["export", archive.to_str().context("non-UTF-8 temp dir")?])?;
+    let mut messages = Vec::new();
+    for line in std::fs::read_to_string(&archive)?.lines().filter(|line| !line.trim().is_empty()) {
+        let record: Value = serde_json::from_str(line)?;
+        if record["fields"].get("text").is_some() {
+            let field = |name: &str| record["fields"][name].as_str().unwrap_or_default().to_string();
+            messages.push((field("server_id"), field("text")));
+        }
+    }
+    Ok(messages)
+}
+
+#[test]
+fn welcome_messages_are_posted_once_by_id() -> Result<()> {
+    let dir = tempfile::tempdir()?;
+    let fixtures = dir.path().join("fixtures.toml");
+    std::fs::write(&fixtures, FIXTURES)?;
+
+    run(dir.path(), &fixtures, &["seed"])?;
+    run(dir.path(), &fixtures, &["seed"])?;
+    assert_eq!(messages(dir.path(), &fixtures)?, [("fixture:hello".to_string(), "Hello!".to_string())]);
+
+    // Rewording a welcome message in the fixtures doesn't post it again
+    std::fs::write(&fixtures, FIXTURES.replace("Hello!", "Hello, everyone!"))?;
+    run(dir.path(), &fixtures, &["seed"])?;
+    assert_eq!(messages(dir.path(), &fixtures)?, [("fixture:hello".to_string(), "Hello!".to_string())]);
+    Ok(())
+}